@@ -0,0 +1,62 @@
+//! measure the time spent computing the internal nodes of the largest merkle trees
+//!
+//! run with `cargo bench`, adding `--features parallel` to compare with several threads
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use merkle_tree::{Hash, HashFunction, MerkleTree};
+
+const HEIGHT: usize = 10;
+const ROUNDS: u32 = 100;
+
+fn bench_hash(input: &[u8]) -> Hash {
+    // a cheap hash keeps the measure focused on the traversal of the nodes
+    input.len().to_le_bytes().to_vec()
+}
+
+fn full_tree() -> MerkleTree {
+    let mut mt = MerkleTree::from_height(bench_hash as HashFunction, HEIGHT).unwrap();
+
+    for i in 0..1 << HEIGHT {
+        mt.insert(i.to_string()).unwrap();
+    }
+
+    mt
+}
+
+fn measure(name: &str, run: impl Fn(&mut MerkleTree)) {
+    let mut total = Duration::ZERO;
+
+    for _ in 0..ROUNDS {
+        let mut mt = full_tree();
+
+        let start = Instant::now();
+        run(&mut mt);
+        total += start.elapsed();
+
+        black_box(mt.get_root().unwrap());
+    }
+
+    println!(
+        "{:<40} {:>10.2?} per round ({} rounds, height {})",
+        name,
+        total / ROUNDS,
+        ROUNDS,
+        HEIGHT
+    );
+}
+
+fn main() {
+    measure("update_internal_nodes", |mt| {
+        mt.update_internal_nodes().unwrap()
+    });
+    measure("rebuild", |mt| mt.rebuild().unwrap());
+    #[cfg(feature = "parallel")]
+    {
+        measure("update_internal_nodes_parallel", |mt| {
+            mt.update_internal_nodes_parallel().unwrap()
+        });
+        measure("rebuild_parallel", |mt| mt.rebuild_parallel().unwrap());
+    }
+}
@@ -0,0 +1,56 @@
+/*
+ * the C API of the merkle_tree crate, built with its `ffi` feature, e.g.
+ * `cargo rustc --lib --release --features ffi --crate-type staticlib`
+ *
+ * the merkle trees hash their values and nodes with SHA-256, so all the digests have 32 bytes,
+ * and the functions returning an `int` return 0 on success and -1 on failure
+ */
+
+#ifndef MERKLE_TREE_H
+#define MERKLE_TREE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* a merkle tree of SHA-256 digests, only handled through pointers */
+typedef struct merkle_tree merkle_tree;
+
+/* create a merkle tree of height `height`, or return NULL if the height is invalid */
+merkle_tree *merkle_tree_new(size_t height);
+
+/* free a merkle tree created by `merkle_tree_new`, doing nothing if `tree` is NULL */
+void merkle_tree_free(merkle_tree *tree);
+
+/* insert the `len` bytes at `value`, failing if the merkle tree is full */
+int merkle_tree_insert(merkle_tree *tree, const uint8_t *value, size_t len);
+
+/* write the root of the merkle tree into the 32 bytes at `root` */
+int merkle_tree_root(merkle_tree *tree, uint8_t *root);
+
+/*
+ * write the siblings of the proof of the `value_index`'th value, from the leaf up to
+ * the children of the root, into the `capacity` 32-byte slots at `siblings`,
+ * and return their number, which is the height of the merkle tree, or -1 on failure,
+ * e.g. if no value was inserted at `value_index`
+ */
+ptrdiff_t merkle_tree_prove(merkle_tree *tree, size_t value_index, uint8_t *siblings,
+                            size_t capacity);
+
+/*
+ * check that the `len` bytes at `value` are the `value_index`'th value of the merkle tree
+ * with the 32-byte root at `root`, given the `sibling_count` siblings written by
+ * `merkle_tree_prove`
+ */
+bool merkle_proof_verify(const uint8_t *root, const uint8_t *value, size_t len,
+                         size_t value_index, const uint8_t *siblings, size_t sibling_count);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MERKLE_TREE_H */
@@ -0,0 +1,159 @@
+//! anchoring of merkle roots into external systems
+//!
+//! an `Anchor` publishes roots to a backend (a blockchain, a timestamping service, ...)
+//! and later tells whether a publication was confirmed by that backend
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use crate::NodeHash;
+
+/// identify a root published through an anchor
+#[derive(Clone, PartialEq, Debug)]
+pub struct Receipt {
+    pub root: NodeHash,
+    /// the backend-specific identifier of the publication
+    pub id: String,
+}
+
+/// the state of a publication on the backend
+#[derive(Clone, PartialEq, Debug)]
+pub enum Confirmation {
+    /// the backend did not confirm the publication yet
+    Pending,
+    /// the backend recorded the published root
+    Confirmed,
+    /// the backend recorded another root, or nothing, for this publication
+    Rejected,
+}
+
+/// a pluggable backend to which merkle roots are anchored
+pub trait Anchor {
+    /// publish `root` to the backend
+    fn publish(&mut self, root: &NodeHash) -> io::Result<Receipt>;
+
+    /// fetch the state of the publication identified by `receipt`
+    fn fetch_confirmation(&self, receipt: &Receipt) -> io::Result<Confirmation>;
+}
+
+/// an anchor appending every published root as a line of a local file
+///
+/// the id of a publication is its line number, starting from 0
+pub struct FileAnchor {
+    path: PathBuf,
+}
+
+impl FileAnchor {
+    /// anchor roots into the file at `path`, which is created on the first publication
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileAnchor { path: path.into() }
+    }
+
+    fn published_roots(&self) -> io::Result<Vec<String>> {
+        match File::open(&self.path) {
+            Ok(file) => BufReader::new(file).lines().collect(),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl Anchor for FileAnchor {
+    fn publish(&mut self, root: &NodeHash) -> io::Result<Receipt> {
+        let id = self.published_roots()?.len();
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", root)?;
+
+        Ok(Receipt {
+            root: root.clone(),
+            id: id.to_string(),
+        })
+    }
+
+    fn fetch_confirmation(&self, receipt: &Receipt) -> io::Result<Confirmation> {
+        let line = match receipt.id.parse::<usize>() {
+            Ok(line) => line,
+            Err(_) => return Ok(Confirmation::Rejected),
+        };
+
+        // a local file is written synchronously, so a publication is never pending
+        match self.published_roots()?.get(line) {
+            Some(root) if *root == receipt.root.to_string() => Ok(Confirmation::Confirmed),
+            _ => Ok(Confirmation::Rejected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn anchor_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "merkle_tree_anchor_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+
+        path
+    }
+
+    #[test]
+    fn file_anchor_publish_and_confirm() {
+        let path = anchor_path("publish_and_confirm");
+        let mut anchor = FileAnchor::new(&path);
+
+        let receipt_one = anchor.publish(&NodeHash(b"H(1)".to_vec())).unwrap();
+        let receipt_two = anchor.publish(&NodeHash(b"H(2)".to_vec())).unwrap();
+
+        assert_eq!("0", receipt_one.id);
+        assert_eq!("1", receipt_two.id);
+        assert_eq!(
+            Confirmation::Confirmed,
+            anchor.fetch_confirmation(&receipt_one).unwrap()
+        );
+        assert_eq!(
+            Confirmation::Confirmed,
+            anchor.fetch_confirmation(&receipt_two).unwrap()
+        );
+        // the roots are written in hexadecimal
+        assert_eq!("48283129\n48283229\n", fs::read_to_string(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_anchor_rejects_unknown_receipts() {
+        let path = anchor_path("rejects_unknown_receipts");
+        let mut anchor = FileAnchor::new(&path);
+
+        anchor.publish(&NodeHash(b"H(1)".to_vec())).unwrap();
+
+        let forged = Receipt {
+            root: NodeHash(b"H(2)".to_vec()),
+            id: "0".to_string(),
+        };
+        let missing = Receipt {
+            root: NodeHash(b"H(1)".to_vec()),
+            id: "1".to_string(),
+        };
+
+        assert_eq!(
+            Confirmation::Rejected,
+            anchor.fetch_confirmation(&forged).unwrap()
+        );
+        assert_eq!(
+            Confirmation::Rejected,
+            anchor.fetch_confirmation(&missing).unwrap()
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! batch updates of the values already inserted in merkle trees
+//!
+//! the ancestors shared by several updated leaves are recomputed only once,
+//! instead of once per updated leaf
+
+use crate::store::NodeStore;
+use crate::{Hash, Hasher, MerkleTree, MerkleTreeError};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// replace the values at the given indices, then recompute each affected internal node
+    /// exactly once, level by level from the lowest one up to the root
+    ///
+    /// if an index appears several times, its last value is kept;
+    /// the values are all checked before any of them is updated,
+    /// so a rejected update leaves the merkle tree untouched
+    ///
+    /// fail if an index is out of bounds or was never inserted,
+    /// if a value is rejected by the validation function,
+    /// if the new hashes would exceed the memory budget or,
+    /// once the values are updated, if the internal nodes cannot be updated
+    pub fn update_all(
+        &mut self,
+        updates: &[(usize, impl AsRef<[u8]>)],
+    ) -> Result<(), MerkleTreeError> {
+        let capacity = self.nodes.node_count() - self.first_leaf_node_index;
+        let mut hashes = Vec::with_capacity(updates.len());
+
+        for (value_index, value) in updates {
+            if *value_index >= capacity {
+                return Err(MerkleTreeError::ValueOutOfBounds(*value_index));
+            }
+
+            if *value_index >= self.length {
+                return Err(MerkleTreeError::NeverInserted(*value_index));
+            }
+
+            if let Some(validation_function) = self.validation_function {
+                validation_function(value.as_ref()).map_err(MerkleTreeError::Rejected)?;
+            }
+
+            let hash = self.hash_value(*value_index, value).0;
+            hashes.push((self.first_leaf_node_index + value_index, hash));
+        }
+
+        // the last update of a leaf wins, and its hash takes the place of the hash it replaces
+        let hashes: BTreeMap<usize, Hash> = hashes.into_iter().collect();
+        let additional_memory = hashes
+            .iter()
+            .map(|(&index, hash)| {
+                let replaced = self.nodes.get_hash(index).map_or(0, |hash| hash.capacity());
+                hash.capacity().saturating_sub(replaced)
+            })
+            .sum();
+        self.reserve_memory(additional_memory)?;
+
+        let mut leaves = Vec::with_capacity(hashes.len());
+        for (index, hash) in hashes {
+            self.nodes.set_hash(index, Some(hash));
+            self.send_update_event(index - self.first_leaf_node_index);
+            leaves.push(index);
+        }
+
+        // mark the ancestors stale first, so that a failure cannot leave an outdated node behind
+        for index in Self::ancestor_indices(self.height, &leaves) {
+            self.nodes.set_hash(index, None);
+        }
+        self.dirty_leaves.extend(leaves);
+
+        // the ancestors shared by several updated leaves are only visited once
+        self.update_internal_nodes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::{HashFunction, MerkleTreeBuilder};
+
+    fn tree_of(values: &[&str]) -> MerkleTree {
+        let builder = MerkleTreeBuilder::new(basic_hash as HashFunction, 3);
+        #[cfg(feature = "std")]
+        let builder = builder.collect_timings(true);
+        let mut mt = builder.build().unwrap();
+
+        for value in values {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+        #[cfg(feature = "std")]
+        mt.reset_timings();
+
+        mt
+    }
+
+    #[test]
+    fn update_all_matches_fresh_tree() {
+        let mut mt = tree_of(&["a", "b", "c", "d", "e"]);
+
+        mt.update_all(&[(1, "x"), (4, "y"), (1, "z")]).unwrap();
+
+        assert_eq!(tree_of(&["a", "z", "c", "d", "y"]).nodes, mt.nodes);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn update_all_recomputes_shared_ancestors_once() {
+        let mut mt = tree_of(&["a", "b", "c", "d"]);
+
+        mt.update_all(&[(0, "w"), (1, "x"), (2, "y"), (3, "z")])
+            .unwrap();
+
+        // the 2 parents of the updated leaves, their common parent and the root,
+        // whose empty right subtree is precomputed
+        assert_eq!(4, mt.timings().unwrap().node_hashing.count);
+    }
+
+    #[test]
+    fn update_all_reserves_the_net_memory() {
+        let values = ["a", "b", "c", "d"];
+        // room for a single extra hash of the basic hash function
+        let memory_budget = tree_of(&values).memory_usage() + 8;
+
+        let mut mt = MerkleTreeBuilder::new(basic_hash as HashFunction, 3)
+            .memory_budget(memory_budget)
+            .build()
+            .unwrap();
+        for value in values {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+
+        // the new hashes replace hashes of the same size, however often a leaf is updated
+        let updates: Vec<_> = (0..8).map(|i| (i % 2, "x")).collect();
+        mt.update_all(&updates).unwrap();
+        assert_eq!(
+            Err(MerkleTreeError::MemoryBudgetExceeded(memory_budget)),
+            mt.update_all(&[(2, "a value much longer than the others")])
+        );
+    }
+
+    #[test]
+    fn update_all_never_inserted_value() {
+        let mut mt = tree_of(&["a", "b"]);
+        let nodes = mt.nodes.clone();
+
+        assert_eq!(
+            Err(MerkleTreeError::NeverInserted(2)),
+            mt.update_all(&[(0, "x"), (2, "y")])
+        );
+        assert_eq!(
+            Err(MerkleTreeError::ValueOutOfBounds(8)),
+            mt.update_all(&[(8, "y")])
+        );
+        assert_eq!(nodes, mt.nodes);
+    }
+}
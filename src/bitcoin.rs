@@ -0,0 +1,134 @@
+//! the merkle roots of Bitcoin blocks, computed from the ids of their transactions
+//!
+//! unlike the merkle trees of this crate, whose missing leaves are empty,
+//! Bitcoin pairs the last node of each level with itself when the level has an odd number
+//! of nodes, and stops as soon as a level has a single node, with double SHA-256 as hasher
+//!
+//! only available with the `sha2` feature
+
+use crate::sha256::sha256;
+use crate::{hex, Hash, Hasher};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// a hasher computing the SHA-256 digest of the SHA-256 digest of the values and
+/// of the concatenation of the children of each node, as Bitcoin does
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct DoubleSha256Hasher;
+
+impl Hasher for DoubleSha256Hasher {
+    fn hash_leaf(&self, value: &[u8]) -> Hash {
+        sha256(&sha256(value)).to_vec()
+    }
+
+    fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash {
+        sha256(&sha256(&[left, right].concat())).to_vec()
+    }
+}
+
+/// compute the root of the `leaf_hashes` by pairing the last node of each level with itself
+/// when the level has an odd number of nodes, or return `None` if there are no leaf hashes
+pub fn duplicate_last_root(hasher: &impl Hasher, leaf_hashes: Vec<Hash>) -> Option<Hash> {
+    let mut level = leaf_hashes;
+
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1].clone());
+        }
+
+        level = level
+            .chunks_exact(2)
+            .map(|pair| hasher.hash_nodes(&pair[0], &pair[1]))
+            .collect();
+    }
+
+    level.pop()
+}
+
+/// compute the merkle root of a Bitcoin block from the ids of its transactions, in block order,
+/// or return `None` if there are no transactions
+///
+/// the ids and the root are in the internal byte order of Bitcoin,
+/// which is the reverse of the order they are displayed in, see `from_display_hex`
+pub fn bitcoin_merkle_root(txids: impl IntoIterator<Item = [u8; 32]>) -> Option<[u8; 32]> {
+    let leaf_hashes = txids.into_iter().map(|txid| txid.to_vec()).collect();
+
+    duplicate_last_root(&DoubleSha256Hasher, leaf_hashes)
+        .map(|root| root.try_into().expect("SHA-256 digests have 32 bytes"))
+}
+
+/// decode a transaction id or a merkle root as displayed by Bitcoin software and block explorers,
+/// i.e. in reverse byte order, or return `None` if it is not 32 bytes of hexadecimal digits
+pub fn from_display_hex(hex: &str) -> Option<[u8; 32]> {
+    let mut bytes: [u8; 32] = hex::decode(hex)?.try_into().ok()?;
+    bytes.reverse();
+
+    Some(bytes)
+}
+
+/// encode a transaction id or a merkle root as displayed by Bitcoin software and block explorers
+pub fn to_display_hex(bytes: &[u8; 32]) -> String {
+    let mut bytes = *bytes;
+    bytes.reverse();
+
+    hex::encode(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root_of(txids: &[&str]) -> String {
+        let txids = txids.iter().map(|txid| from_display_hex(txid).unwrap());
+
+        to_display_hex(&bitcoin_merkle_root(txids).unwrap())
+    }
+
+    #[test]
+    fn genesis_block() {
+        // the root of a single transaction is its id
+        let coinbase = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+        assert_eq!(coinbase, root_of(&[coinbase]));
+        assert_eq!(None, bitcoin_merkle_root([]));
+    }
+
+    #[test]
+    fn block_170() {
+        assert_eq!(
+            "7dac2c5666815c17a3b36427de37bb9d2e2c5ccec3f8633eb91a4205cb4c10ff",
+            root_of(&[
+                "b1fea52486ce0c62bb442b530a3f0132b826c74e473d1f2c220bfa78111c5082",
+                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+            ])
+        );
+    }
+
+    #[test]
+    fn block_100000() {
+        assert_eq!(
+            "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766",
+            root_of(&[
+                "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+                "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+                "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+                "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+            ])
+        );
+    }
+
+    #[test]
+    fn odd_levels_duplicate_their_last_node() {
+        let txids: Vec<[u8; 32]> = (0..5u8).map(|i| [i; 32]).collect();
+        let padded = [&txids[..], &[txids[4]; 3]].concat();
+
+        assert_eq!(
+            bitcoin_merkle_root(padded),
+            bitcoin_merkle_root(txids.clone())
+        );
+        assert_ne!(
+            bitcoin_merkle_root(txids[..4].to_vec()),
+            bitcoin_merkle_root(txids)
+        );
+    }
+}
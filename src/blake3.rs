@@ -0,0 +1,353 @@
+//! a ready-to-use BLAKE3 hasher, in its default and keyed modes, as specified by its authors
+//!
+//! only available with the `blake3` feature
+
+use crate::{Hash, Hasher, MerkleTree};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+const BLOCK_LEN: usize = 64;
+const CHUNK_LEN: usize = 1024;
+
+const CHUNK_START: u32 = 1 << 0;
+const CHUNK_END: u32 = 1 << 1;
+const PARENT: u32 = 1 << 2;
+const ROOT: u32 = 1 << 3;
+const KEYED_HASH: u32 = 1 << 4;
+
+/// a hasher computing the BLAKE3 digest of the values and
+/// of the concatenation of the children of each node,
+/// keyed with a secret key if created with `Blake3Hasher::keyed`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Blake3Hasher {
+    key: Option<[u8; 32]>,
+}
+
+impl Blake3Hasher {
+    /// create a hasher computing keyed BLAKE3 digests, which act as MACs under `key`
+    pub fn keyed(key: [u8; 32]) -> Self {
+        Blake3Hasher { key: Some(key) }
+    }
+
+    fn hash(&self, data: &[u8]) -> [u8; 32] {
+        match &self.key {
+            Some(key) => blake3_keyed(key, data),
+            None => blake3(data),
+        }
+    }
+}
+
+impl Hasher for Blake3Hasher {
+    fn hash_leaf(&self, value: &[u8]) -> Hash {
+        self.hash(value).to_vec()
+    }
+
+    fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash {
+        self.hash(&[left, right].concat()).to_vec()
+    }
+}
+
+impl FromIterator<String> for MerkleTree<Blake3Hasher> {
+    /// build a merkle tree of BLAKE3 digests with `MerkleTree::from_leaves`
+    fn from_iter<I: IntoIterator<Item = String>>(values: I) -> Self {
+        MerkleTree::from_leaves(Blake3Hasher::default(), values)
+            .expect("a merkle tree sized for its values can hold them")
+    }
+}
+
+/// compute the BLAKE3 digest of `data`
+pub fn blake3(data: &[u8]) -> [u8; 32] {
+    hash_with(IV, 0, data)
+}
+
+/// compute the keyed BLAKE3 digest of `data` under `key`
+pub fn blake3_keyed(key: &[u8; 32], data: &[u8]) -> [u8; 32] {
+    hash_with(words(key), KEYED_HASH, data)
+}
+
+/// the state before the last compression of a chunk or of a parent node, which is either
+/// compressed into a chaining value or, for the root, into the digest
+struct Output {
+    chaining_value: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        first_words(compress(
+            &self.chaining_value,
+            &self.block,
+            self.counter,
+            self.block_len,
+            self.flags,
+        ))
+    }
+
+    fn root_hash(&self) -> [u8; 32] {
+        let state = first_words(compress(
+            &self.chaining_value,
+            &self.block,
+            0,
+            self.block_len,
+            self.flags | ROOT,
+        ));
+
+        let mut digest = [0; 32];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+
+        digest
+    }
+}
+
+/// hash `data` split into chunks of 1 KiB, which are the leaves of a binary tree
+/// whose left subtrees are always complete
+fn hash_with(key: [u32; 8], flags: u32, data: &[u8]) -> [u8; 32] {
+    // the empty input is a single empty chunk
+    let chunks: Vec<&[u8]> = if data.is_empty() {
+        vec![data]
+    } else {
+        data.chunks(CHUNK_LEN).collect()
+    };
+    let (last, chunks) = chunks.split_last().expect("there is at least one chunk");
+
+    // the chaining values of the complete subtrees, whose sizes are the bits of the chunk count
+    let mut stack: Vec<[u32; 8]> = Vec::new();
+    for (counter, chunk) in chunks.iter().enumerate() {
+        let mut chaining_value = chunk_output(&key, flags, chunk, counter as u64).chaining_value();
+
+        // merge the subtrees completed by this chunk
+        let mut total_chunks = counter + 1;
+        while total_chunks % 2 == 0 {
+            let left = stack.pop().expect("a complete subtree has a left sibling");
+            chaining_value = parent_output(&key, flags, &left, &chaining_value).chaining_value();
+            total_chunks /= 2;
+        }
+        stack.push(chaining_value);
+    }
+
+    let mut output = chunk_output(&key, flags, last, chunks.len() as u64);
+    while let Some(left) = stack.pop() {
+        output = parent_output(&key, flags, &left, &output.chaining_value());
+    }
+
+    output.root_hash()
+}
+
+/// compress all the blocks of a `chunk` but the last one
+fn chunk_output(key: &[u32; 8], flags: u32, chunk: &[u8], counter: u64) -> Output {
+    let mut chaining_value = *key;
+    let mut blocks = chunk.chunks(BLOCK_LEN).peekable();
+    let mut start = CHUNK_START;
+
+    loop {
+        let block = blocks.next().unwrap_or_default();
+        let mut padded = [0; BLOCK_LEN];
+        padded[..block.len()].copy_from_slice(block);
+
+        if blocks.peek().is_none() {
+            return Output {
+                chaining_value,
+                block: words(&padded),
+                counter,
+                block_len: block.len() as u32,
+                flags: flags | start | CHUNK_END,
+            };
+        }
+
+        chaining_value = first_words(compress(
+            &chaining_value,
+            &words(&padded),
+            counter,
+            BLOCK_LEN as u32,
+            flags | start,
+        ));
+        start = 0;
+    }
+}
+
+fn parent_output(key: &[u32; 8], flags: u32, left: &[u32; 8], right: &[u32; 8]) -> Output {
+    let mut block = [0; 16];
+    block[..8].copy_from_slice(left);
+    block[8..].copy_from_slice(right);
+
+    Output {
+        chaining_value: *key,
+        block,
+        counter: 0,
+        block_len: BLOCK_LEN as u32,
+        flags: flags | PARENT,
+    }
+}
+
+/// mix a `block` into the `chaining_value`, returning the whole state
+fn compress(
+    chaining_value: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+) -> [u32; 16] {
+    let mut state = [
+        chaining_value[0],
+        chaining_value[1],
+        chaining_value[2],
+        chaining_value[3],
+        chaining_value[4],
+        chaining_value[5],
+        chaining_value[6],
+        chaining_value[7],
+        IV[0],
+        IV[1],
+        IV[2],
+        IV[3],
+        counter as u32,
+        (counter >> 32) as u32,
+        block_len,
+        flags,
+    ];
+    let mut block = *block;
+
+    for round in 0..7 {
+        // mix the columns, then the diagonals
+        g(&mut state, 0, 4, 8, 12, block[0], block[1]);
+        g(&mut state, 1, 5, 9, 13, block[2], block[3]);
+        g(&mut state, 2, 6, 10, 14, block[4], block[5]);
+        g(&mut state, 3, 7, 11, 15, block[6], block[7]);
+        g(&mut state, 0, 5, 10, 15, block[8], block[9]);
+        g(&mut state, 1, 6, 11, 12, block[10], block[11]);
+        g(&mut state, 2, 7, 8, 13, block[12], block[13]);
+        g(&mut state, 3, 4, 9, 14, block[14], block[15]);
+
+        if round < 6 {
+            block = MSG_PERMUTATION.map(|i| block[i]);
+        }
+    }
+
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= chaining_value[i];
+    }
+
+    state
+}
+
+/// the quarter-round function, mixing two words of the block into four words of the state
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(x);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(y);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+/// read little-endian words from `bytes`, whose length is a multiple of 4
+fn words<const N: usize>(bytes: &[u8]) -> [u32; N] {
+    let mut words = [0; N];
+    for (word, bytes) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    words
+}
+
+fn first_words(state: [u32; 16]) -> [u32; 8] {
+    let mut words = [0; 8];
+    words.copy_from_slice(&state[..8]);
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex;
+
+    /// the inputs of the official test vectors, repeating the bytes from 0 to 250
+    fn input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    const KEY: &[u8; 32] = b"a secret key of exactly 32 bytes";
+
+    #[test]
+    fn blake3_test_vectors() {
+        assert_eq!(
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+            hex::encode(&blake3(b""))
+        );
+        assert_eq!(
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            hex::encode(&blake3(b"abc"))
+        );
+        // a single full chunk, then several chunks merged into parent nodes
+        assert_eq!(
+            "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7",
+            hex::encode(&blake3(&input(1024)))
+        );
+        assert_eq!(
+            "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444",
+            hex::encode(&blake3(&input(1025)))
+        );
+        assert_eq!(
+            "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a",
+            hex::encode(&blake3(&input(2048)))
+        );
+        assert_eq!(
+            "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2",
+            hex::encode(&blake3(&input(3072)))
+        );
+    }
+
+    #[test]
+    fn keyed_blake3() {
+        // the official test vector of the keyed mode, under the key of the official test vectors
+        assert_eq!(
+            "92b2b75604ed3c761f9d6f62392c8a9227ad0ea3f09573e783f1498a4ed60d26",
+            hex::encode(&blake3_keyed(b"whats the Elvish word for friend", b""))
+        );
+
+        // the key is the initial chaining value, so it changes every digest
+        assert_ne!(blake3(b""), blake3_keyed(KEY, b""));
+        assert_ne!(
+            blake3_keyed(&[0; 32], &input(2048)),
+            blake3_keyed(KEY, &input(2048))
+        );
+        assert_eq!(
+            blake3_keyed(KEY, &input(2048)),
+            blake3_keyed(KEY, &input(2048))
+        );
+    }
+
+    #[test]
+    fn blake3_merkle_root() {
+        for hasher in [Blake3Hasher::default(), Blake3Hasher::keyed(*KEY)] {
+            let mut mt = MerkleTree::from_height(hasher, 1).unwrap();
+            mt.insert("1").unwrap();
+            mt.insert("2").unwrap();
+            mt.update_internal_nodes().unwrap();
+
+            let expected = hasher.hash(&[hasher.hash(b"1"), hasher.hash(b"2")].concat());
+
+            assert_eq!(expected.to_vec(), mt.get_root().unwrap().0);
+        }
+
+        // the keyed digests differ from the default ones
+        let mt: MerkleTree<Blake3Hasher> = ["1".to_string()].into_iter().collect();
+        assert_ne!(blake3_keyed(KEY, b"1").to_vec(), mt.get_value(0).unwrap().0);
+    }
+}
@@ -0,0 +1,280 @@
+//! compact ranges, in the spirit of RFC 9162
+//!
+//! a compact range over the values `[begin, end)` only keeps the roots of the
+//! largest perfect subtrees covering that range, so workers can hash disjoint ranges
+//! independently and merge them later into the range of a whole merkle tree
+
+use crate::{Hash, HashFunction, Hasher, MerkleTreeError, NodeHash};
+use alloc::vec::Vec;
+
+/// the root of a perfect subtree, located by its `level` above the leaves and
+/// its `index` among the nodes of that level
+#[derive(Clone, PartialEq, Debug)]
+struct RangeNode {
+    level: usize,
+    index: usize,
+    hash: Hash,
+}
+
+/// the minimal set of subtree roots covering the values `[begin, end)`
+#[derive(Clone)]
+pub struct CompactRange<H = HashFunction> {
+    hasher: H,
+    begin: usize,
+    end: usize,
+    /// the roots of the covering subtrees, from left to right
+    nodes: Vec<RangeNode>,
+}
+
+impl<H: Hasher> CompactRange<H> {
+    /// create an empty range starting at the `begin`'th value
+    pub fn new(hasher: H, begin: usize) -> Self {
+        CompactRange {
+            hasher,
+            begin,
+            end: begin,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// create the range of the `values` starting at the `begin`'th value
+    pub fn from_values(
+        hasher: H,
+        begin: usize,
+        values: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> Self {
+        let mut range = CompactRange::new(hasher, begin);
+
+        for value in values {
+            range.append(value);
+        }
+
+        range
+    }
+
+    pub fn begin(&self) -> usize {
+        self.begin
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// the hashes of the roots of the covering subtrees, from left to right
+    pub fn hashes(&self) -> Vec<Hash> {
+        self.nodes.iter().map(|node| node.hash.clone()).collect()
+    }
+
+    /// extend the range with the next value
+    pub fn append(&mut self, value: impl AsRef<[u8]>) {
+        let node = RangeNode {
+            level: 0,
+            index: self.end,
+            hash: self.hasher.hash_leaf(value.as_ref()),
+        };
+
+        push(&self.hasher, &mut self.nodes, node);
+        self.end += 1;
+    }
+
+    /// extend the range with the `other` range, which must start where this one ends
+    ///
+    /// fail if the ranges are not adjacent
+    pub fn merge(&mut self, other: CompactRange<H>) -> Result<(), MerkleTreeError> {
+        if self.end != other.begin {
+            return Err(MerkleTreeError::NonAdjacentRanges);
+        }
+
+        for node in other.nodes {
+            push(&self.hasher, &mut self.nodes, node);
+        }
+        self.end = other.end;
+
+        Ok(())
+    }
+
+    /// compute the root of the merkle tree of the given `height`
+    /// whose values are exactly those of the range,
+    /// with the default `EmptyLeaf` hash for the remaining leaves
+    ///
+    /// fail if the range does not start at the first value,
+    /// if the number of leaves of a merkle tree of the given `height` does not fit in a `usize` or
+    /// if the range does not fit in a merkle tree of the given `height`
+    pub fn root(&self, height: usize) -> Result<NodeHash, MerkleTreeError> {
+        if self.begin != 0 {
+            return Err(MerkleTreeError::UnalignedRange);
+        }
+
+        let leaf_count = u32::try_from(height)
+            .ok()
+            .and_then(|height| 1usize.checked_shl(height))
+            .ok_or(MerkleTreeError::InvalidHeight(height))?;
+        if self.end > leaf_count {
+            return Err(MerkleTreeError::Full);
+        }
+
+        let mut nodes = self.nodes.clone();
+        let mut empty_hash = self.hasher.hash_leaf(b"empty node");
+
+        // complete the range with empty subtrees up to the root, level by level
+        for level in 0..height {
+            // a rightmost node at this level is always a left child,
+            // since a right child would have been merged with its sibling,
+            // so its sibling only covers values that were not inserted
+            if nodes.last().map(|node| node.level) == Some(level) {
+                let node = nodes.pop().unwrap();
+                let parent = parent(&self.hasher, node, &empty_hash);
+                push(&self.hasher, &mut nodes, parent);
+            }
+
+            empty_hash = self.hasher.hash_nodes(&empty_hash, &empty_hash);
+        }
+
+        match nodes.pop() {
+            Some(root) => Ok(NodeHash(root.hash)),
+            // all the values of the merkle tree are empty
+            None => Ok(NodeHash(empty_hash)),
+        }
+    }
+}
+
+/// push a `node` to the right of `nodes` and
+/// merge it with its left sibling as long as the sibling is the previous node
+fn push(hasher: &impl Hasher, nodes: &mut Vec<RangeNode>, node: RangeNode) {
+    let mut node = node;
+
+    while let Some(previous) = nodes.last() {
+        let is_sibling = previous.level == node.level
+            && previous.index % 2 == 0
+            && previous.index + 1 == node.index;
+
+        if !is_sibling {
+            break;
+        }
+
+        let previous = nodes.pop().unwrap();
+        node = parent(hasher, previous, &node.hash);
+    }
+
+    nodes.push(node);
+}
+
+/// combine a `left` child with the hash of its right sibling
+fn parent(hasher: &impl Hasher, left: RangeNode, right_hash: &Hash) -> RangeNode {
+    RangeNode {
+        level: left.level + 1,
+        index: left.index / 2,
+        hash: hasher.hash_nodes(&left.hash, right_hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::MerkleTree;
+    use alloc::string::String;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn values(range: core::ops::Range<usize>) -> Vec<String> {
+        range.map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn append_keeps_minimal_nodes() {
+        let range = CompactRange::from_values(basic_hash, 0, values(0..7));
+
+        // 7 = 4 + 2 + 1
+        assert_eq!(
+            vec![
+                b"H(H(H(0)H(1))H(H(2)H(3)))".to_vec(),
+                b"H(H(4)H(5))".to_vec(),
+                b"H(6)".to_vec(),
+            ],
+            range.hashes()
+        );
+    }
+
+    #[test]
+    fn unaligned_range_nodes() {
+        let range = CompactRange::from_values(basic_hash, 1, values(1..6));
+
+        // [1, 6) = [1, 2) + [2, 4) + [4, 6)
+        assert_eq!(
+            vec![
+                b"H(1)".to_vec(),
+                b"H(H(2)H(3))".to_vec(),
+                b"H(H(4)H(5))".to_vec(),
+            ],
+            range.hashes()
+        );
+    }
+
+    #[test]
+    fn root_matches_merkle_tree() {
+        for length in 0..=8 {
+            let mut mt = MerkleTree::from_height(basic_hash, 3).unwrap();
+            for value in values(0..length) {
+                mt.insert(value).unwrap();
+            }
+            mt.update_internal_nodes().unwrap();
+
+            let range = CompactRange::from_values(basic_hash, 0, values(0..length));
+
+            assert_eq!(mt.get_root(), range.root(3));
+        }
+    }
+
+    #[test]
+    fn merge_disjoint_ranges() {
+        let whole = CompactRange::from_values(basic_hash, 0, values(0..13));
+
+        for split in 0..=13 {
+            let mut left = CompactRange::from_values(basic_hash, 0, values(0..split));
+            let right = CompactRange::from_values(basic_hash, split, values(split..13));
+
+            left.merge(right).unwrap();
+
+            assert_eq!(0, left.begin());
+            assert_eq!(13, left.end());
+            assert_eq!(whole.hashes(), left.hashes());
+            assert_eq!(whole.root(4), left.root(4));
+        }
+    }
+
+    #[test]
+    fn merge_non_adjacent_ranges() {
+        let mut left = CompactRange::from_values(basic_hash, 0, values(0..2));
+        let right = CompactRange::from_values(basic_hash, 3, values(3..4));
+
+        assert_eq!(Err(MerkleTreeError::NonAdjacentRanges), left.merge(right));
+        assert_eq!(2, left.end());
+    }
+
+    #[test]
+    fn root_of_unaligned_range() {
+        let range = CompactRange::from_values(basic_hash, 1, values(1..2));
+
+        assert_eq!(Err(MerkleTreeError::UnalignedRange), range.root(1));
+    }
+
+    #[test]
+    fn root_of_range_too_long() {
+        let range = CompactRange::from_values(basic_hash, 0, values(0..3));
+
+        assert_eq!(Err(MerkleTreeError::Full), range.root(1));
+    }
+
+    #[test]
+    fn root_of_too_high_merkle_tree() {
+        let range = CompactRange::from_values(basic_hash, 0, values(0..3));
+
+        for height in [usize::BITS as usize, usize::MAX] {
+            assert_eq!(
+                Err(MerkleTreeError::InvalidHeight(height)),
+                range.root(height)
+            );
+        }
+    }
+}
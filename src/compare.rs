@@ -0,0 +1,329 @@
+//! a small interactive protocol for two holders of merkle trees of the same height
+//! to find the first leaf where their trees diverge
+//!
+//! the initiator drives a `Comparison` and sends its `Request`s to the peer,
+//! which answers each of them with `respond`;
+//! the search descends from the root towards the leaves,
+//! so only O(height) node hashes are exchanged
+
+use crate::store::{MemoryStore, NodeStore};
+use crate::{Hash, HashFunction, Hasher, MerkleTree, MerkleTreeError, NodeHash};
+
+/// a message sent by the initiator of the comparison
+#[derive(Clone, PartialEq, Debug)]
+pub enum Request {
+    /// ask for the height and the root hash of the peer's tree
+    GetRoot,
+    /// ask for the hashes of the children of the node at `index`
+    GetChildren { index: usize },
+}
+
+/// a message sent back by the peer
+#[derive(Clone, PartialEq, Debug)]
+pub enum Response {
+    Root {
+        height: usize,
+        hash: NodeHash,
+    },
+    /// the children are either internal nodes or leaves, depending on the level of `index`
+    Children {
+        index: usize,
+        left: Hash,
+        right: Hash,
+    },
+}
+
+/// the result of a comparison
+#[derive(Clone, PartialEq, Debug)]
+pub enum Outcome {
+    /// both trees have the same root
+    Identical,
+    /// the trees do not have the same height, so their nodes cannot be compared
+    HeightMismatch,
+    /// the `value_index`'th value is the first one that differs between the trees
+    Diverged { value_index: usize },
+}
+
+/// what the initiator must do after receiving a response
+#[derive(Clone, PartialEq, Debug)]
+pub enum Step {
+    Send(Request),
+    Done(Outcome),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum State {
+    Started,
+    /// waiting for the peer's children of the node at this index,
+    /// which is known to differ between the trees
+    Descending(usize),
+    Done,
+}
+
+/// the state machine of the initiator of a comparison
+pub struct Comparison<'a, H = HashFunction, S = MemoryStore> {
+    tree: &'a MerkleTree<H, S>,
+    state: State,
+}
+
+impl<'a, H: Hasher, S: NodeStore> Comparison<'a, H, S> {
+    /// start a comparison of the local `tree` with a peer's tree
+    ///
+    /// the internal nodes of `tree` must be up to date
+    pub fn new(tree: &'a MerkleTree<H, S>) -> Self {
+        Comparison {
+            tree,
+            state: State::Started,
+        }
+    }
+
+    /// the first request to send to the peer
+    pub fn start(&self) -> Request {
+        Request::GetRoot
+    }
+
+    /// process the peer's `response` and decide on the next step
+    ///
+    /// fail if the response does not match the last request or contradicts a previous one,
+    /// if the comparison is already done or
+    /// if the local tree is not up to date
+    pub fn receive(&mut self, response: Response) -> Result<Step, MerkleTreeError> {
+        match (&self.state, response) {
+            (State::Started, Response::Root { height, hash }) => {
+                if height != self.tree.height {
+                    Ok(self.finish(Outcome::HeightMismatch))
+                } else if hash == self.tree.get_root()? {
+                    Ok(self.finish(Outcome::Identical))
+                } else {
+                    Ok(self.descend(1))
+                }
+            }
+            (
+                State::Descending(index),
+                Response::Children {
+                    index: i,
+                    left,
+                    right,
+                },
+            ) if *index == i => {
+                let left_child_index = 2 * i;
+
+                // the first divergence is on the left whenever the left children differ
+                let next_index = if left != self.tree.get_node_hash(left_child_index)? {
+                    left_child_index
+                } else if right != self.tree.get_node_hash(left_child_index + 1)? {
+                    left_child_index + 1
+                } else {
+                    // the children of a diverging node cannot be identical
+                    return Err(MerkleTreeError::InconsistentResponse);
+                };
+
+                if next_index >= self.tree.first_leaf_node_index {
+                    Ok(self.finish(Outcome::Diverged {
+                        value_index: next_index - self.tree.first_leaf_node_index,
+                    }))
+                } else {
+                    Ok(self.descend(next_index))
+                }
+            }
+            (State::Done, _) => Err(MerkleTreeError::ComparisonDone),
+            _ => Err(MerkleTreeError::UnexpectedResponse),
+        }
+    }
+
+    fn descend(&mut self, index: usize) -> Step {
+        self.state = State::Descending(index);
+        Step::Send(Request::GetChildren { index })
+    }
+
+    fn finish(&mut self, outcome: Outcome) -> Step {
+        self.state = State::Done;
+        Step::Done(outcome)
+    }
+}
+
+/// answer a `request` of the initiator with the state of the local `tree`
+///
+/// the internal nodes of `tree` must be up to date
+///
+/// fail if the requested node is a leaf or out of bounds or
+/// if the local tree is not up to date
+pub fn respond<H: Hasher, S: NodeStore>(
+    tree: &MerkleTree<H, S>,
+    request: &Request,
+) -> Result<Response, MerkleTreeError> {
+    match *request {
+        Request::GetRoot => Ok(Response::Root {
+            height: tree.height,
+            hash: tree.get_root()?,
+        }),
+        Request::GetChildren { index } => {
+            if index == 0 || index >= tree.nodes.node_count() {
+                return Err(MerkleTreeError::NodeOutOfBounds(index));
+            }
+
+            if index >= tree.first_leaf_node_index {
+                return Err(MerkleTreeError::NotInternalNode(index));
+            }
+
+            Ok(Response::Children {
+                index,
+                left: tree.get_node_hash(2 * index)?,
+                right: tree.get_node_hash(2 * index + 1)?,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+
+    fn tree_of(height: usize, values: &[&str]) -> MerkleTree {
+        let mut mt = MerkleTree::from_height(basic_hash as HashFunction, height).unwrap();
+
+        for value in values {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+
+        mt
+    }
+
+    fn compare(local: &MerkleTree, remote: &MerkleTree) -> (Outcome, usize) {
+        let mut comparison = Comparison::new(local);
+        let mut request = comparison.start();
+        let mut exchanges = 0;
+
+        loop {
+            exchanges += 1;
+            match comparison
+                .receive(respond(remote, &request).unwrap())
+                .unwrap()
+            {
+                Step::Send(next_request) => request = next_request,
+                Step::Done(outcome) => return (outcome, exchanges),
+            }
+        }
+    }
+
+    #[test]
+    fn identical_trees() {
+        let local = tree_of(3, &["a", "b", "c"]);
+        let remote = tree_of(3, &["a", "b", "c"]);
+
+        assert_eq!((Outcome::Identical, 1), compare(&local, &remote));
+    }
+
+    #[test]
+    fn height_mismatch() {
+        let local = tree_of(2, &["a"]);
+        let remote = tree_of(3, &["a"]);
+
+        assert_eq!((Outcome::HeightMismatch, 1), compare(&local, &remote));
+    }
+
+    #[test]
+    fn diverged_value() {
+        let local = tree_of(3, &["a", "b", "c", "d", "e"]);
+        let remote = tree_of(3, &["a", "b", "c", "x", "e"]);
+
+        // one exchange for the root and one per level below it
+        assert_eq!(
+            (Outcome::Diverged { value_index: 3 }, 4),
+            compare(&local, &remote)
+        );
+    }
+
+    #[test]
+    fn first_of_many_diverged_values() {
+        let local = tree_of(3, &["a", "b", "c", "d", "e", "f"]);
+        let remote = tree_of(3, &["a", "x", "c", "d", "e", "y"]);
+
+        assert_eq!(
+            (Outcome::Diverged { value_index: 1 }, 4),
+            compare(&local, &remote)
+        );
+    }
+
+    #[test]
+    fn missing_value() {
+        let local = tree_of(2, &["a", "b"]);
+        let remote = tree_of(2, &["a", "b", "c"]);
+
+        assert_eq!(
+            (Outcome::Diverged { value_index: 2 }, 3),
+            compare(&local, &remote)
+        );
+    }
+
+    #[test]
+    fn unexpected_response() {
+        let local = tree_of(2, &["a"]);
+        let mut comparison = Comparison::new(&local);
+
+        let response = Response::Children {
+            index: 1,
+            left: b"H(a)".to_vec(),
+            right: b"H(b)".to_vec(),
+        };
+
+        assert_eq!(
+            Err(MerkleTreeError::UnexpectedResponse),
+            comparison.receive(response)
+        );
+    }
+
+    #[test]
+    fn inconsistent_response() {
+        let local = tree_of(1, &["a"]);
+        let mut comparison = Comparison::new(&local);
+
+        comparison
+            .receive(Response::Root {
+                height: 1,
+                hash: NodeHash(b"H(forged)".to_vec()),
+            })
+            .unwrap();
+
+        let response = Response::Children {
+            index: 1,
+            left: b"H(a)".to_vec(),
+            right: b"H(empty node)".to_vec(),
+        };
+
+        assert_eq!(
+            Err(MerkleTreeError::InconsistentResponse),
+            comparison.receive(response)
+        );
+    }
+
+    #[test]
+    fn receive_after_done() {
+        let local = tree_of(1, &["a"]);
+        let mut comparison = Comparison::new(&local);
+
+        let root = respond(&local, &comparison.start()).unwrap();
+        comparison.receive(root.clone()).unwrap();
+
+        assert_eq!(
+            Err(MerkleTreeError::ComparisonDone),
+            comparison.receive(root)
+        );
+    }
+
+    #[test]
+    fn respond_children_of_leaf() {
+        let remote = tree_of(1, &["a"]);
+
+        assert_eq!(
+            Err(MerkleTreeError::NotInternalNode(2)),
+            respond(&remote, &Request::GetChildren { index: 2 })
+        );
+        assert_eq!(
+            Err(MerkleTreeError::NodeOutOfBounds(4)),
+            respond(&remote, &Request::GetChildren { index: 4 })
+        );
+    }
+}
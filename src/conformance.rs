@@ -0,0 +1,189 @@
+//! a canonical sequence of operations to check that ports of this crate
+//! to other languages produce exactly the same merkle trees
+//!
+//! a port runs the same sequence with the same hash function and height,
+//! and compares its digest with the one returned by `run`
+
+use crate::{EmptyLeaf, Hash, Hasher, LeafHash, MerkleTreeBuilder, MerkleTreeError};
+use alloc::format;
+use alloc::string::String;
+
+/// the value inserted at position `value_index` by the canonical sequence
+pub fn canonical_value(value_index: usize) -> String {
+    format!("value {}", value_index)
+}
+
+/// the value replacing the one at position `value_index` once the canonical sequence filled the tree
+pub fn canonical_update(value_index: usize) -> String {
+    format!("updated value {}", value_index)
+}
+
+/// the padding modes the canonical sequence is run with, in order:
+/// the default constant, the leaf hash of `"padding"` used as is and the index of the leaf
+pub fn canonical_empty_leaves<H: Hasher>(hasher: &H) -> [EmptyLeaf; 3] {
+    [
+        EmptyLeaf::default(),
+        EmptyLeaf::Hash(LeafHash(hasher.hash_leaf(b"padding"))),
+        EmptyLeaf::Index,
+    ]
+}
+
+/// run the canonical sequence on merkle trees of the given `height`, once for each of
+/// the `canonical_empty_leaves`, and return a digest of all the intermediate roots, values and proofs
+///
+/// the digest starts as the leaf hash of `"conformance"` and goes through the sequences in order,
+/// see `run_with_empty_leaf`
+///
+/// with `Sha256Hasher` and a height of 3, the digest is
+/// `1c650916c4e1ac84349ead77298d8548f7a311d483792ed8ab8558bfec26f0b3`
+///
+/// fail if the height is invalid
+pub fn run<H: Hasher + Clone>(hasher: H, height: usize) -> Result<Hash, MerkleTreeError> {
+    canonical_empty_leaves(&hasher)
+        .into_iter()
+        .try_fold(hasher.hash_leaf(b"conformance"), |digest, empty_leaf| {
+            fold_sequence(&hasher, height, empty_leaf, digest)
+        })
+}
+
+/// run the canonical sequence on a merkle tree of the given `height`
+/// whose empty leaves are defined by `empty_leaf`, and return its digest
+///
+/// the sequence fills the tree with `canonical_value`s, then replaces each of them
+/// with its `canonical_update`; starting from the root of the empty tree,
+/// it records after every insertion or replacement the hash of the value, the root and
+/// the siblings of the proof of the value, from the leaf up;
+/// the digest starts as the leaf hash of `"conformance"` and each recorded hash `h` is
+/// folded in as the node hash of `digest` and `h`
+///
+/// fail if the height is invalid or if `empty_leaf` is `EmptyLeaf::Forbidden`
+pub fn run_with_empty_leaf<H: Hasher + Clone>(
+    hasher: H,
+    height: usize,
+    empty_leaf: EmptyLeaf,
+) -> Result<Hash, MerkleTreeError> {
+    let digest = hasher.hash_leaf(b"conformance");
+
+    fold_sequence(&hasher, height, empty_leaf, digest)
+}
+
+/// fold the records of the canonical sequence into `digest`
+fn fold_sequence<H: Hasher + Clone>(
+    hasher: &H,
+    height: usize,
+    empty_leaf: EmptyLeaf,
+    digest: Hash,
+) -> Result<Hash, MerkleTreeError> {
+    let mut mt = MerkleTreeBuilder::new(hasher.clone(), height)
+        .empty_leaf(empty_leaf)
+        .build()?;
+    let capacity = mt.capacity();
+
+    let fold = |digest: Hash, hash: Hash| hasher.hash_nodes(&digest, &hash);
+
+    mt.update_internal_nodes()?;
+    let mut digest = fold(digest, mt.get_root()?.0);
+
+    let updates = (0..capacity).map(|value_index| (value_index, canonical_update(value_index)));
+    let operations = (0..capacity)
+        .map(|value_index| (value_index, canonical_value(value_index)))
+        .chain(updates);
+
+    for (value_index, value) in operations {
+        if value_index < mt.len() {
+            mt.set_value(value_index, value)?;
+        } else {
+            mt.insert(value)?;
+        }
+        mt.update_internal_nodes()?;
+
+        digest = fold(digest, mt.get_value(value_index)?.0);
+        digest = fold(digest, mt.get_root()?.0);
+        for step in mt.prove(value_index)?.steps {
+            digest = fold(digest, step.hash);
+        }
+    }
+
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use alloc::string::ToString;
+
+    #[test]
+    fn height_one_golden_digest() {
+        let records = [
+            // the empty tree
+            "H(H(empty node)H(empty node))",
+            // the insertions
+            "H(value 0)",
+            "H(H(value 0)H(empty node))",
+            "H(empty node)",
+            "H(value 1)",
+            "H(H(value 0)H(value 1))",
+            "H(value 0)",
+            // the replacements
+            "H(updated value 0)",
+            "H(H(updated value 0)H(value 1))",
+            "H(value 1)",
+            "H(updated value 1)",
+            "H(H(updated value 0)H(updated value 1))",
+            "H(updated value 0)",
+        ];
+
+        let expected = records
+            .iter()
+            .fold("H(conformance)".to_string(), |digest, hash| {
+                format!("H({}{})", digest, hash)
+            });
+
+        assert_eq!(
+            Ok(expected.into_bytes()),
+            run_with_empty_leaf(basic_hash, 1, EmptyLeaf::default())
+        );
+    }
+
+    #[test]
+    fn run_chains_the_padding_modes() {
+        let digest = |empty_leaf: EmptyLeaf| {
+            String::from_utf8(run_with_empty_leaf(basic_hash, 1, empty_leaf).unwrap()).unwrap()
+        };
+        let constant = digest(EmptyLeaf::default());
+        let hash = digest(EmptyLeaf::Hash(LeafHash(basic_hash(b"padding"))));
+        let index = digest(EmptyLeaf::Index);
+
+        // every padding mode changes the digest
+        assert_ne!(constant, hash);
+        assert_ne!(constant, index);
+        assert!(index.contains("H(H(value 0)H(1))"));
+
+        // the sequences of the three padding modes follow each other, 13 records each
+        let run = String::from_utf8(run(basic_hash, 1).unwrap()).unwrap();
+        assert!(run.starts_with(&format!("{}H(conformance)H(H(empty node)", "H(".repeat(39))));
+        assert!(run.contains("H(H(padding)H(padding))"));
+        assert!(run.ends_with("H(H(updated value 0)H(updated value 1)))H(updated value 0))"));
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn sha256_golden_digest() {
+        assert_eq!(
+            "1c650916c4e1ac84349ead77298d8548f7a311d483792ed8ab8558bfec26f0b3",
+            crate::hex::encode(&run(crate::sha256::Sha256Hasher, 3).unwrap())
+        );
+    }
+
+    #[test]
+    fn digest_depends_on_height() {
+        assert_ne!(run(basic_hash, 2), run(basic_hash, 3));
+    }
+
+    #[test]
+    fn invalid_height_and_padding() {
+        assert_eq!(Err(MerkleTreeError::InvalidHeight(0)), run(basic_hash, 0));
+        assert!(run_with_empty_leaf(basic_hash, 1, EmptyLeaf::Forbidden).is_err());
+    }
+}
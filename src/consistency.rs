@@ -0,0 +1,487 @@
+//! consistency proofs between two sizes of an append-only merkle tree
+//!
+//! like in RFC 6962, a consistency proof shows that the merkle tree with the new root
+//! starts with all the values of the merkle tree with the old root;
+//! as the merkle tree has a fixed height, the proof follows the path of the first leaf
+//! after the old values, whose left siblings only cover old values and are shared by both trees,
+//! while the leaf itself and its right siblings only cover the values appended since
+
+use crate::head::{TreeHead, TreeId};
+use crate::store::NodeStore;
+use crate::{EmptyLeaf, Hash, Hasher, MerkleTree, MerkleTreeError, PairOrder, MAX_HEIGHT};
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// the sibling of a node on the path from the first leaf after the old values to the root
+#[derive(Clone, PartialEq, Debug)]
+pub enum ConsistencyStep {
+    /// a left sibling, only covering old values
+    Shared(Hash),
+    /// a right sibling, with its hashes in the old and the new merkle trees
+    Changed { old: Hash, new: Hash },
+}
+
+/// a proof that a merkle tree of `new_size` values extends the same tree of `old_size` values
+#[derive(Clone, PartialEq, Debug)]
+pub struct ConsistencyProof {
+    /// the id of the merkle tree the proof was generated from
+    pub tree_id: TreeId,
+    pub old_size: usize,
+    pub new_size: usize,
+    /// how the merkle tree orders the hashes of two children
+    pub pair_order: PairOrder,
+    /// the hashes of the leaf at position `old_size` in the old and the new merkle trees,
+    /// or `None` if both sizes are equal, in which case the roots must be too
+    pub boundary: Option<(Hash, Hash)>,
+    /// the siblings from this leaf up to the children of the root
+    pub steps: Vec<ConsistencyStep>,
+}
+
+impl ConsistencyProof {
+    /// check that the merkle tree of the `new` head extends the one of the `old` head,
+    /// without needing the merkle tree itself
+    ///
+    /// the height and the sizes come from the verifier, e.g. from trusted heads, rather than
+    /// from the proof, and `empty_leaf` must be the one of the merkle tree, as the old hashes
+    /// of the nodes after the old values must be those of empty subtrees
+    ///
+    /// the subtrees after the new values are the same in both merkle trees, so their hashes
+    /// are only compared, since recomputing those of `EmptyLeaf::Index` would take
+    /// as many hashes as they have leaves
+    pub fn verify(
+        &self,
+        height: usize,
+        old: &TreeHead,
+        new: &TreeHead,
+        empty_leaf: &EmptyLeaf,
+        hasher: &impl Hasher,
+    ) -> bool {
+        let (old_size, old_root) = (old.length, &old.root);
+        let (new_size, new_root) = (new.length, &new.root);
+        if self.old_size != old_size || self.new_size != new_size {
+            return false;
+        }
+
+        let Some((old_leaf, new_leaf)) = &self.boundary else {
+            return old_size == new_size && old_root == new_root;
+        };
+
+        // the steps must follow the path of the leaf at position `old_size`,
+        // whose bits tell on which side each sibling stands, up to the root of the merkle tree
+        if old_size >= new_size
+            || height > MAX_HEIGHT
+            || self.steps.len() != height
+            || new_size > 1 << height
+        {
+            return false;
+        }
+
+        // the leaf at position `old_size` was still empty in the old merkle tree
+        if self
+            .empty_subtree_hash(hasher, empty_leaf, 0, old_size)
+            .as_ref()
+            != Some(old_leaf)
+        {
+            return false;
+        }
+
+        let mut old_hash = old_leaf.clone();
+        let mut new_hash = new_leaf.clone();
+
+        for (level, step) in self.steps.iter().enumerate() {
+            let is_right_child = old_size >> level & 1 == 1;
+
+            match (step, is_right_child) {
+                (ConsistencyStep::Shared(sibling), true) => {
+                    old_hash = self.hash_pair(hasher, sibling, &old_hash);
+                    new_hash = self.hash_pair(hasher, sibling, &new_hash);
+                }
+                (ConsistencyStep::Changed { old, new }, false) => {
+                    // a right sibling only covers positions after the old values,
+                    // and it is only changed if it also covers new values
+                    let first_value_index = ((old_size >> level) + 1) << level;
+                    if first_value_index >= new_size {
+                        if old != new {
+                            return false;
+                        }
+                    } else if self
+                        .empty_subtree_hash(hasher, empty_leaf, level, first_value_index)
+                        .as_ref()
+                        != Some(old)
+                    {
+                        return false;
+                    }
+
+                    old_hash = self.hash_pair(hasher, &old_hash, old);
+                    new_hash = self.hash_pair(hasher, &new_hash, new);
+                }
+                _ => return false,
+            }
+        }
+
+        old_hash == old_root.0 && new_hash == new_root.0
+    }
+
+    /// compute the hash of the empty subtree `level` levels above the leaves
+    /// whose first leaf is at position `first_value_index`,
+    /// or return `None` if empty leaves are forbidden
+    fn empty_subtree_hash(
+        &self,
+        hasher: &impl Hasher,
+        empty_leaf: &EmptyLeaf,
+        level: usize,
+        first_value_index: usize,
+    ) -> Option<Hash> {
+        let empty_leaf_hash = match empty_leaf {
+            EmptyLeaf::Constant(constant) => hasher.hash_leaf(constant.as_bytes()),
+            EmptyLeaf::Hash(leaf_hash) => leaf_hash.0.clone(),
+            // both halves of the subtree differ when the empty leaves hash their index
+            EmptyLeaf::Index if level > 0 => {
+                let half = 1 << (level - 1);
+                let left =
+                    self.empty_subtree_hash(hasher, empty_leaf, level - 1, first_value_index)?;
+                let right = self.empty_subtree_hash(
+                    hasher,
+                    empty_leaf,
+                    level - 1,
+                    first_value_index + half,
+                )?;
+
+                return Some(self.hash_pair(hasher, &left, &right));
+            }
+            EmptyLeaf::Index => {
+                return Some(hasher.hash_leaf(first_value_index.to_string().as_bytes()))
+            }
+            EmptyLeaf::Forbidden => return None,
+        };
+
+        Some((0..level).fold(empty_leaf_hash, |hash, _| {
+            self.hash_pair(hasher, &hash, &hash)
+        }))
+    }
+
+    fn hash_pair(&self, hasher: &impl Hasher, left: &[u8], right: &[u8]) -> Hash {
+        let (first, second) = self.pair_order.arrange(left, right);
+
+        hasher.hash_nodes(first, second)
+    }
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// generate the proof that the merkle tree of the first `new_size` values
+    /// extends the merkle tree of the first `old_size` values,
+    /// assuming the values were only appended since
+    ///
+    /// fail if `old_size` is greater than `new_size`,
+    /// if `new_size` is greater than the number of inserted values,
+    /// if the internal nodes are not up to date or
+    /// if an empty leaf is needed while empty leaves are forbidden
+    pub fn prove_consistency(
+        &self,
+        old_size: usize,
+        new_size: usize,
+    ) -> Result<ConsistencyProof, MerkleTreeError> {
+        if old_size > new_size || new_size > self.length {
+            return Err(MerkleTreeError::InvalidSizes(old_size, new_size));
+        }
+
+        // check that the root is up to date for the proof to be valid
+        self.get_root()?;
+
+        self.record_proof();
+
+        let mut proof = ConsistencyProof {
+            tree_id: self.tree_id,
+            old_size,
+            new_size,
+            pair_order: self.pair_order,
+            boundary: None,
+            steps: Vec::with_capacity(self.height),
+        };
+
+        if old_size == new_size {
+            return Ok(proof);
+        }
+
+        let mut index = self.first_leaf_node_index + old_size;
+        proof.boundary = Some((
+            self.hash_at_size(index, old_size)?,
+            self.hash_at_size(index, new_size)?,
+        ));
+
+        while index > 1 {
+            // the sibling of a left child is on its right and vice versa
+            let step = if index.is_multiple_of(2) {
+                ConsistencyStep::Changed {
+                    old: self.hash_at_size(index + 1, old_size)?,
+                    new: self.hash_at_size(index + 1, new_size)?,
+                }
+            } else {
+                ConsistencyStep::Shared(self.get_node_hash(index - 1)?)
+            };
+            proof.steps.push(step);
+
+            index /= 2;
+        }
+
+        Ok(proof)
+    }
+
+    /// compute the hash the node at `index` had when only the first `size` values were inserted
+    ///
+    /// fail if a node needed is internal and `None` or an empty leaf that is forbidden
+    fn hash_at_size(&self, index: usize, size: usize) -> Result<Hash, MerkleTreeError> {
+        let depth = self.height - index.ilog2() as usize;
+        let first_value_index = (index << depth) - self.first_leaf_node_index;
+
+        if first_value_index >= size {
+            return self.get_empty_subtree_hash(index);
+        }
+
+        if first_value_index + (1 << depth) <= size {
+            return self.get_node_hash(index);
+        }
+
+        // only the subtrees straddling `size` differ from the current ones
+        let left_child_hash = self.hash_at_size(2 * index, size)?;
+        let right_child_hash = self.hash_at_size(2 * index + 1, size)?;
+        let (first, second) = self.pair_order.arrange(&left_child_hash, &right_child_hash);
+
+        Ok(self.hash_nodes(first, second))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::{HashFunction, MerkleTreeBuilder, NodeHash};
+    use alloc::vec;
+
+    fn tree_of(height: usize, values: &[&str]) -> MerkleTree {
+        let mut mt = MerkleTree::from_height(basic_hash as HashFunction, height).unwrap();
+
+        for value in values {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+
+        mt
+    }
+
+    /// the trusted head of `length` values with root `root`
+    fn head(length: usize, root: &NodeHash) -> TreeHead {
+        TreeHead {
+            tree_id: TreeId(0),
+            root: root.clone(),
+            length,
+        }
+    }
+
+    #[test]
+    fn prove_consistency_between_all_sizes() {
+        let values = ["a", "b", "c", "d", "e", "f"];
+        let mt = tree_of(3, &values);
+
+        for new_size in 0..=values.len() {
+            let new_root = tree_of(3, &values[..new_size]).get_root().unwrap();
+
+            for old_size in 0..=new_size {
+                let old_root = tree_of(3, &values[..old_size]).get_root().unwrap();
+                let proof = mt.prove_consistency(old_size, new_size).unwrap();
+
+                assert!(proof.verify(
+                    3,
+                    &head(old_size, &old_root),
+                    &head(new_size, &new_root),
+                    &EmptyLeaf::default(),
+                    &basic_hash
+                ));
+                assert_eq!(
+                    old_size == new_size,
+                    proof.verify(
+                        3,
+                        &head(old_size, &new_root),
+                        &head(new_size, &old_root),
+                        &EmptyLeaf::default(),
+                        &basic_hash
+                    )
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn verify_rejects_rewritten_history() {
+        let mt = tree_of(2, &["a", "b", "c"]);
+        let forked = tree_of(2, &["a", "x"]);
+
+        let proof = mt.prove_consistency(2, 3).unwrap();
+
+        assert_eq!(
+            vec![
+                ConsistencyStep::Changed {
+                    old: b"H(empty node)".to_vec(),
+                    new: b"H(empty node)".to_vec(),
+                },
+                ConsistencyStep::Shared(b"H(H(a)H(b))".to_vec()),
+            ],
+            proof.steps
+        );
+        assert!(!proof.verify(
+            2,
+            &head(2, &forked.get_root().unwrap()),
+            &head(3, &mt.get_root().unwrap()),
+            &EmptyLeaf::default(),
+            &basic_hash
+        ));
+    }
+
+    #[test]
+    fn verify_rejects_tampered_boundary() {
+        let old = tree_of(2, &["a", "b", "c"]);
+        let new = tree_of(2, &["a", "b", "x"]);
+
+        // claim that the old merkle tree only held its first 2 values, and had `c` after them
+        let mut proof = new.prove_consistency(2, 3).unwrap();
+        proof.boundary.as_mut().unwrap().0 = b"H(c)".to_vec();
+
+        let old_root = old.get_root().unwrap();
+        let new_root = new.get_root().unwrap();
+        assert!(!proof.verify(
+            2,
+            &head(2, &old_root),
+            &head(3, &new_root),
+            &EmptyLeaf::default(),
+            &basic_hash
+        ));
+
+        // nor can the old hash of a right sibling cover values
+        let old = tree_of(2, &["a", "b", "c", "d"]);
+        let new = tree_of(2, &["a", "b", "c", "x"]);
+        let mut proof = new.prove_consistency(1, 4).unwrap();
+        proof.boundary.as_mut().unwrap().0 = b"H(empty node)".to_vec();
+        proof.steps[1] = ConsistencyStep::Changed {
+            old: old.get_node_hash(3).unwrap(),
+            new: new.get_node_hash(3).unwrap(),
+        };
+
+        assert!(!proof.verify(
+            2,
+            &head(1, &old.get_root().unwrap()),
+            &head(4, &new.get_root().unwrap()),
+            &EmptyLeaf::default(),
+            &basic_hash
+        ));
+    }
+
+    #[test]
+    fn verify_with_the_sizes_of_the_verifier() {
+        let values = ["a", "b", "c", "d"];
+        let mt = tree_of(2, &values);
+        let old_root = tree_of(2, &values[..1]).get_root().unwrap();
+        let new_root = mt.get_root().unwrap();
+        let proof = mt.prove_consistency(1, 4).unwrap();
+
+        assert!(!proof.verify(
+            2,
+            &head(2, &old_root),
+            &head(4, &new_root),
+            &EmptyLeaf::default(),
+            &basic_hash
+        ));
+        assert!(!proof.verify(
+            2,
+            &head(1, &old_root),
+            &head(3, &new_root),
+            &EmptyLeaf::default(),
+            &basic_hash
+        ));
+    }
+
+    #[test]
+    fn verify_with_indexed_empty_leaves() {
+        let values = ["a", "b", "c", "d", "e"];
+        let tree_of = |size: usize| {
+            let mut mt = MerkleTreeBuilder::new(basic_hash as HashFunction, 3)
+                .empty_leaf(EmptyLeaf::Index)
+                .build()
+                .unwrap();
+            for value in &values[..size] {
+                mt.insert(value).unwrap();
+            }
+            mt.update_internal_nodes().unwrap();
+
+            mt
+        };
+
+        let mt = tree_of(5);
+        for old_size in 0..5 {
+            let proof = mt.prove_consistency(old_size, 5).unwrap();
+            let old_root = tree_of(old_size).get_root().unwrap();
+
+            assert!(proof.verify(
+                3,
+                &head(old_size, &old_root),
+                &head(5, &mt.get_root().unwrap()),
+                &EmptyLeaf::Index,
+                &basic_hash
+            ));
+            assert!(!proof.verify(
+                3,
+                &head(old_size, &old_root),
+                &head(5, &mt.get_root().unwrap()),
+                &EmptyLeaf::default(),
+                &basic_hash
+            ));
+        }
+    }
+
+    #[test]
+    fn verify_bounds_the_work_on_long_proofs() {
+        let mt = MerkleTreeBuilder::new(basic_hash as HashFunction, 2)
+            .empty_leaf(EmptyLeaf::Index)
+            .build()
+            .unwrap();
+        let mut proof = ConsistencyProof {
+            tree_id: mt.tree_id(),
+            old_size: 1,
+            new_size: 2,
+            pair_order: PairOrder::Positional,
+            boundary: Some((b"H(1)".to_vec(), b"H(b)".to_vec())),
+            steps: vec![ConsistencyStep::Shared(b"H(a)".to_vec())],
+        };
+        let changed = ConsistencyStep::Changed {
+            old: b"H(forged)".to_vec(),
+            new: b"H(forged)".to_vec(),
+        };
+        proof.steps.extend(core::iter::repeat_n(changed, 59));
+        let root = NodeHash(b"H(forged)".to_vec());
+
+        // the steps must reach the root of a merkle tree of the height of the verifier,
+        // and the subtrees after the new values are not recomputed, however high
+        for height in [2, 60] {
+            assert!(!proof.verify(
+                height,
+                &head(1, &root),
+                &head(2, &root),
+                &EmptyLeaf::Index,
+                &basic_hash
+            ));
+        }
+    }
+
+    #[test]
+    fn prove_consistency_invalid_sizes() {
+        let mt = tree_of(2, &["a", "b"]);
+
+        assert_eq!(
+            Err(MerkleTreeError::InvalidSizes(2, 1)),
+            mt.prove_consistency(2, 1)
+        );
+        assert_eq!(
+            Err(MerkleTreeError::InvalidSizes(1, 3)),
+            mt.prove_consistency(1, 3)
+        );
+    }
+}
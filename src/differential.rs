@@ -0,0 +1,265 @@
+//! differential testing of merkle trees against a naive reference implementation
+//!
+//! a `DifferentialTree` mirrors every operation into a reference tree that
+//! recomputes the root from scratch, and panics as soon as both disagree,
+//! so integrations of this crate can catch divergences in their own tests
+//!
+//! only available with the `differential` feature
+
+use crate::store::{MemoryStore, NodeStore};
+use crate::{
+    EmptyLeaf, Hash, HashFunction, Hasher, LeafHash, MerkleTree, MerkleTreeError, NodeHash,
+    PairOrder,
+};
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// a recompute-everything merkle tree keeping only the hashes of its leaves
+struct ReferenceTree<H> {
+    hasher: H,
+    height: usize,
+    empty_leaf: EmptyLeaf,
+    pair_order: PairOrder,
+    bind_index: bool,
+    leaves: Vec<Hash>,
+}
+
+impl<H: Hasher> ReferenceTree<H> {
+    fn leaf(&self, value_index: usize) -> Result<Hash, MerkleTreeError> {
+        match (self.leaves.get(value_index), &self.empty_leaf) {
+            (Some(hash), _) => Ok(hash.clone()),
+            (None, EmptyLeaf::Constant(constant)) => Ok(self.hasher.hash_leaf(constant.as_bytes())),
+            (None, EmptyLeaf::Hash(leaf_hash)) => Ok(leaf_hash.0.clone()),
+            (None, EmptyLeaf::Index) => {
+                Ok(self.hasher.hash_leaf(value_index.to_string().as_bytes()))
+            }
+            (None, EmptyLeaf::Forbidden) => Err(MerkleTreeError::EmptyLeafForbidden(value_index)),
+        }
+    }
+
+    /// compute the hash of the `index`'th node of the `level`'th level above the leaves
+    fn node(&self, level: usize, index: usize) -> Result<Hash, MerkleTreeError> {
+        if level == 0 {
+            return self.leaf(index);
+        }
+
+        let left = self.node(level - 1, 2 * index)?;
+        let right = self.node(level - 1, 2 * index + 1)?;
+
+        let (first, second) = self.pair_order.arrange(&left, &right);
+
+        Ok(self.hasher.hash_nodes(first, second))
+    }
+
+    fn root(&self) -> Result<Hash, MerkleTreeError> {
+        self.node(self.height, 0)
+    }
+
+    /// hash `value` as the leaf at `value_index`
+    fn hash_value(&self, value_index: usize, value: &[u8]) -> Hash {
+        if self.bind_index {
+            let value_index = value_index as u64;
+
+            return self
+                .hasher
+                .hash_leaf(&[&value_index.to_be_bytes(), value].concat());
+        }
+
+        self.hasher.hash_leaf(value)
+    }
+}
+
+/// a merkle tree whose operations are checked against a reference implementation
+pub struct DifferentialTree<H = HashFunction, S = MemoryStore> {
+    tree: MerkleTree<H, S>,
+    reference: ReferenceTree<H>,
+}
+
+impl<H: Hasher + Clone, S: NodeStore> DifferentialTree<H, S> {
+    /// check the operations on `tree`, which may already contain values
+    pub fn new(tree: MerkleTree<H, S>) -> Self {
+        let leaves = (0..tree.length)
+            .map(|value_index| {
+                tree.nodes
+                    .get_hash(tree.first_leaf_node_index + value_index)
+            })
+            .map(|hash| hash.expect("Inserted leaves cannot be None."))
+            .collect();
+
+        let reference = ReferenceTree {
+            hasher: tree.hasher.clone(),
+            height: tree.height,
+            empty_leaf: tree.empty_leaf.clone(),
+            pair_order: tree.pair_order,
+            bind_index: tree.bind_index,
+            leaves,
+        };
+
+        DifferentialTree { tree, reference }
+    }
+
+    /// insert a value into both trees
+    ///
+    /// panic if only one of the trees accepts the value
+    pub fn insert(&mut self, value: impl AsRef<[u8]>) -> Result<(), MerkleTreeError> {
+        let value = value.as_ref();
+        let capacity = 1 << self.reference.height;
+        let result = self.tree.insert(value);
+
+        match &result {
+            Ok(()) => {
+                assert!(
+                    self.reference.leaves.len() < capacity,
+                    "The merkle tree accepted a value past its capacity."
+                );
+                let hash = self
+                    .reference
+                    .hash_value(self.reference.leaves.len(), value);
+                self.reference.leaves.push(hash);
+            }
+            Err(MerkleTreeError::Full) => assert_eq!(
+                capacity,
+                self.reference.leaves.len(),
+                "The merkle tree is full before reaching its capacity."
+            ),
+            Err(_) => {}
+        }
+
+        result
+    }
+
+    /// replace a value in both trees
+    ///
+    /// panic if the merkle tree replaces a value that was never inserted
+    pub fn set_value(
+        &mut self,
+        value_index: usize,
+        value: impl AsRef<[u8]>,
+    ) -> Result<(), MerkleTreeError> {
+        let value = value.as_ref();
+        let result = self.tree.set_value(value_index, value);
+
+        if result.is_ok() {
+            assert!(
+                value_index < self.reference.leaves.len(),
+                "The merkle tree replaced a value that was never inserted."
+            );
+            self.reference.leaves[value_index] = self.reference.hash_value(value_index, value);
+        }
+
+        result
+    }
+
+    pub fn update_internal_nodes(&mut self) -> Result<(), MerkleTreeError> {
+        self.tree.update_internal_nodes()
+    }
+
+    /// get the root hash of the merkle tree
+    ///
+    /// panic if it differs from the root of the reference tree
+    pub fn get_root(&self) -> Result<NodeHash, MerkleTreeError> {
+        let root = self.tree.get_root()?;
+
+        assert_eq!(
+            self.reference.root().map(NodeHash),
+            Ok(root.clone()),
+            "The root of the merkle tree differs from the reference root."
+        );
+
+        Ok(root)
+    }
+
+    /// get the hash of the `value_index`'th value of the merkle tree
+    ///
+    /// panic if it differs from the hash of the value in the reference tree
+    pub fn get_value(&self, value_index: usize) -> Result<LeafHash, MerkleTreeError> {
+        let leaf_hash = self.tree.get_value(value_index)?;
+
+        assert_eq!(
+            self.reference.leaf(value_index).map(LeafHash),
+            Ok(leaf_hash.clone()),
+            "The value of the merkle tree differs from the reference value."
+        );
+
+        Ok(leaf_hash)
+    }
+
+    /// get the checked merkle tree
+    pub fn tree(&self) -> &MerkleTree<H, S> {
+        &self.tree
+    }
+
+    /// stop checking the operations and get the merkle tree back
+    pub fn into_inner(self) -> MerkleTree<H, S> {
+        self.tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::MerkleTreeBuilder;
+
+    #[test]
+    fn matches_reference_while_filling_tree() {
+        let mut dt = DifferentialTree::new(MerkleTree::from_height(basic_hash, 3).unwrap());
+
+        for i in 0..8 {
+            dt.insert(i.to_string()).unwrap();
+            dt.update_internal_nodes().unwrap();
+            dt.get_root().unwrap();
+            dt.get_value(i).unwrap();
+        }
+
+        assert_eq!(Err(MerkleTreeError::Full), dt.insert("8"));
+    }
+
+    #[test]
+    fn matches_reference_with_existing_values_and_empty_leaves() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .empty_leaf(EmptyLeaf::Index)
+            .build()
+            .unwrap();
+        mt.insert("a").unwrap();
+
+        let mut dt = DifferentialTree::new(mt);
+        dt.insert("b").unwrap();
+        dt.update_internal_nodes().unwrap();
+
+        dt.get_root().unwrap();
+        dt.get_value(3).unwrap();
+    }
+
+    #[test]
+    fn matches_reference_while_replacing_values() {
+        let mut dt = DifferentialTree::new(
+            MerkleTreeBuilder::new(basic_hash, 2)
+                .bind_index(true)
+                .build()
+                .unwrap(),
+        );
+        dt.insert("a").unwrap();
+        dt.insert("b").unwrap();
+
+        dt.set_value(0, "c").unwrap();
+        dt.update_internal_nodes().unwrap();
+        dt.get_root().unwrap();
+        dt.get_value(0).unwrap();
+
+        assert_eq!(Err(MerkleTreeError::NeverInserted(2)), dt.set_value(2, "d"));
+    }
+
+    #[test]
+    #[should_panic(expected = "differs from the reference root")]
+    fn diverging_root_panics() {
+        let mut dt = DifferentialTree::new(MerkleTree::from_height(basic_hash, 1).unwrap());
+        dt.insert("1").unwrap();
+        dt.update_internal_nodes().unwrap();
+
+        // simulate a faulty backend by corrupting the root behind the reference's back
+        dt.tree.nodes.set_hash(1, Some(b"corrupted".to_vec()));
+
+        dt.get_root().unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! roots of leaf digests computed by other systems
+//!
+//! the root is computed while the digests are read, keeping a single pending node per level,
+//! so a root can be cross-checked without storing the merkle tree
+
+use crate::{Hash, Hasher, LeafHash, MerkleTreeBuilder, MerkleTreeError, NodeHash};
+use alloc::vec;
+use alloc::vec::Vec;
+
+impl<H: Hasher> MerkleTreeBuilder<H> {
+    /// compute the root of the merkle tree built from the collected options
+    /// whose leaves are the `digests`, inserted as is like with `insert_hash`,
+    /// followed by empty leaves, without storing its nodes
+    ///
+    /// fail if the merkle tree cannot be built,
+    /// if there are more digests than leaves or
+    /// if an empty leaf is needed while empty leaves are forbidden
+    pub fn root_from_digests(
+        self,
+        digests: impl IntoIterator<Item = LeafHash>,
+    ) -> Result<NodeHash, MerkleTreeError> {
+        // an empty merkle tree kept in memory only stores its configuration
+        let mt = self.build()?;
+        let capacity = 1 << mt.height;
+        let hash_pair = |left: &[u8], right: &[u8]| {
+            let (first, second) = mt.pair_order.arrange(left, right);
+
+            mt.hash_nodes(first, second)
+        };
+
+        // `complete[level]` is the root of the last complete subtree of that level
+        // whose right sibling is still missing
+        let mut complete: Vec<Option<Hash>> = vec![None; mt.height + 1];
+        let mut count = 0;
+
+        for LeafHash(digest) in digests {
+            if count == capacity {
+                return Err(MerkleTreeError::Full);
+            }
+
+            let mut hash = digest;
+            let mut level = 0;
+            while let Some(left) = complete[level].take() {
+                hash = hash_pair(&left, &hash);
+                level += 1;
+            }
+            complete[level] = Some(hash);
+
+            count += 1;
+        }
+
+        // go up the path of the first leaf after the digests, where `pending` is the hash
+        // of the node of this path, or `None` if no digest is below it
+        let mut pending: Option<Hash> = None;
+        for (level, complete) in complete.iter_mut().take(mt.height).enumerate() {
+            // the node on the path at this level and its sibling, which is empty if on its right
+            let index = (mt.first_leaf_node_index >> level) + (count >> level);
+
+            pending = match (complete.take(), pending) {
+                (Some(left), Some(right)) => Some(hash_pair(&left, &right)),
+                (Some(left), None) => Some(hash_pair(&left, &mt.get_empty_subtree_hash(index)?)),
+                (None, Some(left)) => {
+                    Some(hash_pair(&left, &mt.get_empty_subtree_hash(index + 1)?))
+                }
+                (None, None) => None,
+            };
+        }
+
+        match (complete[mt.height].take(), pending) {
+            (Some(root), _) | (None, Some(root)) => Ok(NodeHash(root)),
+            (None, None) => Ok(mt.empty_root()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::{EmptyLeaf, HashFunction, MerkleTree, PairOrder};
+    use alloc::string::ToString;
+
+    fn digests(count: usize) -> Vec<LeafHash> {
+        (0..count)
+            .map(|i| LeafHash(basic_hash(i.to_string().as_bytes())))
+            .collect()
+    }
+
+    fn builder(empty_leaf: EmptyLeaf, pair_order: PairOrder) -> MerkleTreeBuilder {
+        MerkleTreeBuilder::new(basic_hash as HashFunction, 3)
+            .empty_leaf(empty_leaf)
+            .pair_order(pair_order)
+    }
+
+    #[test]
+    fn root_from_digests_matches_merkle_tree() {
+        for empty_leaf in [EmptyLeaf::default(), EmptyLeaf::Index] {
+            for pair_order in [PairOrder::Positional, PairOrder::Sorted] {
+                for count in 0..=8 {
+                    let mut mt: MerkleTree =
+                        builder(empty_leaf.clone(), pair_order).build().unwrap();
+                    for digest in digests(count) {
+                        mt.insert_hash(digest).unwrap();
+                    }
+                    mt.update_internal_nodes().unwrap();
+
+                    assert_eq!(
+                        mt.get_root(),
+                        builder(empty_leaf.clone(), pair_order).root_from_digests(digests(count)),
+                        "{} digests with {:?} and {:?}",
+                        count,
+                        empty_leaf,
+                        pair_order
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn root_from_digests_invalid() {
+        assert_eq!(
+            Err(MerkleTreeError::Full),
+            builder(EmptyLeaf::default(), PairOrder::Positional).root_from_digests(digests(9))
+        );
+        assert_eq!(
+            Err(MerkleTreeError::EmptyLeafForbidden(3)),
+            builder(EmptyLeaf::Forbidden, PairOrder::Positional).root_from_digests(digests(3))
+        );
+        assert!(builder(EmptyLeaf::Forbidden, PairOrder::Positional)
+            .root_from_digests(digests(8))
+            .is_ok());
+        assert_eq!(
+            Ok(NodeHash(Vec::new())),
+            builder(EmptyLeaf::Forbidden, PairOrder::Positional).root_from_digests(digests(0))
+        );
+    }
+}
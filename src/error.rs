@@ -0,0 +1,153 @@
+use alloc::string::String;
+use core::fmt;
+
+/// the errors returned by the operations on merkle trees
+#[derive(Clone, PartialEq, Debug)]
+pub enum MerkleTreeError {
+    /// the height is less or equal to 0 or greater than `MAX_HEIGHT`
+    InvalidHeight(usize),
+    /// the merkle tree cannot hold more values
+    Full,
+    /// the value was rejected by the validation function, for the given reason
+    Rejected(String),
+    /// the value index is out of the bounds of the merkle tree
+    ValueOutOfBounds(usize),
+    /// the node index is out of the bounds of the merkle tree
+    NodeOutOfBounds(usize),
+    /// no value was inserted at this value index yet, which is forbidden in strict mode
+    NeverInserted(usize),
+    /// the internal node at this index is `None`, i.e. not computed with `update_internal_nodes`
+    StaleInternalNode(usize),
+    /// the leaf at this value index is empty and empty leaves are forbidden
+    EmptyLeafForbidden(usize),
+    /// this id was already inserted with another value
+    IdConflict(String),
+    /// the head is not the one currently prepared
+    HeadNotPrepared,
+    /// the snapshot is inconsistent with itself
+    InvalidSnapshot,
+    /// the compact ranges are not adjacent
+    NonAdjacentRanges,
+    /// the compact range does not start at the first value
+    UnalignedRange,
+    /// the bitmap does not match the size of the merkle tree
+    BitmapSizeMismatch,
+    /// the node at this index is a leaf, so it has no children
+    NotInternalNode(usize),
+    /// the peer's response does not match the last request of a comparison
+    UnexpectedResponse,
+    /// the peer's response contradicts a previous one
+    InconsistentResponse,
+    /// the comparison is already done
+    ComparisonDone,
+    /// the operation would make the merkle tree hold more than this budget, in bytes
+    MemoryBudgetExceeded(usize),
+    /// the old size is greater than the new size or the new size than the length of the merkle tree
+    InvalidSizes(usize, usize),
+    /// the heads belong to different merkle trees
+    TreeMismatch,
+    /// the key does not have the length of the keys of the sparse merkle tree, in bytes
+    InvalidKeyLength(usize),
+    /// the JSON is not a valid encoding, for the given reason
+    InvalidJson(String),
+    /// a merkle tree with this name already exists
+    TreeExists(String),
+    /// there is no merkle tree with this name
+    UnknownTree(String),
+    /// this tenant already owns as many merkle trees as its quota allows
+    TreeQuotaExceeded(String),
+    /// this tenant already inserted as many values as its quota allows
+    LeafQuotaExceeded(String),
+    /// the rate limiter refused an insertion of this tenant
+    RateLimited(String),
+    /// the line at this number, starting from 1, is not a digest of the list of digests
+    InvalidDigestLine(usize),
+}
+
+impl fmt::Display for MerkleTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MerkleTreeError::InvalidHeight(height) => write!(
+                f,
+                "The height of the merkle tree cannot be less or equal to 0 or greater than {}, got {}.",
+                crate::MAX_HEIGHT, height
+            ),
+            MerkleTreeError::Full => write!(f, "The merkle tree is already full."),
+            MerkleTreeError::Rejected(reason) => write!(f, "The value was rejected: {}", reason),
+            MerkleTreeError::ValueOutOfBounds(value_index) => {
+                write!(f, "The value {} is out of bounds.", value_index)
+            }
+            MerkleTreeError::NodeOutOfBounds(index) => {
+                write!(f, "The node {} is out of bounds.", index)
+            }
+            MerkleTreeError::NeverInserted(value_index) => {
+                write!(f, "The value {} was never inserted.", value_index)
+            }
+            MerkleTreeError::StaleInternalNode(index) => {
+                write!(f, "The internal node {} cannot be None.", index)
+            }
+            MerkleTreeError::EmptyLeafForbidden(value_index) => {
+                write!(f, "The leaf {} is empty and empty leaves are forbidden.", value_index)
+            }
+            MerkleTreeError::IdConflict(id) => {
+                write!(f, "The id {} was already inserted with another value.", id)
+            }
+            MerkleTreeError::HeadNotPrepared => write!(f, "Only the prepared head can be committed."),
+            MerkleTreeError::InvalidSnapshot => write!(f, "The snapshot is inconsistent."),
+            MerkleTreeError::NonAdjacentRanges => write!(f, "Only adjacent ranges can be merged."),
+            MerkleTreeError::UnalignedRange => {
+                write!(f, "Only ranges starting at the first value have a root.")
+            }
+            MerkleTreeError::BitmapSizeMismatch => {
+                write!(f, "The bitmap does not match the size of the merkle tree.")
+            }
+            MerkleTreeError::NotInternalNode(index) => {
+                write!(f, "The node {} is not internal, so it has no children.", index)
+            }
+            MerkleTreeError::UnexpectedResponse => {
+                write!(f, "This response does not match the last request.")
+            }
+            MerkleTreeError::InconsistentResponse => {
+                write!(f, "This response contradicts a previous one.")
+            }
+            MerkleTreeError::ComparisonDone => write!(f, "The comparison is already done."),
+            MerkleTreeError::MemoryBudgetExceeded(budget) => write!(
+                f,
+                "The merkle tree cannot hold more than its budget of {} bytes.",
+                budget
+            ),
+            MerkleTreeError::InvalidSizes(old_size, new_size) => write!(
+                f,
+                "The merkle tree cannot grow from {} to {} values.",
+                old_size, new_size
+            ),
+            MerkleTreeError::TreeMismatch => {
+                write!(f, "The heads belong to different merkle trees.")
+            }
+            MerkleTreeError::InvalidKeyLength(length) => {
+                write!(f, "The key cannot be {} bytes long.", length)
+            }
+            MerkleTreeError::InvalidJson(reason) => write!(f, "The JSON is invalid: {}", reason),
+            MerkleTreeError::TreeExists(name) => {
+                write!(f, "The merkle tree {} already exists.", name)
+            }
+            MerkleTreeError::UnknownTree(name) => {
+                write!(f, "There is no merkle tree {}.", name)
+            }
+            MerkleTreeError::TreeQuotaExceeded(tenant) => {
+                write!(f, "The tenant {} cannot own more merkle trees.", tenant)
+            }
+            MerkleTreeError::LeafQuotaExceeded(tenant) => {
+                write!(f, "The tenant {} cannot insert more values.", tenant)
+            }
+            MerkleTreeError::RateLimited(tenant) => {
+                write!(f, "The tenant {} is inserting values too fast.", tenant)
+            }
+            MerkleTreeError::InvalidDigestLine(number) => {
+                write!(f, "The line {} is not a valid digest.", number)
+            }
+        }
+    }
+}
+
+impl core::error::Error for MerkleTreeError {}
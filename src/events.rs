@@ -0,0 +1,171 @@
+//! events sent as values are inserted into merkle trees
+//!
+//! a merkle tree built with `insert_events` sends an event for every inserted value,
+//! in insertion order, and for every value replaced afterwards, so that external search indexes
+//! and databases can follow the leaves of the merkle tree without polling it
+
+use crate::store::NodeStore;
+use crate::{Hasher, LeafHash, MerkleTree};
+
+/// what happened to the leaf of an `InsertEvent`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EventKind {
+    /// a new value, or a leaf hash computed beforehand, was appended
+    Insert,
+    /// the value already at this index was replaced, e.g. by `set_value` or `update_all`
+    Update,
+}
+
+/// the insertion of a value, or of a leaf hash computed beforehand, or the update of a value
+#[derive(Clone, PartialEq, Debug)]
+pub struct InsertEvent {
+    pub kind: EventKind,
+    pub value_index: usize,
+    /// the new hash of the leaf
+    pub leaf_hash: LeafHash,
+    /// the external id of the value, if it was inserted with `insert_with_id`
+    pub id: Option<String>,
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// send the event of the insertion of the value at `value_index`
+    /// if the merkle tree was built with `insert_events`
+    ///
+    /// a disconnected receiver only stops the events, not the insertions
+    pub(crate) fn send_insert_event(&self, value_index: usize, id: Option<&str>) {
+        self.send_event(EventKind::Insert, value_index, id);
+    }
+
+    /// send the event of the update of the value at `value_index`, with its new leaf hash,
+    /// if the merkle tree was built with `insert_events`
+    pub(crate) fn send_update_event(&self, value_index: usize) {
+        self.send_event(EventKind::Update, value_index, None);
+    }
+
+    fn send_event(&self, kind: EventKind, value_index: usize, id: Option<&str>) {
+        let Some(sender) = &self.insert_events else {
+            return;
+        };
+        let Some(hash) = self
+            .nodes
+            .get_hash(self.first_leaf_node_index + value_index)
+        else {
+            return;
+        };
+
+        let _ = sender.send(InsertEvent {
+            kind,
+            value_index,
+            leaf_hash: LeafHash(hash),
+            id: id.map(str::to_string),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::{MerkleTreeBuilder, MerkleTreeError};
+
+    #[test]
+    fn insertions_send_ordered_events() {
+        let (sender, receiver) = mpsc::channel();
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .insert_events(sender)
+            .build()
+            .unwrap();
+
+        mt.insert("a").unwrap();
+        mt.insert_with_id("id-b".to_string(), "b").unwrap();
+        // inserting the same id and value again changes nothing
+        mt.insert_with_id("id-b".to_string(), "b").unwrap();
+        mt.insert_hash(LeafHash(b"precomputed".to_vec())).unwrap();
+
+        assert_eq!(
+            vec![
+                InsertEvent {
+                    kind: EventKind::Insert,
+                    value_index: 0,
+                    leaf_hash: LeafHash(b"H(a)".to_vec()),
+                    id: None,
+                },
+                InsertEvent {
+                    kind: EventKind::Insert,
+                    value_index: 1,
+                    leaf_hash: LeafHash(b"H(b)".to_vec()),
+                    id: Some("id-b".to_string()),
+                },
+                InsertEvent {
+                    kind: EventKind::Insert,
+                    value_index: 2,
+                    leaf_hash: LeafHash(b"precomputed".to_vec()),
+                    id: None,
+                },
+            ],
+            receiver.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn disconnected_receiver_does_not_stop_insertions() {
+        let (sender, receiver) = mpsc::channel();
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .insert_events(sender)
+            .build()
+            .unwrap();
+        drop(receiver);
+
+        mt.insert("a").unwrap();
+        mt.insert("b").unwrap();
+        assert_eq!(Err(MerkleTreeError::Full), mt.insert("c"));
+    }
+
+    #[test]
+    fn previews_send_no_events() {
+        let (sender, receiver) = mpsc::channel();
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .insert_events(sender)
+            .build()
+            .unwrap();
+
+        mt.insert("a").unwrap();
+        mt.preview_root(&["x", "y"]).unwrap();
+
+        assert_eq!(1, receiver.try_iter().count());
+    }
+
+    #[test]
+    fn updates_send_events() {
+        let (sender, receiver) = mpsc::channel();
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .insert_events(sender)
+            .build()
+            .unwrap();
+
+        mt.insert("a").unwrap();
+        mt.insert("b").unwrap();
+        mt.set_value(0, "x").unwrap();
+        mt.update_all(&[(1, "y"), (0, "z"), (1, "w")]).unwrap();
+        // a rejected update sends nothing
+        assert!(mt.set_value(2, "c").is_err());
+
+        let update = |value_index, leaf_hash: &[u8]| InsertEvent {
+            kind: EventKind::Update,
+            value_index,
+            leaf_hash: LeafHash(leaf_hash.to_vec()),
+            id: None,
+        };
+        assert_eq!(
+            vec![
+                update(0, b"H(x)"),
+                // the updates of a batch are sent once per leaf, with its final hash
+                update(0, b"H(z)"),
+                update(1, b"H(w)"),
+            ],
+            receiver.try_iter().skip(2).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! a C API over merkle trees of SHA-256 digests, behind opaque handles,
+//! so that C and C++ applications can embed them
+//!
+//! the declarations are in `include/merkle_tree.h`, and the library is built for C with e.g.
+//! `cargo rustc --lib --release --features ffi --crate-type staticlib`
+//!
+//! the functions returning an `int` return 0 on success and -1 on failure,
+//! and all the digests have 32 bytes
+//!
+//! only available with the `ffi` feature
+
+use core::slice;
+
+use crate::head::TreeId;
+use crate::proof::MerkleProof;
+use crate::sha256::Sha256Hasher;
+use crate::{Hasher, LeafHash, MerkleTree, NodeHash, PairOrder};
+use alloc::boxed::Box;
+
+/// the length of the SHA-256 digests read and written by the C API
+const DIGEST_LEN: usize = 32;
+
+/// the merkle tree behind the opaque `merkle_tree` handles of the C API
+pub type MerkleTreeHandle = MerkleTree<Sha256Hasher>;
+
+/// read `len` bytes at `data`, which may be null when `len` is 0
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes if `len` is not 0
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// create a merkle tree of SHA-256 digests of height `height`,
+/// to be freed with `merkle_tree_free`, or return null if the height is invalid
+#[no_mangle]
+pub extern "C" fn merkle_tree_new(height: usize) -> *mut MerkleTreeHandle {
+    match MerkleTree::from_height(Sha256Hasher, height) {
+        Ok(mt) => Box::into_raw(Box::new(mt)),
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+/// free a merkle tree created by `merkle_tree_new`, doing nothing if `tree` is null
+///
+/// # Safety
+///
+/// `tree` must be null or a handle returned by `merkle_tree_new` that was not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn merkle_tree_free(tree: *mut MerkleTreeHandle) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+/// insert the `len` bytes at `value` into the merkle tree
+///
+/// fail if `tree` is null or the merkle tree is full
+///
+/// # Safety
+///
+/// `tree` must be null or a live handle, and `value` must point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn merkle_tree_insert(
+    tree: *mut MerkleTreeHandle,
+    value: *const u8,
+    len: usize,
+) -> i32 {
+    let Some(mt) = tree.as_mut() else {
+        return -1;
+    };
+
+    match mt.insert(bytes(value, len)) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// write the root of the merkle tree into the 32 bytes at `root`,
+/// after updating the internal nodes
+///
+/// fail if `tree` or `root` is null or the root cannot be computed
+///
+/// # Safety
+///
+/// `tree` must be null or a live handle, and `root` must be null or point to 32 writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn merkle_tree_root(tree: *mut MerkleTreeHandle, root: *mut u8) -> i32 {
+    let Some(mt) = tree.as_mut() else {
+        return -1;
+    };
+    if root.is_null() {
+        return -1;
+    }
+
+    match mt.root() {
+        Ok(NodeHash(hash)) if hash.len() == DIGEST_LEN => {
+            slice::from_raw_parts_mut(root, DIGEST_LEN).copy_from_slice(&hash);
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// write the siblings of the proof of the `value_index`'th value, from the leaf up to
+/// the children of the root, into the 32-byte slots at `siblings`, of which there are `capacity`,
+/// after updating the internal nodes, and return their number, which is the height of the merkle tree
+///
+/// fail and return -1 if `tree` or `siblings` is null, if the value was not inserted
+/// or if there are fewer slots than siblings
+///
+/// # Safety
+///
+/// `tree` must be null or a live handle,
+/// and `siblings` must be null or point to `32 * capacity` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn merkle_tree_prove(
+    tree: *mut MerkleTreeHandle,
+    value_index: usize,
+    siblings: *mut u8,
+    capacity: usize,
+) -> isize {
+    let Some(mt) = tree.as_mut() else {
+        return -1;
+    };
+    if siblings.is_null() || mt.update_internal_nodes().is_err() {
+        return -1;
+    }
+
+    // unlike `prove`, the positions where no value was inserted yet have no proof
+    if value_index >= mt.len() {
+        return -1;
+    }
+
+    let Ok(proof) = mt.prove(value_index) else {
+        return -1;
+    };
+    if proof.steps.len() > capacity {
+        return -1;
+    }
+
+    let slots = slice::from_raw_parts_mut(siblings, DIGEST_LEN * proof.steps.len());
+    for (slot, step) in slots.chunks_exact_mut(DIGEST_LEN).zip(&proof.steps) {
+        slot.copy_from_slice(&step.hash);
+    }
+
+    proof.steps.len() as isize
+}
+
+/// check that the `len` bytes at `value` are the `value_index`'th value of the merkle tree
+/// of SHA-256 digests with the 32-byte root at `root`, given the `sibling_count` siblings
+/// at `siblings` written by `merkle_tree_prove`
+///
+/// return false if `root` is null, if `siblings` is null while there are siblings
+/// or if the siblings cannot fit in memory
+///
+/// # Safety
+///
+/// `root` must be null or point to 32 readable bytes, `value` to `len` readable bytes
+/// and `siblings` to `32 * sibling_count` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn merkle_proof_verify(
+    root: *const u8,
+    value: *const u8,
+    len: usize,
+    value_index: usize,
+    siblings: *const u8,
+    sibling_count: usize,
+) -> bool {
+    if root.is_null() || (siblings.is_null() && sibling_count > 0) {
+        return false;
+    }
+    let Some(siblings_len) = DIGEST_LEN.checked_mul(sibling_count) else {
+        return false;
+    };
+
+    let root = NodeHash(bytes(root, DIGEST_LEN).to_vec());
+    let leaf_hash = LeafHash(Sha256Hasher.hash_leaf(bytes(value, len)));
+    let siblings = bytes(siblings, siblings_len)
+        .chunks_exact(DIGEST_LEN)
+        .map(<[u8]>::to_vec);
+
+    // the positions of the siblings are given by the bits of the value index
+    let proof = MerkleProof::from_siblings(TreeId(0), value_index, PairOrder::Positional, siblings);
+
+    proof.verify(&leaf_hash, &root, &Sha256Hasher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prove_and_verify_through_the_c_api() {
+        let tree = merkle_tree_new(2);
+        let mut root = [0; DIGEST_LEN];
+        let mut siblings = [0; 2 * DIGEST_LEN];
+
+        unsafe {
+            for value in ["a", "b", "c"] {
+                assert_eq!(0, merkle_tree_insert(tree, value.as_ptr(), value.len()));
+            }
+            assert_eq!(0, merkle_tree_root(tree, root.as_mut_ptr()));
+            assert_eq!(2, merkle_tree_prove(tree, 1, siblings.as_mut_ptr(), 2));
+
+            assert!(merkle_proof_verify(
+                root.as_ptr(),
+                b"b".as_ptr(),
+                1,
+                1,
+                siblings.as_ptr(),
+                2
+            ));
+            assert!(!merkle_proof_verify(
+                root.as_ptr(),
+                b"c".as_ptr(),
+                1,
+                1,
+                siblings.as_ptr(),
+                2
+            ));
+
+            merkle_tree_free(tree);
+        }
+
+        let mut mt = MerkleTree::from_height(Sha256Hasher, 2).unwrap();
+        for value in ["a", "b", "c"] {
+            mt.insert(value).unwrap();
+        }
+        assert_eq!(mt.root().unwrap().0, root);
+    }
+
+    #[test]
+    fn failures_through_the_c_api() {
+        let tree = merkle_tree_new(1);
+        let mut siblings = [0; DIGEST_LEN];
+
+        unsafe {
+            assert_eq!(0, merkle_tree_insert(tree, core::ptr::null(), 0));
+            assert_eq!(0, merkle_tree_insert(tree, b"b".as_ptr(), 1));
+            // the merkle tree is full, then there are not enough slots
+            assert_eq!(-1, merkle_tree_insert(tree, b"c".as_ptr(), 1));
+            assert_eq!(-1, merkle_tree_prove(tree, 0, siblings.as_mut_ptr(), 0));
+            assert_eq!(1, merkle_tree_prove(tree, 0, siblings.as_mut_ptr(), 1));
+
+            assert_eq!(
+                -1,
+                merkle_tree_insert(core::ptr::null_mut(), b"a".as_ptr(), 1)
+            );
+            assert_eq!(-1, merkle_tree_root(tree, core::ptr::null_mut()));
+            assert!(!merkle_proof_verify(
+                core::ptr::null(),
+                b"a".as_ptr(),
+                1,
+                0,
+                siblings.as_ptr(),
+                1
+            ));
+
+            // the length of the siblings would overflow
+            let root = [0; DIGEST_LEN];
+            assert!(!merkle_proof_verify(
+                root.as_ptr(),
+                b"a".as_ptr(),
+                1,
+                0,
+                siblings.as_ptr(),
+                usize::MAX
+            ));
+
+            merkle_tree_free(tree);
+            merkle_tree_free(core::ptr::null_mut());
+        }
+
+        // no value was inserted at the positions after the last one
+        let tree = merkle_tree_new(2);
+        let mut siblings = [0; 2 * DIGEST_LEN];
+        unsafe {
+            assert_eq!(0, merkle_tree_insert(tree, b"a".as_ptr(), 1));
+            assert_eq!(-1, merkle_tree_prove(tree, 3, siblings.as_mut_ptr(), 2));
+            assert_eq!(-1, merkle_tree_prove(tree, 1, siblings.as_mut_ptr(), 2));
+            assert_eq!(2, merkle_tree_prove(tree, 0, siblings.as_mut_ptr(), 2));
+
+            merkle_tree_free(tree);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! tree heads and their two-phase publication
+//!
+//! a head is first prepared from the current state of the merkle tree,
+//! then either committed once it has been approved externally or aborted,
+//! so the publication of a root can take part in a distributed transaction
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, Hasher as _};
+
+use crate::store::NodeStore;
+use crate::{Hasher, MerkleTree, MerkleTreeError, NodeHash};
+
+/// identify a merkle tree among many others,
+/// so that heads of different trees cannot be mistaken for one another
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TreeId(pub u64);
+
+impl TreeId {
+    /// draw a random tree id
+    #[cfg(feature = "std")]
+    pub fn random() -> Self {
+        // the standard library seeds each `RandomState` with fresh random keys
+        TreeId(RandomState::new().build_hasher().finish())
+    }
+}
+
+impl fmt::Display for TreeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// the state of a merkle tree a root commits to
+#[derive(Clone, PartialEq, Debug)]
+pub struct TreeHead {
+    pub tree_id: TreeId,
+    pub root: NodeHash,
+    /// the number of values inserted in the merkle tree when its root was computed
+    pub length: usize,
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// update the internal nodes and prepare a candidate head for the current state,
+    /// replacing any previously prepared head
+    ///
+    /// fail if the internal nodes cannot be computed
+    pub fn prepare_head(&mut self) -> Result<TreeHead, MerkleTreeError> {
+        self.update_internal_nodes()?;
+
+        let head = TreeHead {
+            tree_id: self.tree_id,
+            root: self.get_root()?,
+            length: self.length,
+        };
+        self.prepared_head = Some(head.clone());
+
+        Ok(head)
+    }
+
+    /// publish the prepared `candidate` head
+    ///
+    /// fail if `candidate` is not the currently prepared head
+    pub fn commit_head(&mut self, candidate: TreeHead) -> Result<(), MerkleTreeError> {
+        if self.prepared_head.as_ref() != Some(&candidate) {
+            return Err(MerkleTreeError::HeadNotPrepared);
+        }
+
+        self.prepared_head = None;
+        self.published_head = Some(candidate);
+
+        Ok(())
+    }
+
+    /// discard the prepared head, if any, leaving the published head untouched
+    pub fn abort_head(&mut self) {
+        self.prepared_head = None;
+    }
+
+    /// get the last committed head, if any
+    pub fn published_head(&self) -> Option<&TreeHead> {
+        self.published_head.as_ref()
+    }
+
+    /// get the id of the merkle tree, included in all its heads
+    pub fn tree_id(&self) -> TreeId {
+        self.tree_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::MerkleTreeBuilder;
+    use alloc::string::ToString;
+
+    #[test]
+    fn prepare_and_commit_head() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .tree_id(TreeId(42))
+            .build()
+            .unwrap();
+        mt.insert("1").unwrap();
+
+        let candidate = mt.prepare_head().unwrap();
+        assert_eq!(
+            TreeHead {
+                tree_id: TreeId(42),
+                root: NodeHash(b"H(H(1)H(empty node))".to_vec()),
+                length: 1,
+            },
+            candidate
+        );
+        assert_eq!(None, mt.published_head());
+
+        // values inserted meanwhile do not affect the prepared head
+        mt.insert("2").unwrap();
+        mt.commit_head(candidate.clone()).unwrap();
+
+        assert_eq!(Some(&candidate), mt.published_head());
+    }
+
+    #[test]
+    fn abort_head_keeps_published_head() {
+        let mut mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+        mt.insert("1").unwrap();
+
+        let published = mt.prepare_head().unwrap();
+        mt.commit_head(published.clone()).unwrap();
+
+        mt.insert("2").unwrap();
+        mt.prepare_head().unwrap();
+        mt.abort_head();
+
+        assert_eq!(Some(&published), mt.published_head());
+    }
+
+    #[test]
+    fn commit_aborted_head() {
+        let mut mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+
+        let candidate = mt.prepare_head().unwrap();
+        mt.abort_head();
+
+        assert_eq!(
+            Err(MerkleTreeError::HeadNotPrepared),
+            mt.commit_head(candidate)
+        );
+    }
+
+    #[test]
+    fn commit_unprepared_head() {
+        let mut mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+        mt.prepare_head().unwrap();
+
+        let forged = TreeHead {
+            tree_id: mt.tree_id(),
+            root: NodeHash(b"H(forged)".to_vec()),
+            length: 0,
+        };
+
+        assert_eq!(
+            Err(MerkleTreeError::HeadNotPrepared),
+            mt.commit_head(forged)
+        );
+        assert_eq!(None, mt.published_head());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn random_tree_ids_differ() {
+        let mt_one = MerkleTree::from_height(basic_hash, 1).unwrap();
+        let mt_two = MerkleTree::from_height(basic_hash, 1).unwrap();
+
+        assert_ne!(mt_one.tree_id(), mt_two.tree_id());
+    }
+
+    #[test]
+    fn tree_id_display() {
+        assert_eq!("000000000000002a", TreeId(42).to_string());
+    }
+}
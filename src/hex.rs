@@ -0,0 +1,59 @@
+//! hexadecimal encoding of hashes, to display them or store them as text
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// encode `bytes` as lowercase hexadecimal digits
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// decode hexadecimal digits, either lowercase or uppercase, into bytes
+///
+/// return `None` if the number of digits is odd or if a character is not a digit
+pub fn decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            let digits = hex.get(i..i + 2)?;
+
+            // `from_str_radix` accepts a leading sign, which is not a digit
+            if digits.starts_with('+') {
+                return None;
+            }
+
+            u8::from_str_radix(digits, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn encode_bytes() {
+        assert_eq!("", encode(&[]));
+        assert_eq!("00ff1a", encode(&[0x00, 0xff, 0x1a]));
+    }
+
+    #[test]
+    fn decode_digits() {
+        assert_eq!(Some(vec![0x00, 0xff, 0x1a]), decode("00FF1a"));
+        assert_eq!(Some(vec![]), decode(""));
+    }
+
+    #[test]
+    fn decode_invalid_digits() {
+        assert_eq!(None, decode("abc"));
+        assert_eq!(None, decode("zz"));
+        assert_eq!(None, decode("+1"));
+        assert_eq!(None, decode("é0"));
+    }
+}
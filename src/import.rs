@@ -0,0 +1,176 @@
+//! import of flat lists of digests produced by other tools, e.g. `sha256sum`,
+//! whose digests are inserted as precomputed leaves like with `insert_hash`
+
+use crate::{hex, Hasher, LeafHash, MerkleTree, MerkleTreeError};
+
+/// the formats of the lists of digests, with one digest per line and blank lines ignored
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DigestFormat {
+    /// the output of `sha256sum` and similar tools, `<digest>  <file>` per line,
+    /// or `<digest> *<file>` for the files read in binary mode
+    Checksums,
+    /// a single column of hexadecimal digests
+    HexColumn,
+}
+
+impl DigestFormat {
+    /// guess the format of the list of digests `text` from its first line that is not blank,
+    /// or return `None` if it is in none of the formats
+    pub fn detect(text: &str) -> Option<Self> {
+        let line = text.lines().find(|line| !line.trim().is_empty())?.trim();
+
+        if hex::decode(line).is_some_and(|digest| !digest.is_empty()) {
+            return Some(DigestFormat::HexColumn);
+        }
+
+        let (digest, file) = line.split_once(char::is_whitespace)?;
+        let digest = digest.strip_prefix('\\').unwrap_or(digest);
+        if hex::decode(digest).is_some_and(|digest| !digest.is_empty()) && !file.trim().is_empty() {
+            return Some(DigestFormat::Checksums);
+        }
+
+        None
+    }
+
+    /// read the digest of a `line` that is not blank
+    fn parse_line(self, line: &str) -> Option<Vec<u8>> {
+        let line = line.trim();
+
+        let digest = match self {
+            DigestFormat::HexColumn => line,
+            DigestFormat::Checksums => {
+                let (digest, file) = line.split_once(char::is_whitespace)?;
+                if file.trim().is_empty() {
+                    return None;
+                }
+
+                // the digests of the files with special characters in their names are prefixed with `\`
+                digest.strip_prefix('\\').unwrap_or(digest)
+            }
+        };
+
+        hex::decode(digest).filter(|digest| !digest.is_empty())
+    }
+}
+
+/// read the digests of the list of digests `text` in the given `format`, in order
+///
+/// fail if a line is not in this format or
+/// if its digest does not have the length of the first one
+pub fn parse_digests(text: &str, format: DigestFormat) -> Result<Vec<LeafHash>, MerkleTreeError> {
+    let mut digests: Vec<LeafHash> = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let digest = format
+            .parse_line(line)
+            .filter(|digest| {
+                digests
+                    .first()
+                    .is_none_or(|first| first.0.len() == digest.len())
+            })
+            .ok_or(MerkleTreeError::InvalidDigestLine(number + 1))?;
+
+        digests.push(LeafHash(digest));
+    }
+
+    Ok(digests)
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// create a merkle tree with the provided `hasher`, just high enough to hold all the `leaf_hashes`,
+    /// insert them with `insert_hash` and compute the internal nodes at once
+    ///
+    /// fail if the leaf hashes cannot be inserted or the internal nodes cannot be computed
+    pub fn from_leaf_hashes(
+        hasher: H,
+        leaf_hashes: impl IntoIterator<Item = LeafHash>,
+    ) -> Result<Self, MerkleTreeError> {
+        let leaf_hashes: Vec<_> = leaf_hashes.into_iter().collect();
+        let height = (leaf_hashes.len().next_power_of_two().ilog2() as usize).max(1);
+
+        let mut mt = MerkleTree::from_height(hasher, height)?;
+        for leaf_hash in leaf_hashes {
+            mt.insert_hash(leaf_hash)?;
+        }
+        mt.rebuild()?;
+
+        Ok(mt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+
+    const CHECKSUMS: &str = "\
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  empty.txt
+ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad *abc.bin
+
+\\248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1  new\\nline.txt
+";
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(
+            Some(DigestFormat::Checksums),
+            DigestFormat::detect(CHECKSUMS)
+        );
+        assert_eq!(
+            Some(DigestFormat::HexColumn),
+            DigestFormat::detect("\n  ABCDEF01\n23456789\n")
+        );
+        assert_eq!(None, DigestFormat::detect("not a digest"));
+        assert_eq!(None, DigestFormat::detect("abc  odd.txt"));
+        assert_eq!(None, DigestFormat::detect(""));
+    }
+
+    #[test]
+    fn parse_checksums() {
+        let digests = parse_digests(CHECKSUMS, DigestFormat::Checksums).unwrap();
+
+        assert_eq!(3, digests.len());
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            hex::encode(&digests[1].0)
+        );
+        assert_eq!(
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            hex::encode(&digests[2].0)
+        );
+    }
+
+    #[test]
+    fn parse_invalid_lines() {
+        // a line without a file, a line that is not hexadecimal, digests of different lengths
+        assert_eq!(
+            Err(MerkleTreeError::InvalidDigestLine(2)),
+            parse_digests("abcd  file\nabcd\n", DigestFormat::Checksums)
+        );
+        assert_eq!(
+            Err(MerkleTreeError::InvalidDigestLine(3)),
+            parse_digests("abcd\n\nxyz\n", DigestFormat::HexColumn)
+        );
+        assert_eq!(
+            Err(MerkleTreeError::InvalidDigestLine(2)),
+            parse_digests("abcd\nabcdef\n", DigestFormat::HexColumn)
+        );
+    }
+
+    #[test]
+    fn from_leaf_hashes_inserts_the_digests() {
+        let digests = parse_digests("3132\n3233\n3334\n", DigestFormat::HexColumn).unwrap();
+        let mt = MerkleTree::from_leaf_hashes(basic_hash, digests).unwrap();
+
+        assert_eq!(2, mt.height());
+        assert_eq!(LeafHash(b"12".to_vec()), mt.get_value(0).unwrap());
+        assert_eq!(
+            b"H(H(1223)H(34H(empty node)))".to_vec(),
+            mt.get_root().unwrap().0
+        );
+    }
+}
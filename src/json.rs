@@ -0,0 +1,375 @@
+//! JSON encoding of inclusion proofs, e.g. to pass them between shell commands
+//!
+//! a proof is encoded as
+//! `{"tree_id":"000000000000002a","value_index":1,"pair_order":"positional",
+//! "steps":[{"position":"left","hash":"<hex>"}]}`,
+//! where the positions may be left out to be derived from the value index,
+//! and only this shape is decoded, so no general-purpose JSON library is needed
+
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::head::TreeId;
+use crate::hex;
+use crate::proof::{Directions, MerkleProof, Position, ProofStep};
+use crate::{MerkleTreeError, PairOrder};
+
+/// the JSON values needed to decode a proof
+#[derive(Clone, PartialEq, Debug)]
+enum Json {
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(HashMap<String, Json>),
+}
+
+/// the deepest nesting of arrays and objects accepted, proofs only nesting objects in an array
+/// in an object, so that untrusted inputs cannot exhaust the stack
+const MAX_DEPTH: usize = 16;
+
+/// a parser of the JSON values needed to decode a proof, ignoring the whitespace between tokens
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// the number of arrays and objects containing the value being parsed
+    depth: usize,
+}
+
+fn invalid(reason: &str) -> MerkleTreeError {
+    MerkleTreeError::InvalidJson(reason.to_string())
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), MerkleTreeError> {
+        self.skip_whitespace();
+
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(invalid(&format!("expected '{}'", expected))),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, MerkleTreeError> {
+        self.skip_whitespace();
+
+        match self.chars.peek() {
+            Some('{' | '[') if self.depth >= MAX_DEPTH => Err(invalid("too deeply nested")),
+            Some('{') => self.nested(Self::parse_object),
+            Some('[') => self.nested(Self::parse_array),
+            Some('"') => self.parse_string().map(Json::String),
+            Some(c) if c.is_ascii_digit() => self.parse_number(),
+            _ => Err(invalid("expected a value")),
+        }
+    }
+
+    /// parse an array or an object with `parse`, one level deeper
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Json, MerkleTreeError>,
+    ) -> Result<Json, MerkleTreeError> {
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+
+        value
+    }
+
+    fn parse_object(&mut self) -> Result<Json, MerkleTreeError> {
+        self.expect('{')?;
+        let mut object = HashMap::new();
+
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&'}').is_some() {
+            return Ok(Json::Object(object));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            object.insert(key, self.parse_value()?);
+
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(object)),
+                _ => return Err(invalid("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, MerkleTreeError> {
+        self.expect('[')?;
+        let mut array = Vec::new();
+
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&']').is_some() {
+            return Ok(Json::Array(array));
+        }
+
+        loop {
+            array.push(self.parse_value()?);
+
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(array)),
+                _ => return Err(invalid("expected ',' or ']'")),
+            }
+        }
+    }
+
+    /// parse a string without escape sequences, which never appear in proofs
+    fn parse_string(&mut self) -> Result<String, MerkleTreeError> {
+        self.expect('"')?;
+        let mut string = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => return Err(invalid("unexpected escape sequence")),
+                Some(c) => string.push(c),
+                None => return Err(invalid("unterminated string")),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Json, MerkleTreeError> {
+        let mut digits = String::new();
+        while let Some(digit) = self.chars.next_if(char::is_ascii_digit) {
+            digits.push(digit);
+        }
+
+        digits
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| invalid("invalid number"))
+    }
+}
+
+/// get the field `key` of a JSON `object`
+fn field<'a>(object: &'a HashMap<String, Json>, key: &str) -> Result<&'a Json, MerkleTreeError> {
+    object
+        .get(key)
+        .ok_or_else(|| invalid(&format!("missing field {}", key)))
+}
+
+fn as_str<'a>(json: &'a Json, key: &str) -> Result<&'a str, MerkleTreeError> {
+    match json {
+        Json::String(string) => Ok(string),
+        _ => Err(invalid(&format!("{} is not a string", key))),
+    }
+}
+
+impl MerkleProof {
+    /// encode the proof in JSON, with the positions of the siblings
+    pub fn to_json(&self) -> String {
+        self.to_json_with(Directions::Explicit)
+    }
+
+    /// encode the proof in JSON, leaving out the positions of the siblings
+    /// if `directions` is `Directions::IndexDerived`
+    pub fn to_json_with(&self, directions: Directions) -> String {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|step| {
+                let hash = hex::encode(&step.hash);
+                let position = match step.position {
+                    Position::Left => "left",
+                    Position::Right => "right",
+                };
+
+                match directions {
+                    Directions::Explicit => {
+                        format!(r#"{{"position":"{}","hash":"{}"}}"#, position, hash)
+                    }
+                    Directions::IndexDerived => format!(r#"{{"hash":"{}"}}"#, hash),
+                }
+            })
+            .collect();
+        let pair_order = match self.pair_order {
+            PairOrder::Positional => "positional",
+            PairOrder::Sorted => "sorted",
+        };
+
+        format!(
+            r#"{{"tree_id":"{}","value_index":{},"pair_order":"{}","steps":[{}]}}"#,
+            self.tree_id,
+            self.value_index,
+            pair_order,
+            steps.join(",")
+        )
+    }
+
+    /// decode a proof encoded in JSON with `to_json` or `to_json_with`,
+    /// deriving the positions of the steps without one from the value index
+    ///
+    /// fail if `json` is not a valid encoding of a proof
+    pub fn from_json(json: &str) -> Result<Self, MerkleTreeError> {
+        let mut parser = Parser {
+            chars: json.chars().peekable(),
+            depth: 0,
+        };
+        let Json::Object(proof) = parser.parse_value()? else {
+            return Err(invalid("a proof is an object"));
+        };
+        parser.skip_whitespace();
+        if parser.chars.next().is_some() {
+            return Err(invalid("unexpected data after the proof"));
+        }
+
+        let tree_id = as_str(field(&proof, "tree_id")?, "tree_id")?;
+        let tree_id = u64::from_str_radix(tree_id, 16)
+            .map(TreeId)
+            .map_err(|_| invalid("invalid tree_id"))?;
+
+        let Json::Number(value_index) = field(&proof, "value_index")? else {
+            return Err(invalid("value_index is not a number"));
+        };
+        let value_index =
+            usize::try_from(*value_index).map_err(|_| invalid("value_index is too large"))?;
+
+        let pair_order = match as_str(field(&proof, "pair_order")?, "pair_order")? {
+            "positional" => PairOrder::Positional,
+            "sorted" => PairOrder::Sorted,
+            _ => return Err(invalid("invalid pair_order")),
+        };
+
+        let Json::Array(steps) = field(&proof, "steps")? else {
+            return Err(invalid("steps is not an array"));
+        };
+        let steps = steps
+            .iter()
+            .enumerate()
+            .map(|(level, step)| {
+                let Json::Object(step) = step else {
+                    return Err(invalid("a step is an object"));
+                };
+                let position = match step.get("position") {
+                    None => Position::of_sibling(value_index, level),
+                    Some(position) => match as_str(position, "position")? {
+                        "left" => Position::Left,
+                        "right" => Position::Right,
+                        _ => return Err(invalid("invalid position")),
+                    },
+                };
+                let hash = hex::decode(as_str(field(step, "hash")?, "hash")?)
+                    .ok_or_else(|| invalid("invalid hash"))?;
+
+                Ok(ProofStep { position, hash })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(MerkleProof {
+            tree_id,
+            value_index,
+            pair_order,
+            steps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proof() -> MerkleProof {
+        MerkleProof {
+            tree_id: TreeId(42),
+            value_index: 1,
+            pair_order: PairOrder::Positional,
+            steps: vec![
+                ProofStep {
+                    position: Position::Left,
+                    hash: vec![0xab, 0xcd],
+                },
+                ProofStep {
+                    position: Position::Right,
+                    hash: vec![0x01],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn to_json() {
+        assert_eq!(
+            r#"{"tree_id":"000000000000002a","value_index":1,"pair_order":"positional","steps":[{"position":"left","hash":"abcd"},{"position":"right","hash":"01"}]}"#,
+            proof().to_json()
+        );
+    }
+
+    #[test]
+    fn from_json_round_trip() {
+        assert_eq!(Ok(proof()), MerkleProof::from_json(&proof().to_json()));
+
+        // the fields may come in any order, separated by whitespace
+        let json = r#"
+            { "steps" : [ ] , "pair_order" : "sorted" ,
+              "value_index" : 3 , "tree_id" : "07" }
+        "#;
+        assert_eq!(
+            Ok(MerkleProof {
+                tree_id: TreeId(7),
+                value_index: 3,
+                pair_order: PairOrder::Sorted,
+                steps: Vec::new(),
+            }),
+            MerkleProof::from_json(json)
+        );
+    }
+
+    #[test]
+    fn index_derived_directions() {
+        let json = proof().to_json_with(Directions::IndexDerived);
+
+        assert_eq!(
+            r#"{"tree_id":"000000000000002a","value_index":1,"pair_order":"positional","steps":[{"hash":"abcd"},{"hash":"01"}]}"#,
+            json
+        );
+        assert_eq!(Ok(proof()), MerkleProof::from_json(&json));
+    }
+
+    #[test]
+    fn from_json_invalid() {
+        assert_eq!(
+            Err(MerkleTreeError::InvalidJson(
+                "missing field steps".to_string()
+            )),
+            MerkleProof::from_json(r#"{"tree_id":"07","value_index":3,"pair_order":"sorted"}"#)
+        );
+        assert_eq!(
+            Err(MerkleTreeError::InvalidJson("invalid hash".to_string())),
+            MerkleProof::from_json(
+                r#"{"tree_id":"07","value_index":3,"pair_order":"sorted","steps":[{"position":"left","hash":"xyz"}]}"#
+            )
+        );
+        assert!(MerkleProof::from_json(r#"{"tree_id":"07""#).is_err());
+    }
+
+    #[test]
+    fn from_json_too_deeply_nested() {
+        let too_deep = "[".repeat(200_000);
+
+        assert_eq!(
+            Err(MerkleTreeError::InvalidJson(
+                "too deeply nested".to_string()
+            )),
+            MerkleProof::from_json(&too_deep)
+        );
+
+        // nesting up to the limit is parsed, and only rejected as it is not a proof
+        let nested = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert_eq!(
+            Err(MerkleTreeError::InvalidJson(
+                "a proof is an object".to_string()
+            )),
+            MerkleProof::from_json(&nested)
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! a ready-to-use Keccak-256 hasher, as used by Ethereum, whose padding differs from SHA3-256
+//!
+//! only available with the `keccak` feature
+
+use crate::{Hash, Hasher, MerkleTree};
+use alloc::string::String;
+
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+
+/// the rotation of each lane, indexed by `x + 5 * y`
+const ROTATIONS: [u32; 25] = [
+    0, 1, 62, 28, 27, 36, 44, 6, 55, 20, 3, 10, 43, 25, 39, 41, 45, 15, 21, 8, 18, 2, 61, 56, 14,
+];
+
+/// the number of bytes absorbed per permutation, for a 256-bit digest
+const RATE: usize = 136;
+
+/// a hasher computing the Keccak-256 digest of the values and
+/// of the concatenation of the children of each node
+///
+/// with `PairOrder::Sorted`, the proofs of a full merkle tree, whose number of values
+/// is a power of two, can be checked by the `MerkleProof` library of OpenZeppelin,
+/// so that they can be verified by EVM contracts, given the Keccak-256 digests of the values
+/// as leaves; otherwise the empty leaves are hashed into the root, where OpenZeppelin's trees
+/// promote the last node of each odd level, so the roots differ
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct KeccakHasher;
+
+impl Hasher for KeccakHasher {
+    fn hash_leaf(&self, value: &[u8]) -> Hash {
+        keccak256(value).to_vec()
+    }
+
+    fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash {
+        keccak256(&[left, right].concat()).to_vec()
+    }
+}
+
+impl FromIterator<String> for MerkleTree<KeccakHasher> {
+    /// build a merkle tree of Keccak-256 digests with `MerkleTree::from_leaves`
+    fn from_iter<I: IntoIterator<Item = String>>(values: I) -> Self {
+        MerkleTree::from_leaves(KeccakHasher, values)
+            .expect("a merkle tree sized for its values can hold them")
+    }
+}
+
+/// compute the Keccak-256 digest of `data`
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut state = [0u64; 25];
+
+    // pad the message with the bits 1, zeros and 1, up to a multiple of the rate
+    let mut message = data.to_vec();
+    message.push(0x01);
+    while !message.len().is_multiple_of(RATE) {
+        message.push(0);
+    }
+    *message.last_mut().expect("the message is padded") |= 0x80;
+
+    for block in message.chunks_exact(RATE) {
+        for (lane, bytes) in state.iter_mut().zip(block.chunks_exact(8)) {
+            *lane ^= u64::from_le_bytes(bytes.try_into().expect("lanes have 8 bytes"));
+        }
+        keccak_f(&mut state);
+    }
+
+    let mut digest = [0; 32];
+    for (bytes, lane) in digest.chunks_exact_mut(8).zip(state) {
+        bytes.copy_from_slice(&lane.to_le_bytes());
+    }
+
+    digest
+}
+
+/// permute the `state` with the 24 rounds of Keccak-f[1600]
+fn keccak_f(state: &mut [u64; 25]) {
+    for round_constant in ROUND_CONSTANTS {
+        // θ: mix each column into its neighbours
+        let columns: [u64; 5] = core::array::from_fn(|x| {
+            state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20]
+        });
+        for x in 0..5 {
+            let mix = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                state[x + 5 * y] ^= mix;
+            }
+        }
+
+        // ρ and π: rotate each lane and move it from (x, y) to (y, 2x + 3y)
+        let mut moved = [0u64; 25];
+        for x in 0..5 {
+            for y in 0..5 {
+                moved[y + 5 * ((2 * x + 3 * y) % 5)] =
+                    state[x + 5 * y].rotate_left(ROTATIONS[x + 5 * y]);
+            }
+        }
+
+        // χ: combine each lane with the next two of its row
+        for y in 0..5 {
+            for x in 0..5 {
+                state[x + 5 * y] =
+                    moved[x + 5 * y] ^ (!moved[(x + 1) % 5 + 5 * y] & moved[(x + 2) % 5 + 5 * y]);
+            }
+        }
+
+        // ι: break the symmetry of the rounds
+        state[0] ^= round_constant;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hex, MerkleTreeBuilder, PairOrder};
+
+    #[test]
+    fn keccak256_test_vectors() {
+        assert_eq!(
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            hex::encode(&keccak256(b""))
+        );
+        assert_eq!(
+            "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45",
+            hex::encode(&keccak256(b"abc"))
+        );
+        // a message filling the rate is padded into a second block
+        assert_ne!(keccak256(&[b'a'; 136]), keccak256(&[b'a'; 135]));
+    }
+
+    #[test]
+    fn keccak_merkle_root_with_sorted_pairs() {
+        let mut mt = MerkleTreeBuilder::new(KeccakHasher, 1)
+            .pair_order(PairOrder::Sorted)
+            .build()
+            .unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let (one, two) = (keccak256(b"1"), keccak256(b"2"));
+        let (first, second) = PairOrder::Sorted.arrange(&one, &two);
+        let expected = keccak256(&[first, second].concat());
+
+        assert_eq!(expected.to_vec(), mt.get_root().unwrap().0);
+    }
+}
@@ -1,134 +1,1044 @@
-pub type Hash = String;
-pub type HashFunction = fn(String) -> Hash;
+//! merkle trees of values inserted one after another, with inclusion and consistency proofs
+//!
+//! the core merkle tree only needs `alloc`: without the default `std` feature,
+//! the crate builds in `no_std` environments, leaving out the timings, metrics, events,
+//! files, threads and the other pieces that need the standard library
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod anchor;
+pub mod batch;
+#[cfg(feature = "sha2")]
+pub mod bitcoin;
+#[cfg(feature = "blake3")]
+pub mod blake3;
+pub mod compact;
+pub mod compare;
+pub mod conformance;
+pub mod consistency;
+#[cfg(feature = "differential")]
+pub mod differential;
+pub mod digests;
+mod error;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod head;
+pub mod hex;
+#[cfg(feature = "std")]
+pub mod import;
+#[cfg(feature = "std")]
+pub mod json;
+#[cfg(feature = "keccak")]
+pub mod keccak;
+#[cfg(feature = "std")]
+pub mod manager;
+pub mod memory;
+#[cfg(feature = "std")]
+pub mod metrics;
+pub mod mmr;
+pub mod multiproof;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+pub mod proof;
+pub mod render;
+pub mod replication;
+#[cfg(feature = "sha2")]
+pub mod sha256;
+#[cfg(feature = "std")]
+pub mod shared;
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod sparse;
+pub mod store;
+pub mod summary;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "std")]
+pub mod timing;
+#[cfg(not(feature = "std"))]
+mod uninstrumented;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::mpsc::Sender;
+
+pub use error::MerkleTreeError;
+#[cfg(feature = "std")]
+use events::InsertEvent;
+use head::{TreeHead, TreeId};
+#[cfg(feature = "std")]
+use metrics::MetricsCollector;
+#[cfg(feature = "std")]
+use snapshot::LoadCheck;
+use store::{MemoryStore, NodeStore};
+#[cfg(feature = "std")]
+use timing::TimingCollector;
+
+/// a digest computed by a hash function, displayed in hexadecimal with `hex::encode`
+pub type Hash = Vec<u8>;
+pub type HashFunction = fn(&[u8]) -> Hash;
+
+/// the greatest height of a merkle tree, so that the indices of all its nodes fit in a `usize`
+pub const MAX_HEIGHT: usize = usize::BITS as usize - 2;
+
+/// a hasher computes the hashes of the leaves and of the internal nodes of a merkle tree
+pub trait Hasher {
+    /// hash the bytes of an inserted value, or the constant or index of an empty leaf
+    fn hash_leaf(&self, value: &[u8]) -> Hash;
+
+    /// hash the hashes of the `left` and `right` children of a node
+    fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash;
+
+    /// hash an empty merkle tree whose empty leaves are forbidden, i.e. no bytes by default,
+    /// which no value hashes to, unlike `hash_leaf(&[])` that is the hash of an empty value
+    fn hash_empty(&self) -> Hash {
+        Hash::new()
+    }
+}
+
+/// any hash function, including closures capturing their configuration, is a hasher
+/// hashing the values as is and the nodes as the concatenation of their children
+impl<F: Fn(&[u8]) -> Hash> Hasher for F {
+    fn hash_leaf(&self, value: &[u8]) -> Hash {
+        self(value)
+    }
+
+    fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash {
+        self(&[left, right].concat())
+    }
+}
+
+/// the tag prefixed to the bytes of a leaf by `DomainSeparated`, as in RFC 6962
+pub const LEAF_TAG: u8 = 0x00;
+/// the tag prefixed to the hashes of the children of a node by `DomainSeparated`, as in RFC 6962
+pub const NODE_TAG: u8 = 0x01;
+
+/// a hasher hashing the leaves prefixed with `LEAF_TAG` and
+/// the concatenation of the children of each node prefixed with `NODE_TAG` with the inner hasher,
+/// so that a value can never be hashed into the hash of an internal node,
+/// which would let a proof of this value forge a proof of a shorter merkle tree
+///
+/// the inner hasher hashes both kinds of inputs with its `hash_leaf`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct DomainSeparated<H>(pub H);
+
+impl<H: Hasher> Hasher for DomainSeparated<H> {
+    fn hash_leaf(&self, value: &[u8]) -> Hash {
+        self.0.hash_leaf(&[&[LEAF_TAG], value].concat())
+    }
+
+    fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash {
+        self.0.hash_leaf(&[&[NODE_TAG], left, right].concat())
+    }
+
+    /// hash no bytes without any tag, as RFC 6962 does for the empty merkle tree,
+    /// which cannot be the hash of a tagged leaf or node
+    fn hash_empty(&self) -> Hash {
+        self.0.hash_leaf(&[])
+    }
+}
+
+/// the hash of a leaf of the merkle tree, i.e. of an inserted value or of an empty leaf
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LeafHash(pub Hash);
+
+/// the hash of an internal node of the merkle tree, such as its root
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NodeHash(pub Hash);
+
+impl From<LeafHash> for Hash {
+    fn from(leaf_hash: LeafHash) -> Self {
+        leaf_hash.0
+    }
+}
+
+impl From<NodeHash> for Hash {
+    fn from(node_hash: NodeHash) -> Self {
+        node_hash.0
+    }
+}
+
+impl fmt::Display for LeafHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+impl fmt::Display for NodeHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+/// a validation function accepts the bytes of a value or rejects them with a reason
+pub type ValidationFunction = fn(&[u8]) -> Result<(), String>;
 
-/// a single node of the merkle tree
+/// what the hash of a leaf is before a value is inserted at its position
 #[derive(Clone, PartialEq, Debug)]
-struct Node {
+pub enum EmptyLeaf {
+    /// the hash of a constant string, `"empty node"` by default
+    Constant(String),
+    /// a fixed hash used as is, e.g. a zero digest
+    Hash(LeafHash),
+    /// the hash of the index of the leaf, so that empty leaves are all distinct
+    Index,
+    /// empty leaves have no hash, so only full merkle trees can be computed
+    Forbidden,
+}
+
+impl Default for EmptyLeaf {
+    fn default() -> Self {
+        EmptyLeaf::Constant("empty node".to_string())
+    }
+}
+
+/// how the hashes of two children are ordered before being hashed into their parent's hash
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PairOrder {
+    /// the left child comes first, so proofs must tell on which side each sibling stands
+    #[default]
+    Positional,
+    /// the smaller hash comes first, as expected by many Solidity verifiers,
+    /// so proofs do not need the positions of the siblings
+    Sorted,
+}
+
+impl PairOrder {
+    /// order the hashes of the `left` and `right` children before hashing them
+    pub fn arrange<'a>(self, left: &'a [u8], right: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        match self {
+            PairOrder::Sorted if right < left => (right, left),
+            _ => (left, right),
+        }
+    }
+}
+
+/// a single node of the merkle tree, as stored in a `MemoryStore`
+#[derive(Clone, PartialEq, Debug)]
+pub struct Node {
     /// a node only contains the hash corresponding to its position in the merkle tree
     hash: Hash,
 }
 
-pub struct MerkleTree {
-    hash_function: HashFunction,
+#[derive(Clone)]
+pub struct MerkleTree<H = HashFunction, S = MemoryStore> {
+    hasher: H,
     height: usize,
     /// the `length` represents the number of elements inserted in the merkle tree
     length: usize,
     /// `first_leaf_node_index` corresponds to
     /// the index of the first inserted element in the merkle tree
     first_leaf_node_index: usize,
-    /// the merkle tree is implemented as a linear array of `Option<Node>`,
+    /// the merkle tree is implemented as a linear array of nodes in a `NodeStore`,
     /// where the nodes are sorted in a breadth first fashion
     /// `nodes[0]` is always `None`
     /// `nodes[1]` is either `None` or `Some(node)`, where `node` is the root of the merkle tree
     /// the following nodes up to `first_leaf_node_index` correspond to
     /// the internal nodes of the merkle tree followed by
     /// the external nodes, which correspond to the hash of the inserted values
-    nodes: Vec<Option<Node>>,
+    nodes: S,
+    /// in `strict` mode, reading a leaf slot that was never inserted is an error
+    /// instead of silently returning the "empty node" hash
+    strict: bool,
+    /// the `validation_function` runs on every value before it is hashed and inserted
+    validation_function: Option<ValidationFunction>,
+    /// `ids` maps the external ids of the values inserted with `insert_with_id` to their index
+    ids: BTreeMap<String, usize>,
+    /// the bytes held for the `ids`, kept up to date so that the memory budget is checked
+    /// without visiting every id
+    ids_memory: usize,
+    /// `empty_leaf` defines the hash of the leaves where no value was inserted yet
+    empty_leaf: EmptyLeaf,
+    /// `empty_hashes[level]` is the hash of an empty subtree whose root is `level` levels
+    /// above the leaves, precomputed unless it depends on the position of the subtree,
+    /// so that the empty subtrees are never stored nor hashed again
+    empty_hashes: Vec<Hash>,
+    /// `pair_order` defines how the hashes of two children are ordered to hash their parent
+    pair_order: PairOrder,
+    /// with `bind_index`, the index of each value is hashed along with the value
+    bind_index: bool,
+    /// `tree_id` identifies the merkle tree in its heads
+    tree_id: TreeId,
+    /// the head waiting for approval before it is published
+    prepared_head: Option<TreeHead>,
+    /// the last head committed with `commit_head`
+    published_head: Option<TreeHead>,
+    /// the maximum number of bytes the merkle tree may hold, see `memory_usage`
+    memory_budget: Option<usize>,
+    #[cfg(feature = "std")]
+    /// the durations of the operations, only recorded when built with `collect_timings`
+    timings: Option<TimingCollector>,
+    #[cfg(feature = "std")]
+    /// the counters exported as metrics, only recorded when built with `collect_metrics`
+    metrics: Option<MetricsCollector>,
+    #[cfg(feature = "std")]
+    /// where the events of the insertions and updates are sent, only when built with `insert_events`
+    insert_events: Option<Sender<InsertEvent>>,
+    /// the indices of the leaves changed since the last update of the internal nodes,
+    /// whose ancestors are the only internal nodes to recompute
+    dirty_leaves: Vec<usize>,
+    /// whether any internal node may need to be recomputed, e.g. right after leaves were restored,
+    /// in which case `dirty_leaves` is not enough to find them
+    fully_stale: bool,
+}
+
+/// collect the construction options of a merkle tree before building it
+pub struct MerkleTreeBuilder<H = HashFunction, S = MemoryStore> {
+    hasher: H,
+    nodes: S,
+    height: usize,
+    strict: bool,
+    validation_function: Option<ValidationFunction>,
+    empty_leaf: EmptyLeaf,
+    pair_order: PairOrder,
+    bind_index: bool,
+    tree_id: Option<TreeId>,
+    memory_budget: Option<usize>,
+    #[cfg(feature = "std")]
+    collect_timings: bool,
+    #[cfg(feature = "std")]
+    collect_metrics: bool,
+    #[cfg(feature = "std")]
+    load_check: Option<LoadCheck>,
+    #[cfg(feature = "std")]
+    insert_events: Option<Sender<InsertEvent>>,
 }
 
-impl MerkleTree {
-    /// create an empty merkle tree with the provided `hash_function` and `height`
+impl<H: Hasher> MerkleTreeBuilder<H> {
+    /// start building a merkle tree with the provided `hasher` and `height`,
+    /// whose nodes are kept in memory
+    pub fn new(hasher: H, height: usize) -> Self {
+        MerkleTreeBuilder {
+            hasher,
+            nodes: MemoryStore::default(),
+            height,
+            strict: false,
+            validation_function: None,
+            empty_leaf: EmptyLeaf::default(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            collect_timings: false,
+            #[cfg(feature = "std")]
+            collect_metrics: false,
+            #[cfg(feature = "std")]
+            load_check: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+        }
+    }
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTreeBuilder<H, S> {
+    /// keep the nodes in `store` instead of in memory,
+    /// discarding the nodes it contains when the merkle tree is built
+    pub fn node_store<T: NodeStore>(self, store: T) -> MerkleTreeBuilder<H, T> {
+        MerkleTreeBuilder {
+            hasher: self.hasher,
+            nodes: store,
+            height: self.height,
+            strict: self.strict,
+            validation_function: self.validation_function,
+            empty_leaf: self.empty_leaf,
+            pair_order: self.pair_order,
+            bind_index: self.bind_index,
+            tree_id: self.tree_id,
+            memory_budget: self.memory_budget,
+            #[cfg(feature = "std")]
+            collect_timings: self.collect_timings,
+            #[cfg(feature = "std")]
+            collect_metrics: self.collect_metrics,
+            #[cfg(feature = "std")]
+            load_check: self.load_check,
+            #[cfg(feature = "std")]
+            insert_events: self.insert_events,
+        }
+    }
+
+    /// forbid reading leaf slots that were never inserted
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// reject values that do not pass the `validation_function` before they are inserted
+    pub fn validator(mut self, validation_function: ValidationFunction) -> Self {
+        self.validation_function = Some(validation_function);
+        self
+    }
+
+    /// define the hash of the leaves where no value was inserted yet
+    pub fn empty_leaf(mut self, empty_leaf: EmptyLeaf) -> Self {
+        self.empty_leaf = empty_leaf;
+        self
+    }
+
+    /// define how the hashes of two children are ordered to hash their parent
+    pub fn pair_order(mut self, pair_order: PairOrder) -> Self {
+        self.pair_order = pair_order;
+        self
+    }
+
+    /// hash the index of each value along with the value,
+    /// so that identical values at different positions have distinct leaves
+    /// and a proof cannot be reused for another position, see `MerkleTree::hash_value`
+    pub fn bind_index(mut self, bind_index: bool) -> Self {
+        self.bind_index = bind_index;
+        self
+    }
+
+    /// identify the merkle tree with `tree_id` instead of a random id,
+    /// or of the id 0 without the `std` feature
+    pub fn tree_id(mut self, tree_id: TreeId) -> Self {
+        self.tree_id = Some(tree_id);
+        self
+    }
+
+    /// forbid the merkle tree from holding more than `memory_budget` bytes
+    pub fn memory_budget(mut self, memory_budget: usize) -> Self {
+        self.memory_budget = Some(memory_budget);
+        self
+    }
+
+    /// record the durations of the hashing and traversals, see `timings`
+    #[cfg(feature = "std")]
+    pub fn collect_timings(mut self, collect_timings: bool) -> Self {
+        self.collect_timings = collect_timings;
+        self
+    }
+
+    /// count the served proofs and remember when the root was last computed, see `metrics`
+    #[cfg(feature = "std")]
+    pub fn collect_metrics(mut self, collect_metrics: bool) -> Self {
+        self.collect_metrics = collect_metrics;
+        self
+    }
+
+    /// check the internal nodes imported with `import_nodes` against the leaves
+    /// before the merkle tree is returned, as defined by `load_check`
+    #[cfg(feature = "std")]
+    pub fn verify_on_load(mut self, load_check: LoadCheck) -> Self {
+        self.load_check = Some(load_check);
+        self
+    }
+
+    /// send an event to `sender` for every value inserted, in insertion order,
+    /// and for every value replaced afterwards
+    #[cfg(feature = "std")]
+    pub fn insert_events(mut self, sender: Sender<InsertEvent>) -> Self {
+        self.insert_events = Some(sender);
+        self
+    }
+
+    /// create an empty merkle tree from the collected options
     ///
-    /// panic if the height is less or equal to 0 or greater than 10
-    pub fn from_height(hash_function: HashFunction, height: usize) -> Self {
-        if height <= 0 || height > 10 {
-            panic!(
-                "The height of the merkle tree cannot be less or equal to 0 or greater than 10."
-            );
+    /// fail if the height is less or equal to 0 or greater than `MAX_HEIGHT` or
+    /// if the empty merkle tree already exceeds the memory budget
+    pub fn build(self) -> Result<MerkleTree<H, S>, MerkleTreeError> {
+        self.build_or_return_store().map_err(|(error, _)| error)
+    }
+
+    /// build the merkle tree like `build`, but hand back the node store along with the error,
+    /// so that a store taken from a pool can be returned to it
+    pub(crate) fn build_or_return_store(
+        mut self,
+    ) -> Result<MerkleTree<H, S>, (MerkleTreeError, S)> {
+        if self.height == 0 || self.height > MAX_HEIGHT {
+            return Err((MerkleTreeError::InvalidHeight(self.height), self.nodes));
         }
 
-        MerkleTree {
-            hash_function,
-            height,
+        // we need 1 empty slot for the first `None` + 1 + 2 + ... 2^height to store the merkle tree
+        self.nodes
+            .reset(MerkleTree::<H, S>::sum_of_powers_of_two(self.height) + 1);
+
+        let empty_hashes = MerkleTree::<H, S>::empty_subtree_hashes(
+            &self.hasher,
+            self.height,
+            &self.empty_leaf,
+            self.pair_order,
+        );
+
+        let mt = MerkleTree {
+            hasher: self.hasher,
+            height: self.height,
             length: 0,
             // internal nodes are stored from 1 to 1 + 2 + ... + 2^(height - 1) + 1
-            first_leaf_node_index: Self::sum_of_powers_of_two(height - 1) + 1,
-            // we need 1 empty slot for the first `None` + 1 + 2 + ... 2^height to store the merkle tree
-            nodes: vec![None; Self::sum_of_powers_of_two(height) + 1],
+            first_leaf_node_index: MerkleTree::<H, S>::sum_of_powers_of_two(self.height - 1) + 1,
+            nodes: self.nodes,
+            strict: self.strict,
+            validation_function: self.validation_function,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: self.empty_leaf,
+            empty_hashes,
+            pair_order: self.pair_order,
+            bind_index: self.bind_index,
+            #[cfg(feature = "std")]
+            tree_id: self.tree_id.unwrap_or_else(TreeId::random),
+            // without the standard library, there is no source of randomness
+            #[cfg(not(feature = "std"))]
+            tree_id: self.tree_id.unwrap_or(TreeId(0)),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: self.memory_budget,
+            #[cfg(feature = "std")]
+            timings: self.collect_timings.then(TimingCollector::default),
+            #[cfg(feature = "std")]
+            metrics: self.collect_metrics.then(MetricsCollector::default),
+            #[cfg(feature = "std")]
+            insert_events: self.insert_events,
+            dirty_leaves: Vec::new(),
+            // an empty merkle tree has no internal node to compute, so it can be proven at once
+            fully_stale: false,
+        };
+        if let Err(error) = mt.reserve_memory(0) {
+            return Err((error, mt.nodes));
+        }
+
+        Ok(mt)
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// create an empty merkle tree with the provided `hasher` and `height`,
+    /// whose nodes are kept in memory
+    ///
+    /// fail if the height is less or equal to 0 or greater than `MAX_HEIGHT`
+    pub fn from_height(hasher: H, height: usize) -> Result<Self, MerkleTreeError> {
+        MerkleTreeBuilder::new(hasher, height).build()
+    }
+
+    /// create a merkle tree with the provided `hasher`, just high enough to hold all the `values`,
+    /// insert them and compute the internal nodes at once
+    ///
+    /// fail if the values cannot be inserted or the internal nodes cannot be computed
+    pub fn from_leaves(
+        hasher: H,
+        values: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<Self, MerkleTreeError> {
+        let mut mt = MerkleTree::with_leaves(hasher, values)?;
+        mt.rebuild()?;
+
+        Ok(mt)
+    }
+
+    /// create a merkle tree with the provided `hasher`, just high enough to hold all the `values`,
+    /// and insert them without computing the internal nodes,
+    /// so that every constructor from leaves picks the same height
+    ///
+    /// fail if the values cannot be inserted
+    pub(crate) fn with_leaves(
+        hasher: H,
+        values: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<Self, MerkleTreeError> {
+        let values: Vec<_> = values.into_iter().collect();
+        let height = (values.len().next_power_of_two().ilog2() as usize).max(1);
+
+        let mut mt = MerkleTree::from_height(hasher, height)?;
+        for value in values {
+            mt.insert(value)?;
         }
+
+        Ok(mt)
     }
+}
 
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
     /// insert a new value into the merkle tree and
     /// set to `None` all the corresponding parents up to the root
     ///
-    /// panic if the merkle tree is already full
-    pub fn insert(&mut self, value: String) {
+    /// fail if the merkle tree is already full,
+    /// if the value is rejected by the validation function or
+    /// if its hash would exceed the memory budget
+    pub fn insert(&mut self, value: impl AsRef<[u8]>) -> Result<(), MerkleTreeError> {
+        self.insert_reserving(value.as_ref(), 0)?;
+        self.send_insert_event(self.length - 1, None);
+
+        Ok(())
+    }
+
+    /// insert a new value like `insert`,
+    /// checking that `reserved` more bytes also fit in the memory budget
+    fn insert_reserving(&mut self, value: &[u8], reserved: usize) -> Result<(), MerkleTreeError> {
+        if self.first_leaf_node_index + self.length >= self.nodes.node_count() {
+            return Err(MerkleTreeError::Full);
+        }
+
+        if let Some(validation_function) = self.validation_function {
+            validation_function(value).map_err(MerkleTreeError::Rejected)?;
+        }
+
+        let hash = self.hash_value(self.length, value).0;
+
+        self.insert_leaf(hash, reserved)
+    }
+
+    /// insert a leaf hash computed beforehand, e.g. by a system hashing its records itself,
+    /// which can be mixed with values inserted with `insert`
+    ///
+    /// the leaf hash is inserted as is, so neither the validation function
+    /// nor the binding of the index apply to it
+    ///
+    /// fail if the merkle tree is already full or
+    /// if the leaf hash would exceed the memory budget
+    pub fn insert_hash(&mut self, leaf_hash: LeafHash) -> Result<(), MerkleTreeError> {
+        self.insert_leaf(leaf_hash.0, 0)?;
+        self.send_insert_event(self.length - 1, None);
+
+        Ok(())
+    }
+
+    /// insert the leaf `hash` after the last inserted one and
+    /// set to `None` all the corresponding parents up to the root,
+    /// checking that `reserved` more bytes also fit in the memory budget
+    fn insert_leaf(&mut self, hash: Hash, reserved: usize) -> Result<(), MerkleTreeError> {
         let next_leaf_node_index = self.first_leaf_node_index + self.length;
 
-        if next_leaf_node_index >= self.nodes.len() {
-            panic!("The merkle tree is already full.")
+        if next_leaf_node_index >= self.nodes.node_count() {
+            return Err(MerkleTreeError::Full);
         }
 
-        let hash = (self.hash_function)(value);
+        self.reserve_memory(hash.capacity() + reserved)?;
 
-        self.nodes[next_leaf_node_index] = Some(Node { hash });
+        self.nodes.set_hash(next_leaf_node_index, Some(hash));
         self.length += 1;
+        self.dirty_leaves.push(next_leaf_node_index);
+        self.invalidate_ancestors(next_leaf_node_index);
+
+        Ok(())
+    }
+
+    /// replace the value at `value_index`, which was already inserted, and
+    /// set to `None` all the corresponding parents up to the root,
+    /// so that the merkle tree can follow a mutable dataset
+    ///
+    /// an id of `insert_with_id` at this index now identifies the new value
+    ///
+    /// fail if the value index is out of bounds or was never inserted,
+    /// if the value is rejected by the validation function or
+    /// if its hash would exceed the memory budget
+    pub fn set_value(
+        &mut self,
+        value_index: usize,
+        value: impl AsRef<[u8]>,
+    ) -> Result<(), MerkleTreeError> {
+        if value_index >= self.nodes.node_count() - self.first_leaf_node_index {
+            return Err(MerkleTreeError::ValueOutOfBounds(value_index));
+        }
 
+        if value_index >= self.length {
+            return Err(MerkleTreeError::NeverInserted(value_index));
+        }
+
+        if let Some(validation_function) = self.validation_function {
+            validation_function(value.as_ref()).map_err(MerkleTreeError::Rejected)?;
+        }
+
+        let index = self.first_leaf_node_index + value_index;
+        let hash = self.hash_value(value_index, value).0;
+        let replaced = self.nodes.get_hash(index).map_or(0, |hash| hash.capacity());
+        self.reserve_memory(hash.capacity().saturating_sub(replaced))?;
+
+        self.nodes.set_hash(index, Some(hash));
+        self.dirty_leaves.push(index);
+        self.invalidate_ancestors(index);
+        self.send_update_event(value_index);
+
+        Ok(())
+    }
+
+    /// set to `None` all the parents of the node at `index` up to the root
+    fn invalidate_ancestors(&mut self, index: usize) {
         // find parents up to the root
-        // by iteratively performing an integer division of the index by 2 and
-        // set them to `None`
-        let mut i = next_leaf_node_index;
-        for _ in 0..self.height {
+        // by iteratively performing an integer division of the index by 2
+        let mut i = index;
+        while i > 1 {
             i /= 2;
-            self.nodes[i] = None;
+            self.nodes.set_hash(i, None);
+        }
+    }
+
+    /// insert a new value identified by an external `id` and return its index,
+    /// unless the same `id` and value were already inserted,
+    /// in which case the merkle tree is left untouched and the existing index is returned
+    ///
+    /// fail if the `id` was already inserted with another value or
+    /// if the value cannot be inserted
+    pub fn insert_with_id(
+        &mut self,
+        id: String,
+        value: impl AsRef<[u8]>,
+    ) -> Result<usize, MerkleTreeError> {
+        let value = value.as_ref();
+
+        if let Some(&value_index) = self.ids.get(&id) {
+            if self.get_value(value_index)? != self.hash_value(value_index, value) {
+                return Err(MerkleTreeError::IdConflict(id));
+            }
+
+            return Ok(value_index);
         }
+
+        let value_index = self.length;
+        self.insert_reserving(value, Self::id_memory_usage(&id))?;
+        self.send_insert_event(value_index, Some(&id));
+        self.ids_memory += Self::id_memory_usage(&id);
+        self.ids.insert(id, value_index);
+
+        Ok(value_index)
     }
 
     /// update the state of the internal nodes
-    /// by computing iteratively from the last internal node to the root
-    pub fn update_internal_nodes(&mut self) {
-        for i in (1..self.first_leaf_node_index).rev() {
+    /// by computing them level by level from the lowest one up to the root
+    ///
+    /// only the ancestors of the leaves changed since the last update are visited,
+    /// so appending a value recomputes O(height) nodes
+    ///
+    /// fail if an empty leaf is needed while empty leaves are forbidden or
+    /// if the computed nodes would exceed the memory budget
+    pub fn update_internal_nodes(&mut self) -> Result<(), MerkleTreeError> {
+        let traversal = self.start_traversal();
+
+        let indices = if self.fully_stale {
+            Self::populated_internal_node_indices(self.height, self.length).collect()
+        } else {
+            Self::ancestor_indices(self.height, &self.dirty_leaves)
+        };
+
+        for i in indices {
             // compute only nodes set to `None`
-            if self.nodes[i].is_none() {
-                let left_child_index = 2 * i;
+            if !self.nodes.has_hash(i) {
+                let hash = self.compute_node_hash(i)?;
+                self.reserve_memory(hash.capacity())?;
 
-                let left_child_hash = self.get_node_hash(left_child_index);
-                let right_child_hash = self.get_node_hash(left_child_index + 1);
+                self.nodes.set_hash(i, Some(hash));
+            }
+        }
+        // on failure, the changed leaves stay dirty so that the next update retries them
+        self.dirty_leaves.clear();
+        self.fully_stale = false;
+        self.finish_traversal(traversal);
+        self.record_root_update();
 
-                // the hash of a node is the hash of the concatenation of its children's hashes
-                let hash =
-                    (self.hash_function)(format!("{} | {}", left_child_hash, right_child_hash));
+        Ok(())
+    }
 
-                self.nodes[i] = Some(Node { hash });
-            }
+    /// recompute all the internal nodes from the leaves,
+    /// level by level from the lowest one up to the root
+    ///
+    /// unlike `update_internal_nodes`, every internal node above an inserted value is recomputed,
+    /// which avoids checking each of them after massive changes to the leaves
+    ///
+    /// fail if an empty leaf is needed while empty leaves are forbidden or
+    /// if the computed nodes would exceed the memory budget
+    pub fn rebuild(&mut self) -> Result<(), MerkleTreeError> {
+        let traversal = self.start_traversal();
+
+        for i in Self::populated_internal_node_indices(self.height, self.length) {
+            let hash = self.compute_node_hash(i)?;
+            let replaced = self.nodes.get_hash(i).map_or(0, |hash| hash.capacity());
+            self.reserve_memory(hash.capacity().saturating_sub(replaced))?;
+
+            self.nodes.set_hash(i, Some(hash));
         }
+        self.dirty_leaves.clear();
+        self.fully_stale = false;
+        self.finish_traversal(traversal);
+        self.record_root_update();
+
+        Ok(())
     }
 
-    /// get the root hash
+    /// compute the root the merkle tree would have if the `pending` values were inserted,
+    /// without modifying the merkle tree
     ///
-    /// panic if the root is `None`
-    pub fn get_root(&self) -> Hash {
-        self.get_node_hash(1)
+    /// fail if the pending values cannot be inserted or the root cannot be computed
+    pub fn preview_root(&self, pending: &[impl AsRef<[u8]>]) -> Result<NodeHash, MerkleTreeError>
+    where
+        H: Clone,
+        S: Clone,
+    {
+        let mut preview = self.clone();
+        // the preview is a dry run, so it neither announces its values nor measures itself
+        #[cfg(feature = "std")]
+        {
+            preview.insert_events = None;
+            preview.metrics = None;
+            preview.timings = None;
+        }
+
+        for value in pending {
+            preview.insert(value)?;
+        }
+        preview.update_internal_nodes()?;
+
+        preview.get_root()
+    }
+
+    /// compute the leaf hash of `value` at position `value_index`,
+    /// which binds the index into the hash when the merkle tree was built with `bind_index`,
+    /// i.e. hashes the index as 8 big endian bytes followed by the value
+    pub fn hash_value(&self, value_index: usize, value: impl AsRef<[u8]>) -> LeafHash {
+        if self.bind_index {
+            let index = (value_index as u64).to_be_bytes();
+
+            return LeafHash(self.hash_leaf(&[&index, value.as_ref()].concat()));
+        }
+
+        LeafHash(self.hash_leaf(value.as_ref()))
+    }
+
+    /// get the root hash, which is the `empty_root` as long as no value is inserted
+    ///
+    /// fail if the root is `None`, i.e. not computed with `update_internal_nodes`, see `root`
+    pub fn get_root(&self) -> Result<NodeHash, MerkleTreeError> {
+        if self.length == 0 {
+            return Ok(self.empty_root());
+        }
+
+        self.get_node_hash(1).map(NodeHash)
+    }
+
+    /// get the root hash like `get_root`, first recomputing the stale internal nodes
+    /// with `update_internal_nodes` if any
+    ///
+    /// fail if the internal nodes cannot be updated
+    pub fn root(&mut self) -> Result<NodeHash, MerkleTreeError> {
+        self.update_internal_nodes()?;
+
+        self.get_root()
+    }
+
+    /// get the root of the merkle tree before any value is inserted, which only depends on
+    /// its configuration: the root of a merkle tree of empty leaves or,
+    /// when empty leaves are forbidden, the hash of an empty merkle tree of the hasher,
+    /// see `Hasher::hash_empty`
+    pub fn empty_root(&self) -> NodeHash {
+        match self.get_empty_subtree_hash(1) {
+            Ok(hash) => NodeHash(hash),
+            Err(_) => NodeHash(self.hasher.hash_empty()),
+        }
     }
 
     /// get the node hash corresponding to the `value_index`'th value inserted into the merkle tree
     ///
-    /// panic if the node is out of bounds
-    pub fn get_value(&self, value_index: usize) -> Hash {
+    /// fail if the node is out of bounds,
+    /// if the leaf is empty and empty leaves are forbidden or,
+    /// in `strict` mode, if no value was inserted at `value_index` yet
+    pub fn get_value(&self, value_index: usize) -> Result<LeafHash, MerkleTreeError> {
+        if value_index >= self.nodes.node_count() - self.first_leaf_node_index {
+            return Err(MerkleTreeError::ValueOutOfBounds(value_index));
+        }
+
+        if self.strict && value_index >= self.length {
+            return Err(MerkleTreeError::NeverInserted(value_index));
+        }
+
         let index = self.first_leaf_node_index + value_index;
 
-        self.get_node_hash(index)
+        self.get_node_hash(index).map(LeafHash)
+    }
+
+    /// iterate over the `(value_index, leaf_hash)` pairs of the inserted values, in insertion order
+    pub fn leaves(&self) -> impl Iterator<Item = (usize, LeafHash)> + '_ {
+        (0..self.length).filter_map(|value_index| {
+            let hash = self
+                .nodes
+                .get_hash(self.first_leaf_node_index + value_index)?;
+
+            Some((value_index, LeafHash(hash)))
+        })
+    }
+
+    /// the number of values inserted
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// the number of values the merkle tree can hold, which is its number of leaves
+    pub fn capacity(&self) -> usize {
+        self.first_leaf_node_index
+    }
+
+    /// the number of values that can still be inserted before the merkle tree is full
+    pub fn remaining_capacity(&self) -> usize {
+        self.capacity() - self.length
+    }
+
+    /// the number of levels below the root
+    pub fn height(&self) -> usize {
+        self.height
     }
 
     /// get a node hash corresponding to its position in the merkle tree
     ///
-    /// panic if the node is out of bounds or
-    /// if the node is internal and `None`, i.e. not computed with `update_internal_nodes`
-    fn get_node_hash(&self, index: usize) -> Hash {
-        if index >= self.nodes.len() {
-            panic!("This node is out of bounds.");
-        }
-
-        match &self.nodes[index] {
-            Some(node) => node.hash.clone(),
-            // if the node is external and was not inserted yet, it is replaced by "empty node" hash
-            None if index >= self.first_leaf_node_index => {
-                (self.hash_function)("empty node".to_string())
+    /// fail if the node is out of bounds,
+    /// if the node is internal and `None`, i.e. not computed with `update_internal_nodes`, or
+    /// if the node is an empty leaf and empty leaves are forbidden
+    fn get_node_hash(&self, index: usize) -> Result<Hash, MerkleTreeError> {
+        if index == 0 || index >= self.nodes.node_count() {
+            return Err(MerkleTreeError::NodeOutOfBounds(index));
+        }
+
+        match self.nodes.get_hash(index) {
+            Some(hash) => Ok(hash),
+            // if the node is external and was not inserted yet, it is replaced by the empty leaf hash
+            None if index >= self.first_leaf_node_index => self.get_empty_subtree_hash(index),
+            // internal nodes without any value below them are not stored once the nodes are updated
+            None if !self.fully_stale && self.is_empty_subtree(index) => {
+                self.get_empty_subtree_hash(index)
             }
-            _ => panic!("Internal nodes cannot be None."),
+            None => Err(MerkleTreeError::StaleInternalNode(index)),
+        }
+    }
+
+    /// check whether no value was inserted below the node at `index`
+    fn is_empty_subtree(&self, index: usize) -> bool {
+        let level = index.ilog2() as usize;
+
+        // the first leaf below a node is found by going down its left children
+        index << (self.height - level) >= self.first_leaf_node_index + self.length
+    }
+
+    /// compute the hash of the node at `index` as if no value was inserted below it
+    ///
+    /// fail if empty leaves are forbidden
+    fn get_empty_subtree_hash(&self, index: usize) -> Result<Hash, MerkleTreeError> {
+        let level = self.height - index.ilog2() as usize;
+        if let Some(hash) = self.empty_hashes.get(level) {
+            return Ok(hash.clone());
+        }
+
+        if index >= self.first_leaf_node_index {
+            return self.get_empty_leaf_hash(index - self.first_leaf_node_index);
+        }
+
+        let left_child_hash = self.get_empty_subtree_hash(2 * index)?;
+        // both halves of an empty subtree are identical unless the empty leaves depend on their index
+        let right_child_hash = match self.empty_leaf {
+            EmptyLeaf::Index => self.get_empty_subtree_hash(2 * index + 1)?,
+            _ => left_child_hash.clone(),
+        };
+
+        let (first, second) = self.pair_order.arrange(&left_child_hash, &right_child_hash);
+
+        Ok(self.hash_nodes(first, second))
+    }
+
+    /// compute the hashes of the empty subtrees of every level with `hasher`, from the leaves up,
+    /// unless they depend on their position, i.e. the empty leaves hash their index,
+    /// or empty leaves are forbidden
+    fn empty_subtree_hashes(
+        hasher: &H,
+        height: usize,
+        empty_leaf: &EmptyLeaf,
+        pair_order: PairOrder,
+    ) -> Vec<Hash> {
+        let empty_leaf_hash = match empty_leaf {
+            EmptyLeaf::Constant(constant) => hasher.hash_leaf(constant.as_bytes()),
+            EmptyLeaf::Hash(leaf_hash) => leaf_hash.0.clone(),
+            EmptyLeaf::Index | EmptyLeaf::Forbidden => return Vec::new(),
+        };
+
+        let mut hashes = Vec::with_capacity(height + 1);
+        hashes.push(empty_leaf_hash);
+        for level in 0..height {
+            let (first, second) = pair_order.arrange(&hashes[level], &hashes[level]);
+            hashes.push(hasher.hash_nodes(first, second));
+        }
+
+        hashes
+    }
+
+    /// get the hash of the empty leaf at position `value_index`
+    ///
+    /// fail if empty leaves are forbidden
+    fn get_empty_leaf_hash(&self, value_index: usize) -> Result<Hash, MerkleTreeError> {
+        match &self.empty_leaf {
+            EmptyLeaf::Constant(constant) => Ok(self.hash_leaf(constant.as_bytes())),
+            EmptyLeaf::Hash(leaf_hash) => Ok(leaf_hash.0.clone()),
+            EmptyLeaf::Index => Ok(self.hash_leaf(value_index.to_string().as_bytes())),
+            EmptyLeaf::Forbidden => Err(MerkleTreeError::EmptyLeafForbidden(value_index)),
         }
     }
 
+    /// compute the hash of the internal node at `index` from the hashes of its children
+    ///
+    /// fail if a child is internal and `None` or an empty leaf that is forbidden
+    fn compute_node_hash(&self, index: usize) -> Result<Hash, MerkleTreeError> {
+        let left_child_index = 2 * index;
+
+        let left_child_hash = self.get_child_hash(left_child_index)?;
+        let right_child_hash = self.get_child_hash(left_child_index + 1)?;
+
+        // the hash of a node combines the hashes of its children
+        let (first, second) = self.pair_order.arrange(&left_child_hash, &right_child_hash);
+
+        Ok(self.hash_nodes(first, second))
+    }
+
+    /// get the hash of the child at `index` of a node being computed,
+    /// where the empty subtrees are not stored
+    ///
+    /// fail if the child is internal and `None` or an empty leaf that is forbidden
+    fn get_child_hash(&self, index: usize) -> Result<Hash, MerkleTreeError> {
+        if !self.nodes.has_hash(index) && self.is_empty_subtree(index) {
+            return self.get_empty_subtree_hash(index);
+        }
+
+        self.get_node_hash(index)
+    }
+
+    /// iterate over the indices of the internal nodes above the first `length` leaves
+    /// of a merkle tree of the given `height`, level by level from the lowest one up to the root,
+    /// so that the nodes of each level are accessed contiguously in ascending order
+    fn populated_internal_node_indices(
+        height: usize,
+        length: usize,
+    ) -> impl Iterator<Item = usize> {
+        // the nodes of a level are stored from 2^level to 2^(level + 1) - 1
+        // and each of them covers 2^(height - level) leaves
+        (0..height).rev().flat_map(move |level| {
+            let width = length.div_ceil(1 << (height - level));
+
+            (1 << level)..((1 << level) + width)
+        })
+    }
+
+    /// collect the indices of the ancestors of the leaves at the indices `leaves`,
+    /// level by level from the lowest one up to the root,
+    /// each of them once and in ascending order within a level
+    fn ancestor_indices(height: usize, leaves: &[usize]) -> Vec<usize> {
+        let mut level = leaves.to_vec();
+        level.sort_unstable();
+        level.dedup();
+
+        let mut ancestors = Vec::with_capacity(level.len() * height);
+        for _ in 0..height {
+            // dividing by 2 keeps the indices sorted, so duplicates are adjacent
+            level.iter_mut().for_each(|index| *index /= 2);
+            level.dedup();
+            ancestors.extend_from_slice(&level);
+        }
+
+        ancestors
+    }
+
     /// formula to compute 1 + 2 + ... + 2^n
     fn sum_of_powers_of_two(n: usize) -> usize {
         2usize.pow((n + 1) as u32) - 1
@@ -138,267 +1048,1084 @@ impl MerkleTree {
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    fn basic_hash(input: String) -> Hash {
-        format!("H({})", input)
-    }
+    use crate::test_util::{basic_hash, short_hash};
+    use alloc::vec;
 
     #[test]
-    #[should_panic]
-    fn from_height_zero_panics() {
-        MerkleTree::from_height(basic_hash, 0);
+    fn from_height_zero() {
+        assert_eq!(
+            Some(MerkleTreeError::InvalidHeight(0)),
+            MerkleTree::from_height(basic_hash, 0).err()
+        );
     }
 
     #[test]
     fn from_height_one() {
-        let mt = MerkleTree::from_height(basic_hash, 1);
+        let mt = MerkleTree::from_height(basic_hash, 1).unwrap();
 
         assert_eq!(1, mt.height);
         assert_eq!(0, mt.length);
         assert_eq!(2, mt.first_leaf_node_index);
-        assert_eq!(vec![None; 4], mt.nodes);
+        assert_eq!(MemoryStore::from(vec![None; 4]), mt.nodes);
     }
 
     #[test]
     fn from_height_five() {
-        let mt = MerkleTree::from_height(basic_hash, 5);
+        let mt = MerkleTree::from_height(basic_hash, 5).unwrap();
 
         assert_eq!(5, mt.height);
         assert_eq!(0, mt.length);
         assert_eq!(32, mt.first_leaf_node_index);
-        assert_eq!(vec![None; 64], mt.nodes);
+        assert_eq!(MemoryStore::from(vec![None; 64]), mt.nodes);
     }
 
     #[test]
     fn from_height_ten() {
-        let mt = MerkleTree::from_height(basic_hash, 10);
+        let mt = MerkleTree::from_height(basic_hash, 10).unwrap();
 
         assert_eq!(10, mt.height);
         assert_eq!(0, mt.length);
         assert_eq!(1024, mt.first_leaf_node_index);
-        assert_eq!(vec![None; 2048], mt.nodes);
+        assert_eq!(MemoryStore::from(vec![None; 2048]), mt.nodes);
     }
 
     #[test]
-    #[should_panic]
-    fn from_height_eleven_panics() {
-        MerkleTree::from_height(basic_hash, 11);
+    fn from_height_thirty_two() {
+        let mut mt = MerkleTree::from_height(short_hash as HashFunction, 32).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(1 << 32, mt.first_leaf_node_index);
+        // only the two leaves and their ancestors are stored
+        assert_eq!(34, mt.nodes.present_indices().len());
+
+        let empty_leaf = short_hash(b"empty node");
+        let mut expected = short_hash(&[short_hash(b"1"), short_hash(b"2")].concat());
+        let mut empty = short_hash(&[empty_leaf.clone(), empty_leaf].concat());
+        for _ in 1..32 {
+            expected = short_hash(&[expected, empty.clone()].concat());
+            empty = short_hash(&[empty.clone(), empty].concat());
+        }
+        assert_eq!(NodeHash(expected), mt.get_root().unwrap());
+    }
+
+    #[test]
+    fn from_leaves() {
+        let mt = MerkleTree::from_leaves(basic_hash as HashFunction, ["1", "2", "3"]).unwrap();
+
+        assert_eq!(2, mt.height);
+        assert_eq!(3, mt.length);
+        assert_eq!(
+            NodeHash(b"H(H(H(1)H(2))H(H(3)H(empty node)))".to_vec()),
+            mt.get_root().unwrap()
+        );
+
+        let mt = MerkleTree::from_leaves(basic_hash as HashFunction, Vec::<&str>::new()).unwrap();
+
+        assert_eq!(1, mt.height);
+        assert_eq!(
+            NodeHash(b"H(H(empty node)H(empty node))".to_vec()),
+            mt.get_root().unwrap()
+        );
+    }
+
+    #[test]
+    fn bind_index_separates_identical_values() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .bind_index(true)
+            .build()
+            .unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("1").unwrap();
+
+        assert_eq!(
+            LeafHash([&b"H("[..], &[0; 8], b"1)"].concat()),
+            mt.get_value(0).unwrap()
+        );
+        assert_eq!(
+            LeafHash([&b"H("[..], &[0, 0, 0, 0, 0, 0, 0, 1], b"1)"].concat()),
+            mt.get_value(1).unwrap()
+        );
+        assert_eq!(mt.hash_value(1, "1"), mt.get_value(1).unwrap());
+    }
+
+    #[test]
+    fn insert_hash_mixes_with_values() {
+        let mut mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert_hash(LeafHash(b"precomputed".to_vec())).unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(
+            NodeHash(b"H(H(1)precomputed)".to_vec()),
+            mt.get_root().unwrap()
+        );
+        assert_eq!(
+            Err(MerkleTreeError::Full),
+            mt.insert_hash(LeafHash(b"precomputed".to_vec()))
+        );
+    }
+
+    #[test]
+    fn domain_separation_prevents_forged_trees() {
+        // the values of the shorter tree are the concatenated children of the taller one
+        let values = ["1", "2", "3", "4"];
+        let forged = ["H(1)H(2)", "H(3)H(4)"];
+
+        let root = |values: &[&str]| {
+            MerkleTree::from_leaves(basic_hash, values)
+                .unwrap()
+                .get_root()
+        };
+        assert_eq!(root(&values), root(&forged));
+
+        let separated = DomainSeparated(basic_hash);
+        let separated_root = |values: &[&str]| {
+            MerkleTree::from_leaves(separated, values)
+                .unwrap()
+                .get_root()
+        };
+        assert_ne!(separated_root(&values), separated_root(&forged));
+        assert_eq!(b"H(\x001)".to_vec(), separated.hash_leaf(b"1"));
+        assert_eq!(b"H(\x01ab)".to_vec(), separated.hash_nodes(b"a", b"b"));
+    }
+
+    #[test]
+    fn set_value_replaces_leaf() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.insert("3").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        mt.set_value(1, "4").unwrap();
+
+        assert_eq!(Ok(LeafHash(b"H(4)".to_vec())), mt.get_value(1));
+        assert_eq!(Err(MerkleTreeError::StaleInternalNode(1)), mt.get_root());
+
+        mt.update_internal_nodes().unwrap();
+
+        let mut expected = MerkleTree::from_height(basic_hash, 2).unwrap();
+        expected.insert("1").unwrap();
+        expected.insert("4").unwrap();
+        expected.insert("3").unwrap();
+        expected.update_internal_nodes().unwrap();
+
+        assert_eq!(expected.get_root(), mt.get_root());
+        assert_eq!(3, mt.length);
+    }
+
+    #[test]
+    fn set_value_not_inserted() {
+        let mut mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+        mt.insert("1").unwrap();
+
+        assert_eq!(Err(MerkleTreeError::NeverInserted(1)), mt.set_value(1, "2"));
+        assert_eq!(
+            Err(MerkleTreeError::ValueOutOfBounds(2)),
+            mt.set_value(2, "2")
+        );
+    }
+
+    #[test]
+    fn empty_tree_has_a_root() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+
+        let empty_root =
+            NodeHash(b"H(H(H(empty node)H(empty node))H(H(empty node)H(empty node)))".to_vec());
+        assert_eq!(Ok(empty_root.clone()), mt.get_root());
+        mt.update_internal_nodes().unwrap();
+        assert_eq!(Ok(empty_root.clone()), mt.get_root());
+        assert_eq!(empty_root, mt.empty_root());
+
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .empty_leaf(EmptyLeaf::Forbidden)
+            .build()
+            .unwrap();
+        mt.update_internal_nodes().unwrap();
+        // unlike the hash of an empty value
+        assert_eq!(Ok(NodeHash(Vec::new())), mt.get_root());
+        mt.insert("").unwrap();
+        assert_ne!(Ok(NodeHash(Vec::new())), mt.get_root());
+    }
+
+    #[test]
+    fn leaves_stop_at_length() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+        assert_eq!(0, mt.leaves().count());
+
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+
+        assert_eq!(
+            vec![
+                (0, LeafHash(b"H(1)".to_vec())),
+                (1, LeafHash(b"H(2)".to_vec())),
+            ],
+            mt.leaves().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn root_recomputes_stale_nodes() {
+        let mut mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+        mt.insert("1").unwrap();
+
+        assert_eq!(Err(MerkleTreeError::StaleInternalNode(1)), mt.get_root());
+        assert_eq!(Ok(NodeHash(b"H(H(1)H(empty node))".to_vec())), mt.root());
+        assert_eq!(mt.root(), mt.get_root());
+    }
+
+    #[test]
+    fn len_and_capacity() {
+        let mut mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+        assert!(mt.is_empty());
+        assert_eq!(
+            (0, 2, 2, 1),
+            (
+                mt.len(),
+                mt.capacity(),
+                mt.remaining_capacity(),
+                mt.height()
+            )
+        );
+
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+
+        assert!(!mt.is_empty());
+        assert_eq!((2, 0), (mt.len(), mt.remaining_capacity()));
+        assert_eq!(Err(MerkleTreeError::Full), mt.insert("3"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn max_height_tree_hashes_only_the_path() {
+        // a fixed-size hash, as the hashes of the basic hash function would grow with the height
+        let length_hash = |input: &[u8]| input.len().to_le_bytes().to_vec();
+        let mut mt = MerkleTreeBuilder::new(length_hash, MAX_HEIGHT)
+            .collect_timings(true)
+            .build()
+            .unwrap();
+
+        mt.insert("1").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        // the empty subtrees on the right of the path come from the precomputed hashes
+        assert_eq!(MAX_HEIGHT as u64, mt.timings().unwrap().node_hashing.count);
+        // a few hundred bytes per level, rather than per leaf
+        assert!(mt.memory_usage() < 16 * 1024);
+    }
+
+    #[test]
+    fn from_height_too_large() {
+        assert_eq!(
+            Some(MerkleTreeError::InvalidHeight(MAX_HEIGHT + 1)),
+            MerkleTree::from_height(basic_hash, MAX_HEIGHT + 1).err()
+        );
     }
 
     #[test]
     fn height_one_insert() {
         let mut mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
             length: 0,
             first_leaf_node_index: 2,
-            nodes: vec![None; 4],
+            nodes: MemoryStore::from(vec![None; 4]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
         let value_one = "1".to_string();
         let node_one = Node {
-            hash: basic_hash(value_one.clone()),
+            hash: basic_hash(value_one.as_bytes()),
         };
 
-        mt.insert(value_one);
+        mt.insert(value_one).unwrap();
         assert_eq!(1, mt.length);
-        assert_eq!(vec![None, None, Some(node_one.clone()), None], mt.nodes);
+        assert_eq!(
+            MemoryStore::from(vec![None, None, Some(node_one.clone()), None]),
+            mt.nodes
+        );
 
         let value_two = "2".to_string();
         let node_two = Node {
-            hash: basic_hash(value_two.clone()),
+            hash: basic_hash(value_two.as_bytes()),
         };
 
-        mt.insert(value_two);
+        mt.insert(value_two).unwrap();
         assert_eq!(2, mt.length);
-        assert_eq!(vec![None, None, Some(node_one), Some(node_two)], mt.nodes);
+        assert_eq!(
+            MemoryStore::from(vec![None, None, Some(node_one), Some(node_two)]),
+            mt.nodes
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn height_one_insert_full_panics() {
+    fn height_one_insert_full() {
         let node_one = Node {
-            hash: basic_hash("1".to_string()),
+            hash: basic_hash(b"1"),
         };
         let node_two = Node {
-            hash: basic_hash("2".to_string()),
+            hash: basic_hash(b"2"),
         };
 
         let mut full_mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
             length: 2,
             first_leaf_node_index: 2,
-            nodes: vec![None, None, Some(node_one), Some(node_two)],
+            nodes: MemoryStore::from(vec![None, None, Some(node_one), Some(node_two)]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
-        full_mt.insert("3".to_string());
+        assert_eq!(Err(MerkleTreeError::Full), full_mt.insert("3"));
+        assert_eq!(2, full_mt.length);
     }
 
     #[test]
     fn height_one_insert_two_set_root_to_none() {
         let root = Node {
-            hash: basic_hash("H(1) | H(empty node)".to_string()),
+            hash: basic_hash(b"H(1)H(empty node)"),
         };
         let node_one = Node {
-            hash: basic_hash("1".to_string()),
+            hash: basic_hash(b"1"),
         };
 
         let mut mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
             length: 1,
             first_leaf_node_index: 2,
-            nodes: vec![None, Some(root), Some(node_one.clone()), None],
+            nodes: MemoryStore::from(vec![None, Some(root), Some(node_one.clone()), None]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
         let value_two = "2".to_string();
         let node_two = Node {
-            hash: basic_hash(value_two.clone()),
+            hash: basic_hash(value_two.as_bytes()),
         };
 
-        mt.insert(value_two);
+        mt.insert(value_two).unwrap();
         assert_eq!(2, mt.length);
-        assert_eq!(vec![None, None, Some(node_one), Some(node_two)], mt.nodes);
+        assert_eq!(
+            MemoryStore::from(vec![None, None, Some(node_one), Some(node_two)]),
+            mt.nodes
+        );
     }
 
     #[test]
     fn height_one_empty_update_internal_node() {
         let mut mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
             length: 0,
             first_leaf_node_index: 2,
-            nodes: vec![None; 4],
+            nodes: MemoryStore::from(vec![None; 4]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
-        mt.update_internal_nodes();
-
-        let root = Node {
-            hash: "H(H(empty node) | H(empty node))".to_string(),
-        };
+        mt.update_internal_nodes().unwrap();
 
-        assert_eq!(vec![None, Some(root), None, None], mt.nodes);
+        // the root of an empty merkle tree is not stored, as it only depends on its configuration
+        assert_eq!(MemoryStore::from(vec![None; 4]), mt.nodes);
+        assert_eq!(
+            Ok(NodeHash(b"H(H(empty node)H(empty node))".to_vec())),
+            mt.get_root()
+        );
     }
 
     #[test]
     fn height_one_half_full_update_internal_node() {
         let node_one = Node {
-            hash: "H(1)".to_string(),
+            hash: b"H(1)".to_vec(),
         };
 
         let mut mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
             length: 1,
             first_leaf_node_index: 2,
-            nodes: vec![None, None, Some(node_one.clone()), None],
+            nodes: MemoryStore::from(vec![None, None, Some(node_one.clone()), None]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
-        mt.update_internal_nodes();
+        mt.update_internal_nodes().unwrap();
 
         let root = Node {
-            hash: "H(H(1) | H(empty node))".to_string(),
+            hash: b"H(H(1)H(empty node))".to_vec(),
         };
 
-        assert_eq!(vec![None, Some(root), Some(node_one), None], mt.nodes);
+        assert_eq!(
+            MemoryStore::from(vec![None, Some(root), Some(node_one), None]),
+            mt.nodes
+        );
     }
 
     #[test]
     fn height_one_full_update_internal_node() {
         let node_one = Node {
-            hash: "H(1)".to_string(),
+            hash: b"H(1)".to_vec(),
         };
         let node_two = Node {
-            hash: "H(2)".to_string(),
+            hash: b"H(2)".to_vec(),
         };
 
         let mut mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
             length: 2,
             first_leaf_node_index: 2,
-            nodes: vec![None, None, Some(node_one.clone()), Some(node_two.clone())],
+            nodes: MemoryStore::from(vec![
+                None,
+                None,
+                Some(node_one.clone()),
+                Some(node_two.clone()),
+            ]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
-        mt.update_internal_nodes();
+        mt.update_internal_nodes().unwrap();
 
         let root = Node {
-            hash: "H(H(1) | H(2))".to_string(),
+            hash: b"H(H(1)H(2))".to_vec(),
         };
 
         assert_eq!(
-            vec![None, Some(root), Some(node_one), Some(node_two)],
+            MemoryStore::from(vec![None, Some(root), Some(node_one), Some(node_two)]),
             mt.nodes
         );
     }
 
     #[test]
-    #[should_panic]
-    fn height_one_get_root_none_panics() {
+    fn height_one_get_root_none() {
         let mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
-            length: 0,
+            length: 1,
             first_leaf_node_index: 2,
-            nodes: vec![None; 4],
+            nodes: MemoryStore::from(vec![
+                None,
+                None,
+                Some(Node {
+                    hash: b"H(1)".to_vec(),
+                }),
+                None,
+            ]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
-        mt.get_root();
+        assert_eq!(Err(MerkleTreeError::StaleInternalNode(1)), mt.get_root());
     }
 
     #[test]
     fn height_one_get_root_some() {
         let root = Node {
-            hash: "H(H(1) | H(2))".to_string(),
+            hash: b"H(H(1)H(2))".to_vec(),
         };
         let node_one = Node {
-            hash: "H(1)".to_string(),
+            hash: b"H(1)".to_vec(),
         };
         let node_two = Node {
-            hash: "H(2)".to_string(),
+            hash: b"H(2)".to_vec(),
         };
 
         let mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
             length: 2,
             first_leaf_node_index: 2,
-            nodes: vec![None, Some(root), Some(node_one), Some(node_two)],
+            nodes: MemoryStore::from(vec![None, Some(root), Some(node_one), Some(node_two)]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
-        assert_eq!("H(H(1) | H(2))", mt.get_root());
+        assert_eq!(b"H(H(1)H(2))".to_vec(), mt.get_root().unwrap().0);
     }
 
     #[test]
     fn height_one_half_full_get_value() {
         let node_one = Node {
-            hash: "H(1)".to_string(),
+            hash: b"H(1)".to_vec(),
         };
 
         let mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
             length: 1,
             first_leaf_node_index: 2,
-            nodes: vec![None, None, Some(node_one), None],
+            nodes: MemoryStore::from(vec![None, None, Some(node_one), None]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
-        assert_eq!("H(1)", mt.get_value(0));
-        assert_eq!("H(empty node)", mt.get_value(1));
+        assert_eq!(b"H(1)".to_vec(), mt.get_value(0).unwrap().0);
+        assert_eq!(b"H(empty node)".to_vec(), mt.get_value(1).unwrap().0);
     }
 
     #[test]
-    #[should_panic]
-    fn height_one_get_value_out_of_bounds_panics() {
+    fn height_one_get_value_out_of_bounds() {
         let mt = MerkleTree {
-            hash_function: basic_hash,
+            hasher: basic_hash,
             height: 1,
             length: 0,
             first_leaf_node_index: 2,
-            nodes: vec![None, None, None, None],
+            nodes: MemoryStore::from(vec![None, None, None, None]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
         };
 
-        mt.get_value(2);
+        assert_eq!(Err(MerkleTreeError::ValueOutOfBounds(2)), mt.get_value(2));
+    }
+
+    #[test]
+    fn strict_get_inserted_value() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .strict(true)
+            .build()
+            .unwrap();
+
+        mt.insert("1").unwrap();
+
+        assert!(mt.strict);
+        assert_eq!(b"H(1)".to_vec(), mt.get_value(0).unwrap().0);
+    }
+
+    #[test]
+    fn strict_get_never_inserted_value() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .strict(true)
+            .build()
+            .unwrap();
+
+        mt.insert("1").unwrap();
+
+        assert_eq!(Err(MerkleTreeError::NeverInserted(1)), mt.get_value(1));
+    }
+
+    fn at_most_three_bytes(value: &[u8]) -> Result<(), String> {
+        if value.len() > 3 {
+            return Err("values cannot be longer than 3 bytes".to_string());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn validator_accepts_value() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .validator(at_most_three_bytes)
+            .build()
+            .unwrap();
+
+        mt.insert("abc").unwrap();
+
+        assert_eq!(1, mt.length);
+        assert_eq!(b"H(abc)".to_vec(), mt.get_value(0).unwrap().0);
+    }
+
+    #[test]
+    fn validator_rejects_value() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .validator(at_most_three_bytes)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Err(MerkleTreeError::Rejected(
+                "values cannot be longer than 3 bytes".to_string()
+            )),
+            mt.insert("abcd")
+        );
+        assert_eq!(0, mt.length);
+    }
+
+    #[test]
+    fn height_two_rebuild() {
+        let root = Node {
+            hash: b"stale root".to_vec(),
+        };
+        let node_one = Node {
+            hash: b"H(1)".to_vec(),
+        };
+        let node_two = Node {
+            hash: b"H(2)".to_vec(),
+        };
+        let node_three = Node {
+            hash: b"H(3)".to_vec(),
+        };
+
+        let mut mt = MerkleTree {
+            hasher: basic_hash,
+            height: 2,
+            length: 3,
+            first_leaf_node_index: 4,
+            nodes: MemoryStore::from(vec![
+                None,
+                Some(root),
+                None,
+                None,
+                Some(node_one),
+                Some(node_two),
+                Some(node_three),
+                None,
+            ]),
+            strict: false,
+            validation_function: None,
+            ids: BTreeMap::new(),
+            ids_memory: 0,
+            empty_leaf: EmptyLeaf::default(),
+            empty_hashes: Vec::new(),
+            pair_order: PairOrder::default(),
+            bind_index: false,
+            tree_id: TreeId(0),
+            prepared_head: None,
+            published_head: None,
+            memory_budget: None,
+            #[cfg(feature = "std")]
+            timings: None,
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "std")]
+            insert_events: None,
+            dirty_leaves: Vec::new(),
+            fully_stale: true,
+        };
+
+        mt.rebuild().unwrap();
+
+        assert_eq!(
+            b"H(H(H(1)H(2))H(H(3)H(empty node)))".to_vec(),
+            mt.get_root().unwrap().0
+        );
+        assert_eq!(b"H(H(1)H(2))".to_vec(), mt.get_node_hash(2).unwrap());
+        assert_eq!(
+            b"H(H(3)H(empty node))".to_vec(),
+            mt.get_node_hash(3).unwrap()
+        );
+    }
+
+    #[test]
+    fn rebuild_matches_update_internal_nodes() {
+        let mut updated = MerkleTree::from_height(basic_hash, 4).unwrap();
+        let mut rebuilt = MerkleTree::from_height(basic_hash, 4).unwrap();
+
+        for i in 0..11 {
+            updated.insert(i.to_string()).unwrap();
+            rebuilt.insert(i.to_string()).unwrap();
+        }
+
+        updated.update_internal_nodes().unwrap();
+        rebuilt.rebuild().unwrap();
+
+        assert_eq!(updated.nodes, rebuilt.nodes);
+    }
+
+    #[test]
+    fn insert_with_id_is_idempotent() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+
+        assert_eq!(0, mt.insert_with_id("a".to_string(), "1").unwrap());
+        assert_eq!(1, mt.insert_with_id("b".to_string(), "2").unwrap());
+        assert_eq!(0, mt.insert_with_id("a".to_string(), "1").unwrap());
+        assert_eq!(2, mt.length);
+
+        mt.insert("3").unwrap();
+        assert_eq!(3, mt.insert_with_id("c".to_string(), "3").unwrap());
+        assert_eq!(4, mt.length);
+    }
+
+    #[test]
+    fn insert_with_id_other_value() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+
+        mt.insert_with_id("a".to_string(), "1").unwrap();
+
+        assert_eq!(
+            Err(MerkleTreeError::IdConflict("a".to_string())),
+            mt.insert_with_id("a".to_string(), "2")
+        );
+        assert_eq!(1, mt.length);
+    }
+
+    #[test]
+    fn preview_root_does_not_modify_tree() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+        mt.insert("1").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let root = mt.get_root().unwrap();
+        let pending = vec!["2".to_string(), "3".to_string()];
+
+        let preview = mt.preview_root(&pending).unwrap();
+
+        assert_eq!(1, mt.length);
+        assert_eq!(root, mt.get_root().unwrap());
+
+        for value in pending {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(preview, mt.get_root().unwrap());
+    }
+
+    #[test]
+    fn preview_root_past_capacity() {
+        let mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+
+        assert_eq!(
+            Err(MerkleTreeError::Full),
+            mt.preview_root(&["1".to_string(), "2".to_string(), "3".to_string()])
+        );
+    }
+
+    #[test]
+    fn empty_leaf_constant() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .empty_leaf(EmptyLeaf::Constant("nothing".to_string()))
+            .build()
+            .unwrap();
+
+        mt.insert("1").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(b"H(nothing)".to_vec(), mt.get_value(1).unwrap().0);
+        assert_eq!(b"H(H(1)H(nothing))".to_vec(), mt.get_root().unwrap().0);
+    }
+
+    #[test]
+    fn empty_leaf_hash() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .empty_leaf(EmptyLeaf::Hash(LeafHash(vec![0; 4])))
+            .build()
+            .unwrap();
+
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(
+            [&b"H("[..], &[0; 8], b")"].concat(),
+            mt.get_root().unwrap().0
+        );
+    }
+
+    #[test]
+    fn empty_leaf_index() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .empty_leaf(EmptyLeaf::Index)
+            .build()
+            .unwrap();
+
+        mt.insert("a").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(
+            b"H(H(H(a)H(1))H(H(2)H(3)))".to_vec(),
+            mt.get_root().unwrap().0
+        );
+    }
+
+    #[test]
+    fn empty_leaf_forbidden_full_tree() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .empty_leaf(EmptyLeaf::Forbidden)
+            .build()
+            .unwrap();
+
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(b"H(H(1)H(2))".to_vec(), mt.get_root().unwrap().0);
+    }
+
+    #[test]
+    fn empty_leaf_forbidden_update_internal_nodes() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .empty_leaf(EmptyLeaf::Forbidden)
+            .build()
+            .unwrap();
+
+        mt.insert("1").unwrap();
+
+        assert_eq!(
+            Err(MerkleTreeError::EmptyLeafForbidden(1)),
+            mt.update_internal_nodes()
+        );
+        assert_eq!(Err(MerkleTreeError::EmptyLeafForbidden(1)), mt.get_value(1));
+    }
+
+    #[test]
+    fn leaf_and_node_hashes_convert_into_hash() {
+        let mut mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+        mt.insert("1").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let leaf_hash: Hash = mt.get_value(0).unwrap().into();
+        let node_hash: Hash = mt.get_root().unwrap().into();
+
+        assert_eq!(b"H(1)".to_vec(), leaf_hash);
+        assert_eq!(b"H(H(1)H(empty node))".to_vec(), node_hash);
+        assert_eq!("48283129", mt.get_value(0).unwrap().to_string());
+    }
+
+    #[test]
+    fn keyed_hasher() {
+        struct KeyedHasher {
+            key: String,
+        }
+
+        impl Hasher for KeyedHasher {
+            fn hash_leaf(&self, value: &[u8]) -> Hash {
+                [self.key.as_bytes(), b"L(", value, b")"].concat()
+            }
+
+            fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash {
+                [self.key.as_bytes(), b"N(", left, b", ", right, b")"].concat()
+            }
+        }
+
+        let mut mt = MerkleTree::from_height(
+            KeyedHasher {
+                key: "k".to_string(),
+            },
+            1,
+        )
+        .unwrap();
+        mt.insert("1").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(
+            b"kN(kL(1), kL(empty node))".to_vec(),
+            mt.get_root().unwrap().0
+        );
+    }
+
+    #[test]
+    fn closure_hasher() {
+        let prefix = b"C".to_vec();
+        let mut mt =
+            MerkleTree::from_height(move |input: &[u8]| [&prefix, input].concat(), 1).unwrap();
+        mt.insert("1").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(b"CC1Cempty node".to_vec(), mt.get_root().unwrap().0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn update_internal_nodes_only_visits_changed_paths() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 3)
+            .collect_timings(true)
+            .build()
+            .unwrap();
+        mt.insert("1").unwrap();
+        mt.update_internal_nodes().unwrap();
+        mt.reset_timings();
+
+        mt.insert("2").unwrap();
+        mt.insert("3").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        // the parents of both leaves, their common ancestors being computed once,
+        // the empty right subtree of the root being precomputed
+        assert_eq!(4, mt.timings().unwrap().node_hashing.count);
+        assert!(mt.dirty_leaves.is_empty());
+
+        let mut expected = MerkleTree::from_height(basic_hash, 3).unwrap();
+        expected.insert("1").unwrap();
+        expected.insert("2").unwrap();
+        expected.insert("3").unwrap();
+        expected.update_internal_nodes().unwrap();
+
+        assert_eq!(expected.nodes, mt.nodes);
     }
 }
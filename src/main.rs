@@ -1,26 +1,232 @@
-use merkle_tree::{Hash, HashFunction, MerkleTree};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use merkle_tree::import::{self, DigestFormat};
+use merkle_tree::proof::MerkleProof;
+use merkle_tree::sha256::Sha256Hasher;
+use merkle_tree::{hex, DomainSeparated, Hasher, LeafHash, MerkleTree, NodeHash};
+
+const USAGE: &str = "\
+usage:
+  merkle-tree root <file> [--chunk-size <bytes>]
+      print the root of the merkle tree of the lines of <file>,
+      or of its chunks of <bytes> bytes
+  merkle-tree import <digests>
+      print the root of the merkle tree whose leaves are the digests listed in <digests>,
+      either the output of sha256sum or a column of hexadecimal digests
+  merkle-tree prove <file> <index> [--chunk-size <bytes>]
+      print the JSON proof of the line, or chunk, at <index> of <file>
+  merkle-tree verify <root> <proof.json> <value>
+      check that <value> is included under <root> according to the JSON proof,
+      exiting with 1 if it is not
+  merkle-tree repl [<height>]
+      explore a merkle tree of <height>, 3 by default, interactively";
+
+/// a merkle tree hashing the values and the nodes with SHA-256,
+/// tagged as leaves or nodes as in RFC 6962
+type Sha256Tree = MerkleTree<DomainSeparated<Sha256Hasher>>;
+
+/// the hasher of `Sha256Tree`
+const HASHER: DomainSeparated<Sha256Hasher> = DomainSeparated(Sha256Hasher);
 
 fn main() {
-    println!("Hello, merkle tree!");
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Err(error) = run(&args) {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+/// run the command given by the arguments `args`
+///
+/// fail if the arguments are invalid or the command fails
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["root", file] => println!("{}", tree_of_file(file, None)?.get_root()?),
+        ["root", file, "--chunk-size", chunk_size] => println!(
+            "{}",
+            tree_of_file(file, Some(chunk_size.parse()?))?.get_root()?
+        ),
+        ["import", digests] => println!("{}", tree_of_digests(digests)?.get_root()?),
+        ["prove", file, index] => println!(
+            "{}",
+            tree_of_file(file, None)?.prove(index.parse()?)?.to_json()
+        ),
+        ["prove", file, index, "--chunk-size", chunk_size] => println!(
+            "{}",
+            tree_of_file(file, Some(chunk_size.parse()?))?
+                .prove(index.parse()?)?
+                .to_json()
+        ),
+        ["verify", root, proof, value] => {
+            if verify(root, proof, value)? {
+                println!("valid");
+            } else {
+                println!("invalid");
+                process::exit(1);
+            }
+        }
+        ["repl"] => repl(MerkleTree::from_height(HASHER, 3)?),
+        ["repl", height] => repl(MerkleTree::from_height(HASHER, height.parse()?)?),
+        _ => return Err(USAGE.into()),
+    }
+
+    Ok(())
+}
+
+/// build the merkle tree of the lines of `file`, or of its chunks of `chunk_size` bytes
+///
+/// fail if the file cannot be read or the chunk size is 0
+fn tree_of_file(file: &str, chunk_size: Option<usize>) -> Result<Sha256Tree, Box<dyn Error>> {
+    let content = fs::read(file)?;
+
+    let values: Vec<&[u8]> = match chunk_size {
+        Some(0) => return Err("the chunk size cannot be 0".into()),
+        Some(chunk_size) => content.chunks(chunk_size).collect(),
+        None => {
+            let mut lines: Vec<&[u8]> = content
+                .split(|&byte| byte == b'\n')
+                .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+                .collect();
+            // a final newline does not start another line
+            if lines.last() == Some(&&b""[..]) {
+                lines.pop();
+            }
+
+            lines
+        }
+    };
 
-    // define a dummy hash function that hashes "x" into "Hash of (x)"
-    let dummy_hash: HashFunction = |input: String| -> Hash { format!("Hash of ({})", input) };
+    Ok(MerkleTree::from_leaves(HASHER, values)?)
+}
+
+/// build the merkle tree whose leaves are the digests listed in `file`, detecting their format
+///
+/// fail if the file cannot be read or if it is not a list of digests
+fn tree_of_digests(file: &str) -> Result<Sha256Tree, Box<dyn Error>> {
+    let text = fs::read_to_string(file)?;
+    let format = DigestFormat::detect(&text).ok_or("unknown format of the list of digests")?;
+
+    Ok(MerkleTree::from_leaf_hashes(
+        HASHER,
+        import::parse_digests(&text, format)?,
+    )?)
+}
+
+/// check that `value` is included under the hexadecimal `root` according to the JSON proof
+/// stored in the file `proof`
+///
+/// fail if the root is not hexadecimal or the proof cannot be read or decoded
+fn verify(root: &str, proof: &str, value: &str) -> Result<bool, Box<dyn Error>> {
+    let root = NodeHash(hex::decode(root).ok_or(format!("invalid root {}", root))?);
+    let proof = MerkleProof::from_json(&fs::read_to_string(proof)?)?;
+    let leaf_hash = LeafHash(HASHER.hash_leaf(value.as_bytes()));
+
+    Ok(proof.verify(&leaf_hash, &root, &HASHER))
+}
+
+const HELP: &str = "\
+commands:
+  insert <value>                  insert a value
+  root                            print the root
+  prove <index>                   print the proof of the value at <index>
+  verify <index> <value> [<root>] check that <value> is at <index> under <root>,
+                                  the current root by default
+  print                           print all the nodes
+  help                            print this help
+  quit                            exit";
+
+/// read commands from the standard input and run them on `mt` until `quit` or the end of the input,
+/// printing the errors of the commands instead of stopping
+fn repl(mut mt: Sha256Tree) {
+    println!("{}", HELP);
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        let _ = io::stdout().flush();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["quit"] | ["exit"] => return,
+            ["help"] => println!("{}", HELP),
+            command => match run_command(&mut mt, command) {
+                Ok(output) => println!("{}", output),
+                Err(error) => println!("error: {}", error),
+            },
+        }
+    }
+}
 
-    // create a merkle tree with the given dummy function and height of 2
-    let mut mt = MerkleTree::from_height(dummy_hash, 2);
+/// run a single REPL command on `mt` and return what to print
+///
+/// fail if the command is unknown, its arguments are invalid or the merkle tree rejects it
+fn run_command(mt: &mut Sha256Tree, command: &[&str]) -> Result<String, Box<dyn Error>> {
+    let parse_index = |index: &str| {
+        index
+            .parse::<usize>()
+            .map_err(|_| format!("invalid index {}", index))
+    };
 
-    // insert three elements into the merkle tree
-    mt.insert("Hello".to_string());
-    mt.insert("Merkle".to_string());
-    mt.insert("Tree".to_string());
+    match command {
+        ["insert", value] => {
+            mt.insert(value)?;
+            Ok(format!(
+                "inserted {} at {}, {} more can be inserted",
+                value,
+                mt.len() - 1,
+                mt.remaining_capacity()
+            ))
+        }
+        ["root"] => Ok(mt.root()?.to_string()),
+        ["prove", index] => {
+            mt.update_internal_nodes()?;
+            let proof = mt.prove(parse_index(index)?)?;
 
-    // update the state of the internal nodes
-    mt.update_internal_nodes();
+            Ok(proof
+                .steps
+                .iter()
+                .map(|step| format!("{:?} {}", step.position, hex::encode(&step.hash)))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        ["verify", index, value, root @ ..] if root.len() <= 1 => {
+            mt.update_internal_nodes()?;
+            let root = match root {
+                [root] => NodeHash(hex::decode(root).ok_or(format!("invalid root {}", root))?),
+                _ => mt.get_root()?,
+            };
+            let value_index = parse_index(index)?;
+            let proof = mt.prove(value_index)?;
+            let leaf_hash = mt.hash_value(value_index, value);
 
-    // get and print the root's hash of the merkle tree
-    println!("root: {}", mt.get_root());
+            Ok(if proof.verify(&leaf_hash, &root, &HASHER) {
+                "valid".to_string()
+            } else {
+                "invalid".to_string()
+            })
+        }
+        ["print"] => {
+            mt.update_internal_nodes()?;
 
-    // get and print the hash corresponding to value "Tree"
-    let value_index = 2;
-    println!("value {}: {}", value_index, mt.get_value(value_index));
+            Ok(mt
+                .export_nodes()
+                .map(|(index, hash)| format!("{}: {}", index, hex::encode(&hash)))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        _ => Err(format!("unknown command {}, type help", command.join(" ")).into()),
+    }
 }
@@ -0,0 +1,445 @@
+//! registry of many named merkle trees, e.g. one per customer of a service
+//!
+//! every merkle tree is created from its own builder, so its height, hasher and other options
+//! are its own, while the stores of the deleted merkle trees are pooled and handed
+//! to the next created ones, so that a backend such as a database connection is set up only once
+//!
+//! merkle trees may belong to tenants, whose quotas and rate limit are enforced
+//! when their merkle trees are created and when values are inserted with `TreeManager::insert`,
+//! the only way to add values to them
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::store::{MemoryStore, NodeStore};
+use crate::{Hasher, MerkleTree, MerkleTreeBuilder, MerkleTreeError, NodeHash};
+
+/// the limits of a tenant, unlimited by default
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Quota {
+    /// the most merkle trees the tenant can own
+    pub max_trees: Option<usize>,
+    /// the most values that can be inserted into all the merkle trees of the tenant together
+    pub max_leaves: Option<usize>,
+}
+
+/// a hook deciding whether a tenant may insert a value into its merkle tree,
+/// given the names of the tenant and of the merkle tree, e.g. to enforce a rate per plan
+pub type RateLimiter = Box<dyn FnMut(&str, &str) -> bool + Send>;
+
+/// the quota of a tenant and the names of its merkle trees
+#[derive(Default)]
+struct Tenant {
+    quota: Quota,
+    trees: BTreeSet<String>,
+}
+
+/// the owner of many merkle trees, identified by their names
+pub struct TreeManager<H, S = MemoryStore> {
+    trees: BTreeMap<String, MerkleTree<H, S>>,
+    /// the tenant owning each merkle tree that has one
+    owners: HashMap<String, String>,
+    tenants: HashMap<String, Tenant>,
+    rate_limiter: Option<RateLimiter>,
+    /// the stores of the deleted merkle trees, reset before being reused
+    pool: Vec<S>,
+    /// create a store when the pool is empty
+    new_store: Box<dyn Fn() -> S + Send>,
+}
+
+impl<H: Hasher> TreeManager<H> {
+    /// create a manager of merkle trees whose nodes are kept in memory
+    pub fn new() -> Self {
+        TreeManager::with_store(MemoryStore::default)
+    }
+}
+
+impl<H: Hasher> Default for TreeManager<H> {
+    fn default() -> Self {
+        TreeManager::new()
+    }
+}
+
+impl<H: Hasher, S: NodeStore> TreeManager<H, S> {
+    /// create a manager of merkle trees whose stores are created with `new_store`
+    /// when none of the deleted merkle trees can be reused
+    pub fn with_store(new_store: impl Fn() -> S + Send + 'static) -> Self {
+        TreeManager {
+            trees: BTreeMap::new(),
+            owners: HashMap::new(),
+            tenants: HashMap::new(),
+            rate_limiter: None,
+            pool: Vec::new(),
+            new_store: Box::new(new_store),
+        }
+    }
+
+    /// create the merkle tree `name` from the options collected by `builder`,
+    /// backed by a pooled store if any, and return it
+    ///
+    /// fail if a merkle tree with this name already exists or if the merkle tree cannot be built
+    pub fn create(
+        &mut self,
+        name: impl Into<String>,
+        builder: MerkleTreeBuilder<H>,
+    ) -> Result<&mut MerkleTree<H, S>, MerkleTreeError> {
+        let name = name.into();
+        if self.trees.contains_key(&name) {
+            return Err(MerkleTreeError::TreeExists(name));
+        }
+
+        // building the merkle tree resets the store, which goes back to the pool if it fails
+        let store = self.pool.pop().unwrap_or_else(|| (self.new_store)());
+        let mt = match builder.node_store(store).build_or_return_store() {
+            Ok(mt) => mt,
+            Err((error, mut store)) => {
+                store.reset(0);
+                self.pool.push(store);
+                return Err(error);
+            }
+        };
+
+        Ok(self.trees.entry(name).or_insert(mt))
+    }
+
+    /// create the merkle tree `name` like `create`, owned by `tenant`,
+    /// which can then only be modified through the quota-checked methods of the manager,
+    /// e.g. `insert` and `update_root`
+    ///
+    /// fail if the tenant already owns as many merkle trees as its quota allows,
+    /// if a merkle tree with this name already exists or if the merkle tree cannot be built
+    pub fn create_for(
+        &mut self,
+        tenant: &str,
+        name: impl Into<String>,
+        builder: MerkleTreeBuilder<H>,
+    ) -> Result<&MerkleTree<H, S>, MerkleTreeError> {
+        let name = name.into();
+        let owned = self
+            .tenants
+            .get(tenant)
+            .map_or(0, |tenant| tenant.trees.len());
+        let quota = self.quota(tenant);
+        if quota.max_trees.is_some_and(|max_trees| owned >= max_trees) {
+            return Err(MerkleTreeError::TreeQuotaExceeded(tenant.to_string()));
+        }
+
+        self.create(name.clone(), builder)?;
+
+        self.owners.insert(name.clone(), tenant.to_string());
+        self.tenants
+            .entry(tenant.to_string())
+            .or_default()
+            .trees
+            .insert(name.clone());
+
+        Ok(self
+            .trees
+            .get(&name)
+            .expect("The merkle tree was just created."))
+    }
+
+    /// set the quota of `tenant`, which only applies to the merkle trees created
+    /// and the values inserted from now on
+    pub fn set_quota(&mut self, tenant: &str, quota: Quota) {
+        self.tenants.entry(tenant.to_string()).or_default().quota = quota;
+    }
+
+    /// get the quota of `tenant`
+    pub fn quota(&self, tenant: &str) -> Quota {
+        self.tenants
+            .get(tenant)
+            .map_or_else(Quota::default, |tenant| tenant.quota)
+    }
+
+    /// consult `rate_limiter` before every insertion into a merkle tree owned by a tenant
+    pub fn set_rate_limiter(
+        &mut self,
+        rate_limiter: impl FnMut(&str, &str) -> bool + Send + 'static,
+    ) {
+        self.rate_limiter = Some(Box::new(rate_limiter));
+    }
+
+    /// insert `value` into the merkle tree `name`, enforcing the quota and the rate limit
+    /// of its tenant if it has one
+    ///
+    /// fail if there is no merkle tree with this name, if the tenant already reached its quota
+    /// of values, if the rate limiter refuses the insertion or if the value cannot be inserted
+    pub fn insert(&mut self, name: &str, value: impl AsRef<[u8]>) -> Result<(), MerkleTreeError> {
+        if !self.trees.contains_key(name) {
+            return Err(MerkleTreeError::UnknownTree(name.to_string()));
+        }
+
+        if let Some(owner) = self.owners.get(name) {
+            // the quota is checked first, so that a refused insertion does not consume the rate limit
+            let tenant = &self.tenants[owner];
+            let leaves: usize = tenant.trees.iter().map(|name| self.trees[name].len()).sum();
+            if tenant
+                .quota
+                .max_leaves
+                .is_some_and(|max_leaves| leaves >= max_leaves)
+            {
+                return Err(MerkleTreeError::LeafQuotaExceeded(owner.clone()));
+            }
+
+            if let Some(rate_limiter) = &mut self.rate_limiter {
+                if !rate_limiter(owner, name) {
+                    return Err(MerkleTreeError::RateLimited(owner.clone()));
+                }
+            }
+        }
+
+        self.trees
+            .get_mut(name)
+            .expect("The merkle tree exists.")
+            .insert(value)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MerkleTree<H, S>> {
+        self.trees.get(name)
+    }
+
+    /// get the merkle tree `name` to modify it freely, or `None` if it does not exist or
+    /// if it is owned by a tenant, so that its values cannot bypass the quota and the rate limit
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut MerkleTree<H, S>> {
+        if self.owners.contains_key(name) {
+            return None;
+        }
+
+        self.trees.get_mut(name)
+    }
+
+    /// update the internal nodes of the merkle tree `name`, which does not change its values,
+    /// and get its root, e.g. for the merkle trees of tenants that `get_mut` does not hand out
+    ///
+    /// fail if there is no merkle tree with this name or if the internal nodes cannot be updated
+    pub fn update_root(&mut self, name: &str) -> Result<NodeHash, MerkleTreeError> {
+        self.trees
+            .get_mut(name)
+            .ok_or_else(|| MerkleTreeError::UnknownTree(name.to_string()))?
+            .root()
+    }
+
+    /// delete the merkle tree `name`, keeping its store for the next created merkle tree,
+    /// and return whether it existed
+    pub fn delete(&mut self, name: &str) -> bool {
+        let Some(mt) = self.trees.remove(name) else {
+            return false;
+        };
+        if let Some(owner) = self.owners.remove(name) {
+            if let Some(tenant) = self.tenants.get_mut(&owner) {
+                tenant.trees.remove(name);
+            }
+        }
+
+        let mut store = mt.nodes;
+        store.reset(0);
+        self.pool.push(store);
+
+        true
+    }
+
+    /// iterate over the `(name, merkle tree)` pairs, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &MerkleTree<H, S>)> {
+        self.trees.iter().map(|(name, mt)| (name.as_str(), mt))
+    }
+
+    /// the number of merkle trees
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::basic_hash;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::HashFunction;
+
+    fn builder(height: usize) -> MerkleTreeBuilder<HashFunction> {
+        MerkleTreeBuilder::new(basic_hash as HashFunction, height)
+    }
+
+    #[test]
+    fn create_get_delete() {
+        let mut manager = TreeManager::new();
+
+        manager
+            .create("b", builder(1))
+            .unwrap()
+            .insert("1")
+            .unwrap();
+        manager.create("a", builder(2)).unwrap();
+        assert_eq!(
+            Some(MerkleTreeError::TreeExists("a".to_string())),
+            manager.create("a", builder(1)).err()
+        );
+
+        // every merkle tree keeps its own options and values
+        assert_eq!(2, manager.get("a").unwrap().height());
+        assert_eq!(1, manager.get("b").unwrap().len());
+        manager.get_mut("a").unwrap().insert("2").unwrap();
+        assert_eq!(
+            vec![("a", 1), ("b", 1)],
+            manager
+                .iter()
+                .map(|(name, mt)| (name, mt.len()))
+                .collect::<Vec<_>>()
+        );
+
+        assert!(manager.delete("a"));
+        assert!(!manager.delete("a"));
+        assert!(manager.get("a").is_none());
+        assert_eq!(1, manager.len());
+    }
+
+    #[test]
+    fn deleted_stores_are_reused() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&created);
+        let mut manager = TreeManager::with_store(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+            MemoryStore::default()
+        });
+
+        manager
+            .create("a", builder(2))
+            .unwrap()
+            .insert("1")
+            .unwrap();
+        manager.delete("a");
+        let mt = manager.create("b", builder(1)).unwrap();
+
+        // the store of `a` backs `b`, without any of the nodes of `a`
+        assert_eq!(1, created.load(Ordering::Relaxed));
+        mt.update_internal_nodes().unwrap();
+        assert_eq!(mt.empty_root(), mt.get_root().unwrap());
+        assert_eq!(0, mt.export_nodes().count());
+
+        // the store of a merkle tree that cannot be built goes back to the pool
+        assert_eq!(
+            Some(MerkleTreeError::InvalidHeight(0)),
+            manager.create("c", builder(0)).err()
+        );
+        assert_eq!(
+            Some(MerkleTreeError::MemoryBudgetExceeded(1)),
+            manager.create("c", builder(2).memory_budget(1)).err()
+        );
+        manager.create("c", builder(2)).unwrap();
+        assert_eq!(2, created.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn managers_can_be_sent_to_other_threads() {
+        let mut manager = TreeManager::new();
+        manager.create("a", builder(1)).unwrap();
+        manager.set_rate_limiter(|_, _| true);
+
+        let manager = std::thread::spawn(move || manager).join().unwrap();
+        assert_eq!(1, manager.len());
+    }
+
+    #[test]
+    fn quotas_are_enforced() {
+        let mut manager = TreeManager::new();
+        manager.set_quota(
+            "tenant",
+            Quota {
+                max_trees: Some(2),
+                max_leaves: Some(3),
+            },
+        );
+
+        manager.create_for("tenant", "a", builder(2)).unwrap();
+        manager.create_for("tenant", "b", builder(2)).unwrap();
+        assert_eq!(
+            Some(MerkleTreeError::TreeQuotaExceeded("tenant".to_string())),
+            manager.create_for("tenant", "c", builder(2)).err()
+        );
+
+        // the values are counted across all the merkle trees of the tenant
+        manager.insert("a", "1").unwrap();
+        manager.insert("a", "2").unwrap();
+        manager.insert("b", "3").unwrap();
+        assert_eq!(
+            Err(MerkleTreeError::LeafQuotaExceeded("tenant".to_string())),
+            manager.insert("b", "4")
+        );
+
+        // the merkle trees of the tenant cannot be modified around the quota,
+        // but their roots can still be computed
+        assert!(manager.get_mut("b").is_none());
+        let mut expected = MerkleTree::from_height(basic_hash as HashFunction, 2).unwrap();
+        expected.insert("3").unwrap();
+        assert_eq!(expected.root(), manager.update_root("b"));
+        assert_eq!(
+            Err(MerkleTreeError::UnknownTree("missing".to_string())),
+            manager.update_root("missing")
+        );
+
+        // deleting a merkle tree frees its share of the quota
+        manager.delete("a");
+        manager.create_for("tenant", "c", builder(2)).unwrap();
+        manager.insert("c", "4").unwrap();
+
+        // merkle trees without a tenant are unlimited
+        manager.create("free", builder(2)).unwrap();
+        for value in ["1", "2", "3", "4"] {
+            manager.insert("free", value).unwrap();
+        }
+        assert_eq!(
+            Err(MerkleTreeError::UnknownTree("missing".to_string())),
+            manager.insert("missing", "1")
+        );
+    }
+
+    #[test]
+    fn rate_limiter_is_consulted() {
+        let mut manager = TreeManager::new();
+        manager.create_for("limited", "a", builder(2)).unwrap();
+        manager.create_for("unlimited", "b", builder(2)).unwrap();
+
+        let remaining = Arc::new(AtomicUsize::new(1));
+        let tokens = Arc::clone(&remaining);
+        manager.set_rate_limiter(move |tenant, _| {
+            if tenant == "unlimited" {
+                return true;
+            }
+            tokens
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |tokens| {
+                    tokens.checked_sub(1)
+                })
+                .is_ok()
+        });
+
+        manager.insert("a", "1").unwrap();
+        assert_eq!(
+            Err(MerkleTreeError::RateLimited("limited".to_string())),
+            manager.insert("a", "2")
+        );
+        manager.insert("b", "1").unwrap();
+        manager.insert("b", "2").unwrap();
+        assert_eq!(1, manager.get("a").unwrap().len());
+
+        // insertions beyond the quota are refused before the rate limiter is consulted
+        manager.set_quota(
+            "limited",
+            Quota {
+                max_trees: None,
+                max_leaves: Some(1),
+            },
+        );
+        remaining.store(1, Ordering::Relaxed);
+        assert_eq!(
+            Err(MerkleTreeError::LeafQuotaExceeded("limited".to_string())),
+            manager.insert("a", "2")
+        );
+        assert_eq!(1, remaining.load(Ordering::Relaxed));
+    }
+}
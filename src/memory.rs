@@ -0,0 +1,189 @@
+//! accounting of the memory held by merkle trees
+//!
+//! a merkle tree built with a memory budget refuses to grow past it,
+//! so that many merkle trees can share a process without exhausting its memory,
+//! and `MerkleTree::plan` estimates the resources of a merkle tree before it is built
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use crate::store::NodeStore;
+use crate::{Hasher, MerkleTree, MerkleTreeError, Node, MAX_HEIGHT};
+
+/// the resources needed by a merkle tree holding a given number of values in memory
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Plan {
+    /// the height of the smallest merkle tree holding all the values, as built by `from_leaves`
+    pub height: usize,
+    /// the number of nodes stored once the values are inserted and the internal nodes computed
+    pub node_count: usize,
+    /// the estimated number of bytes held in memory at this point, see `memory_usage`
+    pub memory_usage: usize,
+    /// the number of bytes of the sibling hashes of an inclusion proof
+    pub proof_size: usize,
+}
+
+impl MerkleTree {
+    /// estimate the resources needed by a merkle tree of `value_count` values
+    /// whose hashes are `digest_len` bytes long, kept in a `MemoryStore`,
+    /// without the ids of `insert_with_id` nor the heads
+    ///
+    /// fail if no merkle tree is high enough to hold the values
+    pub fn plan(value_count: usize, digest_len: usize) -> Result<Plan, MerkleTreeError> {
+        let height = value_count
+            .checked_next_power_of_two()
+            .map_or(usize::BITS as usize, |count| count.ilog2() as usize)
+            .max(1);
+        if height > MAX_HEIGHT {
+            return Err(MerkleTreeError::InvalidHeight(height));
+        }
+
+        // the leaves and, on each level, the internal nodes above them,
+        // as the empty subtrees are not stored
+        let internal_node_count: usize = (0..height)
+            .map(|level| value_count.div_ceil(1 << (height - level)).max(1))
+            .sum();
+        let node_count = value_count + internal_node_count;
+
+        let levels = (height + 1) * size_of::<Vec<Option<Node>>>();
+        let nodes = node_count * (size_of::<Option<Node>>() + digest_len);
+        let empty_hashes = (height + 1) * digest_len;
+        let dirty_leaves = value_count * size_of::<usize>();
+
+        Ok(Plan {
+            height,
+            node_count,
+            memory_usage: size_of::<Self>() + levels + nodes + empty_hashes + dirty_leaves,
+            proof_size: height * digest_len,
+        })
+    }
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// estimate the number of bytes held in memory by the merkle tree,
+    /// i.e. the tree itself, its nodes and their hashes, the hashes of the empty subtrees,
+    /// the changed leaves, the ids of the values and the heads
+    ///
+    /// the estimate of the nodes is left to the `NodeStore`, and the estimate of the ids
+    /// is kept up to date as they are inserted, so that the memory budget can be checked
+    /// on every insertion without visiting every node nor every id
+    pub fn memory_usage(&self) -> usize {
+        let nodes = self.nodes.memory_usage();
+        let empty_hashes: usize = self.empty_hashes.iter().map(Vec::capacity).sum();
+        let dirty_leaves = self.dirty_leaves.capacity() * size_of::<usize>();
+        let ids = self.ids_memory;
+        let heads: usize = [&self.prepared_head, &self.published_head]
+            .into_iter()
+            .flatten()
+            .map(|head| head.root.0.capacity())
+            .sum();
+
+        size_of::<Self>() + nodes + empty_hashes + dirty_leaves + ids + heads
+    }
+
+    /// get the maximum number of bytes the merkle tree may hold, if any
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    /// check that the merkle tree can hold `additional` bytes without exceeding its budget
+    ///
+    /// fail if the memory budget would be exceeded
+    pub(crate) fn reserve_memory(&self, additional: usize) -> Result<(), MerkleTreeError> {
+        match self.memory_budget {
+            Some(budget) if self.memory_usage() + additional > budget => {
+                Err(MerkleTreeError::MemoryBudgetExceeded(budget))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// estimate the number of bytes held for an `id` of `insert_with_id`
+    pub(crate) fn id_memory_usage(id: &str) -> usize {
+        size_of::<(String, usize)>() + id.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{basic_hash, short_hash};
+    use crate::{HashFunction, MerkleTreeBuilder};
+    use alloc::string::ToString;
+
+    #[test]
+    fn memory_usage_grows_with_values() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+        let empty = mt.memory_usage();
+
+        mt.insert("1").unwrap();
+        let one_value = mt.memory_usage();
+
+        mt.insert_with_id("id".to_string(), "2").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert!(empty < one_value);
+        assert!(one_value < mt.memory_usage());
+    }
+
+    #[test]
+    fn insert_past_memory_budget() {
+        let empty_usage = MerkleTree::from_height(basic_hash, 2)
+            .unwrap()
+            .memory_usage();
+        let budget = empty_usage + basic_hash(b"1").capacity();
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .memory_budget(budget)
+            .build()
+            .unwrap();
+
+        // only the hash of the first value fits in the budget
+        mt.insert("1").unwrap();
+
+        assert_eq!(
+            Err(MerkleTreeError::MemoryBudgetExceeded(budget)),
+            mt.insert("2")
+        );
+        assert_eq!(1, mt.length);
+        assert_eq!(
+            Err(MerkleTreeError::MemoryBudgetExceeded(budget)),
+            mt.update_internal_nodes()
+        );
+    }
+
+    #[test]
+    fn plan_matches_built_tree() {
+        let values: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        let mt = MerkleTree::from_leaves(short_hash as HashFunction, &values).unwrap();
+
+        let plan = MerkleTree::plan(values.len(), 8).unwrap();
+
+        assert_eq!(mt.height, plan.height);
+        assert_eq!(mt.export_nodes().count(), plan.node_count);
+        assert_eq!(mt.prove(0).unwrap().steps.len() * 8, plan.proof_size);
+        // the vectors of the built tree grow past their length
+        assert!(plan.memory_usage <= mt.memory_usage());
+        assert!(plan.memory_usage * 2 > mt.memory_usage());
+    }
+
+    #[test]
+    fn plan_too_many_values() {
+        assert_eq!(1, MerkleTree::plan(0, 32).unwrap().height);
+        assert_eq!(
+            Err(MerkleTreeError::InvalidHeight(64)),
+            MerkleTree::plan(usize::MAX, 32)
+        );
+    }
+
+    #[test]
+    fn build_past_memory_budget() {
+        assert_eq!(
+            Some(MerkleTreeError::MemoryBudgetExceeded(16)),
+            MerkleTreeBuilder::new(basic_hash, 2)
+                .memory_budget(16)
+                .build()
+                .err()
+        );
+    }
+}
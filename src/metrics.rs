@@ -0,0 +1,210 @@
+//! opt-in metrics of merkle trees, exported in the Prometheus text format
+//!
+//! a merkle tree built with `collect_metrics` counts the proofs it serves and remembers
+//! when its root was last computed, so that a proof service can expose them to its monitoring,
+//! e.g. by serving `Metrics::to_prometheus` on its metrics endpoint
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::head::TreeId;
+use crate::store::NodeStore;
+use crate::{Hasher, MerkleTree};
+
+/// the state of a merkle tree at the time its metrics were read
+#[derive(Clone, PartialEq, Debug)]
+pub struct Metrics {
+    pub tree_id: TreeId,
+    /// the number of inserted values
+    pub leaf_count: usize,
+    /// the time elapsed since the internal nodes were last updated,
+    /// or `None` if they never were
+    pub root_age: Option<Duration>,
+    /// the number of proofs generated, single, multiple or consistency proofs alike
+    pub proofs_served: u64,
+    /// the number of internal nodes to recompute before the root is up to date
+    pub stale_nodes: usize,
+}
+
+impl Metrics {
+    /// render the metrics in the Prometheus text exposition format,
+    /// labelled with the id of the merkle tree
+    pub fn to_prometheus(&self) -> String {
+        let root_age = self
+            .root_age
+            .map_or("NaN".to_string(), |age| age.as_secs_f64().to_string());
+        let metrics = [
+            (
+                "merkle_tree_leaves",
+                "gauge",
+                "The number of inserted values.",
+                self.leaf_count.to_string(),
+            ),
+            (
+                "merkle_tree_root_age_seconds",
+                "gauge",
+                "The time elapsed since the root was last computed.",
+                root_age,
+            ),
+            (
+                "merkle_tree_proofs_served_total",
+                "counter",
+                "The number of proofs generated.",
+                self.proofs_served.to_string(),
+            ),
+            (
+                "merkle_tree_stale_nodes",
+                "gauge",
+                "The number of internal nodes to recompute before the root is up to date.",
+                self.stale_nodes.to_string(),
+            ),
+        ];
+
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            // writing to a `String` cannot fail
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            let _ = writeln!(text, "{}{{tree_id=\"{}\"}} {}", name, self.tree_id, value);
+        }
+
+        text
+    }
+}
+
+/// the counters of a merkle tree, updated through shared references as proofs are generated
+#[derive(Debug, Default)]
+pub(crate) struct MetricsCollector {
+    proofs_served: AtomicU64,
+    root_updated_at: Mutex<Option<Instant>>,
+}
+
+impl MetricsCollector {
+    fn root_updated_at(&self) -> Option<Instant> {
+        // the instant stays meaningful even if a recording panicked
+        *self
+            .root_updated_at
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl Clone for MetricsCollector {
+    fn clone(&self) -> Self {
+        MetricsCollector {
+            proofs_served: AtomicU64::new(self.proofs_served.load(Ordering::Relaxed)),
+            root_updated_at: Mutex::new(self.root_updated_at()),
+        }
+    }
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// read the current metrics,
+    /// or `None` if the merkle tree was not built with `collect_metrics`
+    pub fn metrics(&self) -> Option<Metrics> {
+        let collector = self.metrics.as_ref()?;
+
+        Some(Metrics {
+            tree_id: self.tree_id,
+            leaf_count: self.length,
+            root_age: collector.root_updated_at().map(|instant| instant.elapsed()),
+            proofs_served: collector.proofs_served.load(Ordering::Relaxed),
+            stale_nodes: self.stale_node_count(),
+        })
+    }
+
+    /// count a served proof if metrics are collected
+    pub(crate) fn record_proof(&self) {
+        if let Some(collector) = &self.metrics {
+            collector.proofs_served.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// remember that the internal nodes were just updated if metrics are collected
+    pub(crate) fn record_root_update(&self) {
+        if let Some(collector) = &self.metrics {
+            *collector
+                .root_updated_at
+                .lock()
+                .unwrap_or_else(|error| error.into_inner()) = Some(Instant::now());
+        }
+    }
+
+    /// count the internal nodes `update_internal_nodes` would recompute
+    fn stale_node_count(&self) -> usize {
+        let candidates = if self.fully_stale {
+            Self::populated_internal_node_indices(self.height, self.length).collect()
+        } else {
+            Self::ancestor_indices(self.height, &self.dirty_leaves)
+        };
+
+        candidates
+            .into_iter()
+            .filter(|&index| !self.nodes.has_hash(index))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::MerkleTreeBuilder;
+
+    #[test]
+    fn metrics_not_collected() {
+        let mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+
+        assert_eq!(None, mt.metrics());
+    }
+
+    #[test]
+    fn metrics_follow_the_merkle_tree() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .tree_id(TreeId(42))
+            .collect_metrics(true)
+            .build()
+            .unwrap();
+
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+
+        let metrics = mt.metrics().unwrap();
+        assert_eq!(2, metrics.leaf_count);
+        assert_eq!(None, metrics.root_age);
+        // the parent of both leaves and the root
+        assert_eq!(2, metrics.stale_nodes);
+
+        mt.update_internal_nodes().unwrap();
+        mt.prove(0).unwrap();
+        mt.prove_many(&[0, 1]).unwrap();
+        mt.insert("3").unwrap();
+
+        let metrics = mt.metrics().unwrap();
+        assert_eq!(3, metrics.leaf_count);
+        assert!(metrics.root_age.is_some());
+        assert_eq!(2, metrics.proofs_served);
+        assert_eq!(2, metrics.stale_nodes);
+    }
+
+    #[test]
+    fn to_prometheus() {
+        let metrics = Metrics {
+            tree_id: TreeId(42),
+            leaf_count: 3,
+            root_age: None,
+            proofs_served: 7,
+            stale_nodes: 0,
+        };
+
+        let text = metrics.to_prometheus();
+
+        assert!(text.contains("# TYPE merkle_tree_proofs_served_total counter\n"));
+        assert!(text.contains("merkle_tree_proofs_served_total{tree_id=\"000000000000002a\"} 7\n"));
+        assert!(text.contains("merkle_tree_leaves{tree_id=\"000000000000002a\"} 3\n"));
+        assert!(text.contains("merkle_tree_root_age_seconds{tree_id=\"000000000000002a\"} NaN\n"));
+        assert_eq!(12, text.lines().count());
+    }
+}
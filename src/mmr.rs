@@ -0,0 +1,260 @@
+//! merkle mountain ranges, i.e. append-only merkle trees without a fixed height
+//!
+//! the values form a list of perfect merkle trees of decreasing heights, the peaks,
+//! one for each bit set in the number of values; appending a value merges the peaks of equal height
+//! and the root bags the peaks from right to left, as the node hash of a peak and the bag on its right
+
+use crate::{Hash, HashFunction, Hasher, LeafHash, MerkleTreeError, NodeHash};
+use alloc::vec::Vec;
+
+/// an append-only merkle tree growing without bounds
+#[derive(Clone)]
+pub struct MmrTree<H = HashFunction> {
+    hasher: H,
+    /// `levels[level][i]` is the root of the perfect subtree of the values
+    /// from `i * 2^level` to `(i + 1) * 2^level - 1`, so `levels[0]` holds the leaves
+    levels: Vec<Vec<Hash>>,
+}
+
+/// a proof that a value is included in a merkle mountain range
+#[derive(Clone, PartialEq, Debug)]
+pub struct MmrProof {
+    pub value_index: usize,
+    /// the number of values of the merkle mountain range, which defines its peaks
+    pub length: usize,
+    /// the siblings from the leaf up to the children of the peak above it
+    pub siblings: Vec<Hash>,
+    /// the other peaks, from left to right
+    pub peaks: Vec<Hash>,
+}
+
+/// get the heights of the peaks of a merkle mountain range of `length` values, from left to right
+fn peak_heights(length: usize) -> impl Iterator<Item = usize> {
+    (0..usize::BITS as usize)
+        .rev()
+        .filter(move |height| length >> height & 1 == 1)
+}
+
+/// bag the `peaks` from right to left, as the node hash of each peak and the bag on its right
+fn bag_peaks(hasher: &impl Hasher, peaks: &[Hash]) -> Option<Hash> {
+    let (last, rest) = peaks.split_last()?;
+
+    Some(
+        rest.iter()
+            .rev()
+            .fold(last.clone(), |bag, peak| hasher.hash_nodes(peak, &bag)),
+    )
+}
+
+impl MmrProof {
+    /// check that the leaf with hash `leaf_hash` is included in the merkle mountain range
+    /// with root `root`, without needing the merkle mountain range itself
+    pub fn verify(&self, leaf_hash: &LeafHash, root: &NodeHash, hasher: &impl Hasher) -> bool {
+        if self.value_index >= self.length {
+            return false;
+        }
+
+        // find the peak above the value and the position of the value below this peak
+        let mut first_value_index = 0;
+        let Some((position, height)) = peak_heights(self.length).enumerate().find(|(_, height)| {
+            first_value_index += 1 << height;
+            self.value_index < first_value_index
+        }) else {
+            return false;
+        };
+        let local_index = self.value_index - (first_value_index - (1 << height));
+
+        if self.siblings.len() != height
+            || self.peaks.len() != peak_heights(self.length).count() - 1
+        {
+            return false;
+        }
+
+        let peak =
+            self.siblings
+                .iter()
+                .enumerate()
+                .fold(leaf_hash.0.clone(), |hash, (level, sibling)| {
+                    if local_index >> level & 1 == 1 {
+                        hasher.hash_nodes(sibling, &hash)
+                    } else {
+                        hasher.hash_nodes(&hash, sibling)
+                    }
+                });
+
+        let mut peaks = self.peaks.clone();
+        peaks.insert(position, peak);
+
+        bag_peaks(hasher, &peaks).as_ref() == Some(&root.0)
+    }
+}
+
+impl<H: Hasher> MmrTree<H> {
+    /// create an empty merkle mountain range with the provided `hasher`
+    pub fn new(hasher: H) -> Self {
+        MmrTree {
+            hasher,
+            levels: Vec::new(),
+        }
+    }
+
+    /// the number of values appended
+    pub fn len(&self) -> usize {
+        self.levels.first().map_or(0, Vec::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// append a value, merging the peaks of equal height, and return its index
+    pub fn append(&mut self, value: impl AsRef<[u8]>) -> usize {
+        let value_index = self.len();
+        let mut hash = self.hasher.hash_leaf(value.as_ref());
+
+        for level in 0.. {
+            if self.levels.len() == level {
+                self.levels.push(Vec::new());
+            }
+
+            let nodes = &mut self.levels[level];
+            nodes.push(hash);
+
+            // an odd number of nodes leaves the last one as a peak
+            if nodes.len() % 2 == 1 {
+                break;
+            }
+            hash = self
+                .hasher
+                .hash_nodes(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+        }
+
+        value_index
+    }
+
+    /// get the hash of the `value_index`'th value
+    ///
+    /// fail if the value index is out of bounds
+    pub fn get_value(&self, value_index: usize) -> Result<LeafHash, MerkleTreeError> {
+        self.levels
+            .first()
+            .and_then(|leaves| leaves.get(value_index))
+            .map(|hash| LeafHash(hash.clone()))
+            .ok_or(MerkleTreeError::ValueOutOfBounds(value_index))
+    }
+
+    /// get the hashes of the peaks, from left to right
+    pub fn peaks(&self) -> Vec<Hash> {
+        let mut first_value_index = 0;
+
+        peak_heights(self.len())
+            .map(|height| {
+                let peak = self.levels[height][first_value_index >> height].clone();
+                first_value_index += 1 << height;
+
+                peak
+            })
+            .collect()
+    }
+
+    /// get the root hash, bagging the peaks, or `None` if no value was appended yet
+    pub fn get_root(&self) -> Option<NodeHash> {
+        bag_peaks(&self.hasher, &self.peaks()).map(NodeHash)
+    }
+
+    /// generate the inclusion proof of the `value_index`'th value
+    ///
+    /// fail if the value index is out of bounds
+    pub fn prove(&self, value_index: usize) -> Result<MmrProof, MerkleTreeError> {
+        if value_index >= self.len() {
+            return Err(MerkleTreeError::ValueOutOfBounds(value_index));
+        }
+
+        let mut peaks = self.peaks();
+        let mut first_value_index = 0;
+        for (position, height) in peak_heights(self.len()).enumerate() {
+            if value_index < first_value_index + (1 << height) {
+                peaks.remove(position);
+
+                // the siblings are complete below a peak, so they are all stored
+                let siblings = (0..height)
+                    .map(|level| self.levels[level][(value_index >> level) ^ 1].clone())
+                    .collect();
+
+                return Ok(MmrProof {
+                    value_index,
+                    length: self.len(),
+                    siblings,
+                    peaks,
+                });
+            }
+            first_value_index += 1 << height;
+        }
+
+        unreachable!("The peaks cover all the values.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use alloc::vec;
+
+    fn mmr_of(values: &[&str]) -> MmrTree {
+        let mut mmr = MmrTree::new(basic_hash as HashFunction);
+        for value in values {
+            mmr.append(value);
+        }
+
+        mmr
+    }
+
+    #[test]
+    fn append_merges_peaks() {
+        let mmr = mmr_of(&["a", "b", "c", "d", "e", "f", "g"]);
+
+        assert_eq!(7, mmr.len());
+        assert_eq!(
+            vec![
+                b"H(H(H(a)H(b))H(H(c)H(d)))".to_vec(),
+                b"H(H(e)H(f))".to_vec(),
+                b"H(g)".to_vec(),
+            ],
+            mmr.peaks()
+        );
+        assert_eq!(
+            Some(NodeHash(
+                b"H(H(H(H(a)H(b))H(H(c)H(d)))H(H(H(e)H(f))H(g)))".to_vec()
+            )),
+            mmr.get_root()
+        );
+    }
+
+    #[test]
+    fn empty_mmr_has_no_root() {
+        let mmr = mmr_of(&[]);
+
+        assert!(mmr.is_empty());
+        assert_eq!(None, mmr.get_root());
+        assert_eq!(Err(MerkleTreeError::ValueOutOfBounds(0)), mmr.prove(0));
+    }
+
+    #[test]
+    fn prove_all_values() {
+        let values = ["a", "b", "c", "d", "e", "f", "g"];
+
+        for length in 1..=values.len() {
+            let mmr = mmr_of(&values[..length]);
+            let root = mmr.get_root().unwrap();
+
+            for value_index in 0..length {
+                let proof = mmr.prove(value_index).unwrap();
+                let leaf_hash = mmr.get_value(value_index).unwrap();
+
+                assert!(proof.verify(&leaf_hash, &root, &basic_hash));
+                assert!(!proof.verify(&LeafHash(b"H(x)".to_vec()), &root, &basic_hash));
+            }
+        }
+    }
+}
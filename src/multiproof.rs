@@ -0,0 +1,260 @@
+//! inclusion proofs of several values at once
+//!
+//! a multiproof only contains the hashes of the nodes that cannot be computed
+//! from the proven leaves, so the ancestors shared by the leaves are not repeated
+//! as they would be in as many single proofs
+
+use crate::head::TreeId;
+use crate::store::NodeStore;
+use crate::{Hash, Hasher, LeafHash, MerkleTree, MerkleTreeError, NodeHash, PairOrder, MAX_HEIGHT};
+use alloc::vec::Vec;
+
+/// a proof that several values are included in a merkle tree
+#[derive(Clone, PartialEq, Debug)]
+pub struct MultiProof {
+    /// the id of the merkle tree the proof was generated from
+    pub tree_id: TreeId,
+    pub height: usize,
+    /// the indices of the proven values, sorted and without duplicates
+    pub value_indices: Vec<usize>,
+    /// how the merkle tree orders the hashes of two children
+    pub pair_order: PairOrder,
+    /// the hashes of the nodes needed to compute the root from the proven leaves,
+    /// level by level from the leaves up to the children of the root
+    /// and in ascending order within a level
+    pub hashes: Vec<Hash>,
+}
+
+impl MultiProof {
+    /// check that the leaves with hashes `leaf_hashes`, given in the order of `value_indices`,
+    /// are included in the merkle tree with root `root`, without needing the merkle tree itself
+    pub fn verify(&self, leaf_hashes: &[LeafHash], root: &NodeHash, hasher: &impl Hasher) -> bool {
+        if self.height > MAX_HEIGHT
+            || self.value_indices.is_empty()
+            || leaf_hashes.len() != self.value_indices.len()
+        {
+            return false;
+        }
+
+        // the indices must be within the leaves, sorted and without duplicates,
+        // as the nodes of each level are paired with their next neighbour only
+        let first_leaf_node_index = 1 << self.height;
+        if self
+            .value_indices
+            .iter()
+            .any(|&value_index| value_index >= first_leaf_node_index)
+            || self.value_indices.windows(2).any(|pair| pair[0] >= pair[1])
+        {
+            return false;
+        }
+
+        let mut level: Vec<(usize, Hash)> = self
+            .value_indices
+            .iter()
+            .zip(leaf_hashes)
+            .map(|(value_index, leaf_hash)| {
+                (first_leaf_node_index + value_index, leaf_hash.0.clone())
+            })
+            .collect();
+        let mut hashes = self.hashes.iter();
+
+        for _ in 0..self.height {
+            let mut parents = Vec::with_capacity(level.len());
+            let mut nodes = level.into_iter().peekable();
+
+            while let Some((index, hash)) = nodes.next() {
+                // the sibling is either the next proven node or taken from the proof
+                let sibling_hash = match nodes.peek() {
+                    Some((next_index, _)) if index % 2 == 0 && *next_index == index + 1 => {
+                        nodes.next().map(|(_, hash)| hash)
+                    }
+                    _ => hashes.next().cloned(),
+                };
+                let Some(sibling_hash) = sibling_hash else {
+                    return false;
+                };
+
+                let (left, right) = if index % 2 == 0 {
+                    (&hash, &sibling_hash)
+                } else {
+                    (&sibling_hash, &hash)
+                };
+                let (first, second) = self.pair_order.arrange(left, right);
+
+                parents.push((index / 2, hasher.hash_nodes(first, second)));
+            }
+
+            level = parents;
+        }
+
+        hashes.next().is_none() && level == [(1, root.0.clone())]
+    }
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// generate a single proof of the inclusion of the values at `value_indices`
+    ///
+    /// fail if a value index is out of bounds,
+    /// if the internal nodes are not up to date or,
+    /// in `strict` mode, if no value was inserted at one of the `value_indices` yet
+    pub fn prove_many(&self, value_indices: &[usize]) -> Result<MultiProof, MerkleTreeError> {
+        // check the bounds and the strict mode on the proven leaves themselves,
+        // and that the root is up to date for the proof to be valid
+        for &value_index in value_indices {
+            self.get_value(value_index)?;
+        }
+        self.get_root()?;
+
+        let mut value_indices = value_indices.to_vec();
+        value_indices.sort_unstable();
+        value_indices.dedup();
+
+        let mut level: Vec<usize> = value_indices
+            .iter()
+            .map(|value_index| self.first_leaf_node_index + value_index)
+            .collect();
+        let mut siblings = Vec::new();
+
+        for _ in 0..self.height {
+            for (position, &index) in level.iter().enumerate() {
+                let sibling_index = index ^ 1;
+
+                // the siblings within the proven nodes are computed by the verifier
+                let known = if sibling_index > index {
+                    level.get(position + 1) == Some(&sibling_index)
+                } else {
+                    position > 0 && level[position - 1] == sibling_index
+                };
+                if !known {
+                    siblings.push(sibling_index);
+                }
+            }
+
+            // dividing by 2 keeps the indices sorted, so duplicates are adjacent
+            level.iter_mut().for_each(|index| *index /= 2);
+            level.dedup();
+        }
+
+        self.nodes.prefetch(&siblings);
+        let hashes = siblings
+            .into_iter()
+            .map(|index| self.get_node_hash(index))
+            .collect::<Result<_, _>>()?;
+
+        self.record_proof();
+
+        Ok(MultiProof {
+            tree_id: self.tree_id,
+            height: self.height,
+            value_indices,
+            pair_order: self.pair_order,
+            hashes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::{HashFunction, MerkleTreeBuilder};
+    use alloc::vec;
+
+    fn tree_of(height: usize, values: &[&str]) -> MerkleTree {
+        let mut mt = MerkleTreeBuilder::new(basic_hash as HashFunction, height)
+            .tree_id(TreeId(7))
+            .build()
+            .unwrap();
+
+        for value in values {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+
+        mt
+    }
+
+    fn leaves(values: &[&str]) -> Vec<LeafHash> {
+        values
+            .iter()
+            .map(|value| LeafHash(basic_hash(value.as_bytes())))
+            .collect()
+    }
+
+    #[test]
+    fn prove_many_shares_ancestors() {
+        let mt = tree_of(3, &["a", "b", "c", "d", "e"]);
+
+        let proof = mt.prove_many(&[3, 0, 1, 3]).unwrap();
+
+        assert_eq!(vec![0, 1, 3], proof.value_indices);
+        // the sibling of d, then the right child of the root
+        assert_eq!(
+            vec![
+                b"H(c)".to_vec(),
+                b"H(H(H(e)H(empty node))H(H(empty node)H(empty node)))".to_vec(),
+            ],
+            proof.hashes
+        );
+
+        let root = mt.get_root().unwrap();
+        assert!(proof.verify(&leaves(&["a", "b", "d"]), &root, &basic_hash));
+        assert!(!proof.verify(&leaves(&["a", "b", "c"]), &root, &basic_hash));
+        assert!(!proof.verify(&leaves(&["a", "b"]), &root, &basic_hash));
+    }
+
+    #[test]
+    fn prove_many_single_value_matches_prove() {
+        let mt = tree_of(2, &["a", "b", "c"]);
+
+        let proof = mt.prove_many(&[2]).unwrap();
+
+        assert_eq!(mt.prove(2).unwrap().sibling_hashes(), proof.hashes);
+        assert!(proof.verify(&leaves(&["c"]), &mt.get_root().unwrap(), &basic_hash));
+    }
+
+    #[test]
+    fn prove_many_out_of_bounds() {
+        let mt = tree_of(1, &["a"]);
+
+        assert_eq!(
+            Err(MerkleTreeError::ValueOutOfBounds(2)),
+            mt.prove_many(&[0, 2])
+        );
+    }
+
+    #[test]
+    fn verify_rejects_extra_hashes() {
+        let mt = tree_of(2, &["a", "b"]);
+
+        let mut proof = mt.prove_many(&[0, 1]).unwrap();
+        proof.hashes.push(b"H(extra)".to_vec());
+
+        assert!(!proof.verify(&leaves(&["a", "b"]), &mt.get_root().unwrap(), &basic_hash));
+    }
+
+    #[test]
+    fn verify_rejects_invalid_indices() {
+        let mt = tree_of(2, &["a", "b", "c"]);
+        let root = mt.get_root().unwrap();
+        let proof = mt.prove_many(&[0, 2]).unwrap();
+
+        let with_indices = |value_indices: Vec<usize>| MultiProof {
+            value_indices,
+            ..proof.clone()
+        };
+
+        // out of the leaves, even when the node index would overflow
+        assert!(!with_indices(vec![0, 4]).verify(&leaves(&["a", "c"]), &root, &basic_hash));
+        assert!(!with_indices(vec![0, usize::MAX]).verify(
+            &leaves(&["a", "c"]),
+            &root,
+            &basic_hash
+        ));
+        // unsorted or repeated
+        assert!(!with_indices(vec![2, 0]).verify(&leaves(&["c", "a"]), &root, &basic_hash));
+        assert!(!with_indices(vec![0, 0]).verify(&leaves(&["a", "a"]), &root, &basic_hash));
+
+        assert!(proof.verify(&leaves(&["a", "c"]), &root, &basic_hash));
+    }
+}
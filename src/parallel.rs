@@ -0,0 +1,229 @@
+//! computation of the internal nodes on several threads
+//!
+//! the nodes of a level only depend on the level below, so each level is split
+//! between the available threads, which hash their share of the nodes independently,
+//! before the hashes are stored and the next level starts
+//!
+//! every node is hashed from its children alone, whichever thread hashes it,
+//! so the nodes and the root are bit-identical to those computed on a single thread,
+//! whatever the number of threads; the `parallel-check` feature also hashes every level
+//! on a single thread and panics if the hashes differ, e.g. to test a custom hasher
+
+use std::num::NonZeroUsize;
+use std::panic;
+use std::thread;
+
+use crate::store::NodeStore;
+use crate::{Hash, Hasher, MerkleTree, MerkleTreeError};
+
+/// the fewest nodes worth handing to another thread, below which spawning it costs more than hashing
+const MIN_NODES_PER_THREAD: usize = 256;
+
+impl<H: Hasher + Sync> MerkleTree<H> {
+    /// create a merkle tree like `from_leaves`, computing the internal nodes on several threads
+    ///
+    /// fail if the values cannot be inserted or the internal nodes cannot be computed
+    pub fn from_leaves_parallel(
+        hasher: H,
+        values: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<Self, MerkleTreeError> {
+        let mut mt = MerkleTree::with_leaves(hasher, values)?;
+        mt.rebuild_parallel()?;
+
+        Ok(mt)
+    }
+}
+
+impl<H: Hasher + Sync, S: NodeStore + Sync> MerkleTree<H, S> {
+    /// update the internal nodes like `update_internal_nodes`,
+    /// hashing the nodes of each level on several threads
+    ///
+    /// fail if an empty leaf is needed while empty leaves are forbidden or
+    /// if the computed nodes would exceed the memory budget
+    pub fn update_internal_nodes_parallel(&mut self) -> Result<(), MerkleTreeError> {
+        let traversal = self.start_traversal();
+
+        let candidates = if self.fully_stale {
+            Self::populated_internal_node_indices(self.height, self.length).collect()
+        } else {
+            Self::ancestor_indices(self.height, &self.dirty_leaves)
+        };
+        // only the nodes set to `None` are computed, and computing them sets no other node
+        let indices: Vec<usize> = candidates
+            .into_iter()
+            .filter(|&index| !self.nodes.has_hash(index))
+            .collect();
+
+        self.set_levels_in_parallel(&indices)?;
+        self.dirty_leaves.clear();
+        self.fully_stale = false;
+        self.finish_traversal(traversal);
+        self.record_root_update();
+
+        Ok(())
+    }
+
+    /// recompute all the internal nodes like `rebuild`, hashing the nodes of each level on several threads
+    ///
+    /// fail if an empty leaf is needed while empty leaves are forbidden or
+    /// if the computed nodes would exceed the memory budget
+    pub fn rebuild_parallel(&mut self) -> Result<(), MerkleTreeError> {
+        let traversal = self.start_traversal();
+
+        let indices: Vec<usize> =
+            Self::populated_internal_node_indices(self.height, self.length).collect();
+
+        self.set_levels_in_parallel(&indices)?;
+        self.dirty_leaves.clear();
+        self.fully_stale = false;
+        self.finish_traversal(traversal);
+        self.record_root_update();
+
+        Ok(())
+    }
+
+    /// compute and store the internal nodes at `indices`, given level by level
+    /// from the lowest one up to the root, one level at a time
+    fn set_levels_in_parallel(&mut self, indices: &[usize]) -> Result<(), MerkleTreeError> {
+        for level in indices.chunk_by(|a, b| a.ilog2() == b.ilog2()) {
+            let hashes = self.compute_node_hashes(level)?;
+
+            for (&index, hash) in level.iter().zip(hashes) {
+                let replaced = self.nodes.get_hash(index).map_or(0, |hash| hash.capacity());
+                self.reserve_memory(hash.capacity().saturating_sub(replaced))?;
+
+                self.nodes.set_hash(index, Some(hash));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// compute the hashes of the internal nodes at `indices` of a single level,
+    /// splitting them between the available threads
+    fn compute_node_hashes(&self, indices: &[usize]) -> Result<Vec<Hash>, MerkleTreeError> {
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let hashes = self.compute_node_hashes_on(indices, threads)?;
+
+        #[cfg(feature = "parallel-check")]
+        assert_eq!(
+            self.compute_node_hashes_on(indices, 1)?,
+            hashes,
+            "The hashes computed on {} threads differ from those computed on a single thread.",
+            threads
+        );
+
+        Ok(hashes)
+    }
+
+    /// compute the hashes of the internal nodes at `indices` of a single level,
+    /// splitting them between at most `threads` threads
+    fn compute_node_hashes_on(
+        &self,
+        indices: &[usize],
+        threads: usize,
+    ) -> Result<Vec<Hash>, MerkleTreeError> {
+        let chunk_size = indices.len().div_ceil(threads).max(MIN_NODES_PER_THREAD);
+
+        if indices.len() <= chunk_size {
+            return indices
+                .iter()
+                .map(|&index| self.compute_node_hash(index))
+                .collect();
+        }
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = indices
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|&index| self.compute_node_hash(index))
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                })
+                .collect();
+
+            let mut hashes = Vec::with_capacity(indices.len());
+            for handle in handles {
+                // a panicking hasher panics the caller as it would without threads
+                let chunk = handle
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload))?;
+                hashes.extend(chunk);
+            }
+
+            Ok(hashes)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::short_hash;
+    use crate::HashFunction;
+
+    fn values(count: usize) -> Vec<String> {
+        (0..count).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn from_leaves_parallel_matches_from_leaves() {
+        let values = values(3000);
+
+        let sequential = MerkleTree::from_leaves(short_hash as HashFunction, &values).unwrap();
+        let parallel =
+            MerkleTree::from_leaves_parallel(short_hash as HashFunction, &values).unwrap();
+
+        assert_eq!(sequential.get_root(), parallel.get_root());
+        assert_eq!(
+            sequential.export_nodes().collect::<Vec<_>>(),
+            parallel.export_nodes().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn node_hashes_do_not_depend_on_thread_count() {
+        let mt = MerkleTree::from_leaves(short_hash as HashFunction, values(3000)).unwrap();
+        let indices: Vec<usize> =
+            MerkleTree::<HashFunction>::populated_internal_node_indices(mt.height, mt.length)
+                .collect();
+
+        for level in indices.chunk_by(|a, b| a.ilog2() == b.ilog2()) {
+            let sequential: Vec<Hash> = level
+                .iter()
+                .map(|&index| mt.nodes.get_hash(index).unwrap())
+                .collect();
+
+            for threads in [1, 2, 3, 7, 16] {
+                assert_eq!(
+                    sequential,
+                    mt.compute_node_hashes_on(level, threads).unwrap(),
+                    "{} nodes on {} threads",
+                    level.len(),
+                    threads
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn update_internal_nodes_parallel_matches_update_internal_nodes() {
+        let mut sequential = MerkleTree::from_height(short_hash as HashFunction, 12).unwrap();
+        let mut parallel = MerkleTree::from_height(short_hash as HashFunction, 12).unwrap();
+
+        // a full computation, then an incremental one
+        for values in [values(2000), values(1500)] {
+            for value in &values {
+                sequential.insert(value).unwrap();
+                parallel.insert(value).unwrap();
+            }
+            sequential.update_internal_nodes().unwrap();
+            parallel.update_internal_nodes_parallel().unwrap();
+
+            assert_eq!(sequential.get_root(), parallel.get_root());
+        }
+    }
+}
@@ -0,0 +1,677 @@
+//! inclusion proofs of values in merkle trees
+//!
+//! a proof contains the hashes of the siblings of all the nodes
+//! on the path from a leaf up to the root
+
+use crate::head::TreeId;
+use crate::store::NodeStore;
+use crate::{Hash, Hasher, LeafHash, MerkleTree, MerkleTreeError, NodeHash, PairOrder};
+use alloc::vec::Vec;
+
+/// the side on which a sibling stands relative to the node on the path to the root
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Position {
+    Left,
+    Right,
+}
+
+impl Position {
+    /// get the position of the sibling at `level` on the path from the `value_index`'th leaf,
+    /// which the bits of the value index give as every merkle tree is perfect
+    pub fn of_sibling(value_index: usize, level: usize) -> Self {
+        // a node is a left child, with its sibling on its right, when its index is even
+        if value_index
+            .checked_shr(level as u32)
+            .unwrap_or(0)
+            .is_multiple_of(2)
+        {
+            Position::Right
+        } else {
+            Position::Left
+        }
+    }
+}
+
+/// how the positions of the siblings of a proof are transmitted
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Directions {
+    /// every step carries the position of its sibling
+    #[default]
+    Explicit,
+    /// only the hashes of the siblings are transmitted,
+    /// and their positions are derived from the value index when the proof is decoded
+    IndexDerived,
+}
+
+/// the sibling of a node on the path from a leaf to the root
+#[derive(Clone, PartialEq, Debug)]
+pub struct ProofStep {
+    pub position: Position,
+    /// the hash of the sibling, which is a leaf for the first step and an internal node otherwise
+    pub hash: Hash,
+}
+
+/// a proof that a value is included in a merkle tree
+#[derive(Clone, PartialEq, Debug)]
+pub struct MerkleProof {
+    /// the id of the merkle tree the proof was generated from
+    pub tree_id: TreeId,
+    pub value_index: usize,
+    /// how the merkle tree orders the hashes of two children,
+    /// the positions of the steps being irrelevant for `PairOrder::Sorted`
+    pub pair_order: PairOrder,
+    /// the siblings from the leaf up to the children of the root
+    pub steps: Vec<ProofStep>,
+}
+
+impl MerkleProof {
+    /// create the proof of the `value_index`'th value from the hashes of its siblings
+    /// from the leaf up to the children of the root, deriving their positions from the value index,
+    /// as in the ecosystems that only transmit the siblings
+    pub fn from_siblings(
+        tree_id: TreeId,
+        value_index: usize,
+        pair_order: PairOrder,
+        siblings: impl IntoIterator<Item = Hash>,
+    ) -> Self {
+        let steps = siblings
+            .into_iter()
+            .enumerate()
+            .map(|(level, hash)| ProofStep {
+                position: Position::of_sibling(value_index, level),
+                hash,
+            })
+            .collect();
+
+        MerkleProof {
+            tree_id,
+            value_index,
+            pair_order,
+            steps,
+        }
+    }
+
+    /// check that the leaf with hash `leaf_hash` is included in the merkle tree with root `root`,
+    /// without needing the merkle tree itself
+    ///
+    /// with `PairOrder::Positional`, the position of every step must also be the one given
+    /// by the bits of the value index, which must fit in as many bits as there are steps,
+    /// so that a proof cannot be replayed for another value index
+    pub fn verify(&self, leaf_hash: &LeafHash, root: &NodeHash, hasher: &impl Hasher) -> bool {
+        self.compute_root(leaf_hash, hasher)
+            .is_some_and(|computed_root| computed_root == root.0)
+    }
+
+    /// check the inclusion like `verify` against several candidate `roots`, e.g. the recently
+    /// observed heads of a merkle tree, and return the index of the first one that matches,
+    /// or `None` if none of them does
+    pub fn verify_any(
+        &self,
+        leaf_hash: &LeafHash,
+        roots: &[NodeHash],
+        hasher: &impl Hasher,
+    ) -> Option<usize> {
+        let computed_root = self.compute_root(leaf_hash, hasher)?;
+
+        roots.iter().position(|root| root.0 == computed_root)
+    }
+
+    /// whether the positions of the steps are the ones given by the bits of the value index,
+    /// which always holds with `PairOrder::Sorted` where the positions are irrelevant
+    fn directions_match_value_index(&self) -> bool {
+        if self.pair_order == PairOrder::Sorted {
+            return true;
+        }
+
+        let fits = self
+            .value_index
+            .checked_shr(self.steps.len() as u32)
+            .unwrap_or(0)
+            == 0;
+
+        fits && self
+            .steps
+            .iter()
+            .enumerate()
+            .all(|(level, step)| step.position == Position::of_sibling(self.value_index, level))
+    }
+
+    /// compute the root of the merkle tree from the leaf with hash `leaf_hash` and the siblings,
+    /// or `None` if the positions of the siblings do not match the value index
+    fn compute_root(&self, leaf_hash: &LeafHash, hasher: &impl Hasher) -> Option<Hash> {
+        if !self.directions_match_value_index() {
+            return None;
+        }
+
+        let root = self.steps.iter().fold(leaf_hash.0.clone(), |hash, step| {
+            // the hash of a node combines the hashes of its children
+            let (left, right) = match step.position {
+                Position::Left => (&step.hash, &hash),
+                Position::Right => (&hash, &step.hash),
+            };
+            let (first, second) = self.pair_order.arrange(left, right);
+
+            hasher.hash_nodes(first, second)
+        });
+
+        Some(root)
+    }
+
+    /// get the hashes of the siblings from the leaf up to the children of the root,
+    /// which are enough to verify a proof of a merkle tree with `PairOrder::Sorted`
+    pub fn sibling_hashes(&self) -> Vec<Hash> {
+        self.steps.iter().map(|step| step.hash.clone()).collect()
+    }
+
+    /// check the inclusion like `verify`, and also that the proof was generated from
+    /// the merkle tree identified by `tree_id`
+    pub fn verify_for_tree(
+        &self,
+        tree_id: TreeId,
+        leaf_hash: &LeafHash,
+        root: &NodeHash,
+        hasher: &impl Hasher,
+    ) -> bool {
+        self.tree_id == tree_id && self.verify(leaf_hash, root, hasher)
+    }
+}
+
+/// check that the leaf with hash `leaf_hash` is included in the merkle tree with root `root`
+pub fn verify_proof(
+    proof: &MerkleProof,
+    leaf_hash: &LeafHash,
+    root: &NodeHash,
+    hasher: &impl Hasher,
+) -> bool {
+    proof.verify(leaf_hash, root, hasher)
+}
+
+/// find which of the candidate `roots` the leaf with hash `leaf_hash` is included in,
+/// see `MerkleProof::verify_any`
+pub fn verify_any(
+    roots: &[NodeHash],
+    leaf_hash: &LeafHash,
+    proof: &MerkleProof,
+    hasher: &impl Hasher,
+) -> Option<usize> {
+    proof.verify_any(leaf_hash, roots, hasher)
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// generate the inclusion proof of the `value_index`'th value
+    ///
+    /// fail if the value index is out of bounds,
+    /// if the internal nodes are not up to date or,
+    /// in `strict` mode, if no value was inserted at `value_index` yet
+    pub fn prove(&self, value_index: usize) -> Result<MerkleProof, MerkleTreeError> {
+        // check the bounds and the strict mode on the proven leaf itself,
+        // and that the root is up to date for the proof to be valid
+        self.get_value(value_index)?;
+        self.get_root()?;
+
+        let mut index = self.first_leaf_node_index + value_index;
+        let mut steps = Vec::with_capacity(self.height);
+
+        let siblings: Vec<usize> = (0..self.height).map(|level| (index >> level) ^ 1).collect();
+        self.nodes.prefetch(&siblings);
+
+        while index > 1 {
+            steps.push(self.proof_step(index)?);
+
+            index /= 2;
+        }
+
+        self.record_proof();
+
+        Ok(MerkleProof {
+            tree_id: self.tree_id,
+            value_index,
+            pair_order: self.pair_order,
+            steps,
+        })
+    }
+
+    /// iterate over the inserted values with their inclusion proofs, in insertion order
+    ///
+    /// the proof of each value is derived from the proof of the previous one,
+    /// so only the steps below the lowest common ancestor of both leaves are recomputed
+    ///
+    /// fail if the internal nodes are not up to date,
+    /// and yield an error, then stop, if a node needed by a proof is missing
+    pub fn iter_with_proofs(&self) -> Result<ProofIter<'_, H, S>, MerkleTreeError> {
+        // once the root is up to date, every node needed by the proofs is available
+        self.get_root()?;
+
+        Ok(ProofIter {
+            tree: self,
+            value_index: 0,
+            steps: Vec::with_capacity(self.height),
+        })
+    }
+
+    /// get the sibling of the node at `index` as a step of a proof
+    ///
+    /// fail if the sibling is internal and `None` or an empty leaf that is forbidden
+    fn proof_step(&self, index: usize) -> Result<ProofStep, MerkleTreeError> {
+        // the sibling of a left child is on its right and vice versa
+        let (sibling_index, position) = if index.is_multiple_of(2) {
+            (index + 1, Position::Right)
+        } else {
+            (index - 1, Position::Left)
+        };
+
+        Ok(ProofStep {
+            position,
+            hash: self.get_node_hash(sibling_index)?,
+        })
+    }
+}
+
+/// an iterator over the `(value_index, leaf_hash, proof)` of the inserted values of a merkle tree,
+/// created with `iter_with_proofs`
+pub struct ProofIter<'a, H, S> {
+    tree: &'a MerkleTree<H, S>,
+    value_index: usize,
+    /// the steps of the proof of the previous value, empty before the first one
+    steps: Vec<ProofStep>,
+}
+
+impl<H: Hasher, S: NodeStore> ProofIter<'_, H, S> {
+    /// generate the proof of the `value_index`'th value from the proof of the previous one
+    ///
+    /// fail if a node on the path of the value is missing
+    fn next_proof(&mut self) -> Result<(usize, LeafHash, MerkleProof), MerkleTreeError> {
+        let value_index = self.value_index;
+        let leaf_index = self.tree.first_leaf_node_index + value_index;
+
+        for level in 0..self.tree.height {
+            let index = leaf_index >> level;
+
+            // above the lowest common ancestor of the previous leaf and this one,
+            // both paths to the root, and therefore their siblings, are the same
+            if level < self.steps.len() && index == (leaf_index - 1) >> level {
+                break;
+            }
+
+            let step = self.tree.proof_step(index)?;
+
+            if level < self.steps.len() {
+                self.steps[level] = step;
+            } else {
+                self.steps.push(step);
+            }
+        }
+
+        let leaf_hash = self.tree.get_value(value_index)?;
+
+        self.value_index += 1;
+
+        Ok((
+            value_index,
+            leaf_hash,
+            MerkleProof {
+                tree_id: self.tree.tree_id,
+                value_index,
+                pair_order: self.tree.pair_order,
+                steps: self.steps.clone(),
+            },
+        ))
+    }
+}
+
+impl<H: Hasher, S: NodeStore> Iterator for ProofIter<'_, H, S> {
+    type Item = Result<(usize, LeafHash, MerkleProof), MerkleTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.value_index >= self.tree.length {
+            return None;
+        }
+
+        let item = self.next_proof();
+        if item.is_err() {
+            // stop after the first error, as the following proofs need the same nodes
+            self.value_index = self.tree.length;
+        }
+
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.tree.length - self.value_index;
+
+        // an error stops the iteration early
+        (remaining.min(1), Some(remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{basic_hash, short_hash};
+    use crate::{HashFunction, MerkleTreeBuilder};
+    use alloc::vec;
+
+    fn tree_of(height: usize, values: &[&str]) -> MerkleTree {
+        let mut mt = MerkleTreeBuilder::new(basic_hash as HashFunction, height)
+            .tree_id(TreeId(7))
+            .build()
+            .unwrap();
+
+        for value in values {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+
+        mt
+    }
+
+    fn step(position: Position, hash: &[u8]) -> ProofStep {
+        ProofStep {
+            position,
+            hash: hash.to_vec(),
+        }
+    }
+
+    #[test]
+    fn height_one_prove() {
+        let mt = tree_of(1, &["1", "2"]);
+
+        assert_eq!(
+            Ok(MerkleProof {
+                tree_id: TreeId(7),
+                value_index: 0,
+                pair_order: PairOrder::Positional,
+                steps: vec![step(Position::Right, b"H(2)")],
+            }),
+            mt.prove(0)
+        );
+        assert_eq!(
+            Ok(MerkleProof {
+                tree_id: TreeId(7),
+                value_index: 1,
+                pair_order: PairOrder::Positional,
+                steps: vec![step(Position::Left, b"H(1)")],
+            }),
+            mt.prove(1)
+        );
+    }
+
+    #[test]
+    fn height_two_prove() {
+        let mt = tree_of(2, &["1", "2", "3"]);
+
+        assert_eq!(
+            vec![
+                step(Position::Right, b"H(empty node)"),
+                step(Position::Left, b"H(H(1)H(2))"),
+            ],
+            mt.prove(2).unwrap().steps
+        );
+    }
+
+    #[test]
+    fn prove_out_of_bounds() {
+        let mt = tree_of(1, &["1"]);
+
+        assert_eq!(Err(MerkleTreeError::ValueOutOfBounds(2)), mt.prove(2));
+    }
+
+    #[test]
+    fn prove_stale_tree() {
+        let mut mt = tree_of(2, &["1"]);
+        mt.insert("2").unwrap();
+
+        assert_eq!(Err(MerkleTreeError::StaleInternalNode(1)), mt.prove(2));
+    }
+
+    #[test]
+    fn prove_empty_tree() {
+        let mt = MerkleTree::from_height(basic_hash as HashFunction, 2).unwrap();
+        let mut updated = mt.clone();
+        updated.update_internal_nodes().unwrap();
+
+        // an empty merkle tree does not need to be updated to be proven
+        let proof = mt.prove(0).unwrap();
+        assert_eq!(updated.prove(0), Ok(proof.clone()));
+        assert!(proof.verify(
+            &LeafHash(b"H(empty node)".to_vec()),
+            &mt.get_root().unwrap(),
+            &basic_hash
+        ));
+    }
+
+    #[test]
+    fn strict_prove_never_inserted_value() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 1)
+            .strict(true)
+            .build()
+            .unwrap();
+        mt.insert("1").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(Err(MerkleTreeError::NeverInserted(1)), mt.prove(1));
+    }
+
+    #[test]
+    fn verify_every_value() {
+        let mt = tree_of(3, &["1", "2", "3", "4", "5"]);
+        let root = mt.get_root().unwrap();
+
+        for value_index in 0..8 {
+            let proof = mt.prove(value_index).unwrap();
+            let leaf_hash = mt.get_value(value_index).unwrap();
+
+            assert!(proof.verify(&leaf_hash, &root, &basic_hash));
+            assert!(verify_proof(&proof, &leaf_hash, &root, &basic_hash));
+        }
+    }
+
+    #[test]
+    fn verify_wrong_leaf_or_root() {
+        let mt = tree_of(2, &["1", "2", "3"]);
+        let root = mt.get_root().unwrap();
+        let proof = mt.prove(1).unwrap();
+
+        assert!(!proof.verify(&LeafHash(b"H(3)".to_vec()), &root, &basic_hash));
+        assert!(!proof.verify(
+            &LeafHash(b"H(2)".to_vec()),
+            &NodeHash(b"H(forged)".to_vec()),
+            &basic_hash
+        ));
+    }
+
+    #[test]
+    fn verify_against_several_roots() {
+        let mut mt = tree_of(2, &["1", "2"]);
+        let old_root = mt.get_root().unwrap();
+        mt.insert("3").unwrap();
+        mt.update_internal_nodes().unwrap();
+        let new_root = mt.get_root().unwrap();
+
+        let old_proof = tree_of(2, &["1", "2"]).prove(1).unwrap();
+        let new_proof = mt.prove(1).unwrap();
+        let leaf_hash = mt.get_value(1).unwrap();
+        let roots = [NodeHash(b"H(forged)".to_vec()), new_root, old_root];
+
+        assert_eq!(
+            Some(2),
+            old_proof.verify_any(&leaf_hash, &roots, &basic_hash)
+        );
+        assert_eq!(
+            Some(1),
+            verify_any(&roots, &leaf_hash, &new_proof, &basic_hash)
+        );
+        assert_eq!(
+            None,
+            new_proof.verify_any(&LeafHash(b"H(3)".to_vec()), &roots, &basic_hash)
+        );
+        assert_eq!(None, new_proof.verify_any(&leaf_hash, &[], &basic_hash));
+    }
+
+    #[test]
+    fn verify_tampered_proof() {
+        let mt = tree_of(2, &["1", "2", "3"]);
+        let root = mt.get_root().unwrap();
+        let leaf_hash = mt.get_value(1).unwrap();
+
+        let mut swapped = mt.prove(1).unwrap();
+        swapped.steps[0].position = Position::Right;
+
+        let mut truncated = mt.prove(1).unwrap();
+        truncated.steps.pop();
+
+        assert!(!swapped.verify(&leaf_hash, &root, &basic_hash));
+        assert!(!truncated.verify(&leaf_hash, &root, &basic_hash));
+    }
+
+    #[test]
+    fn verify_rejects_other_value_indices() {
+        let mt = tree_of(2, &["1", "2", "3"]);
+        let root = mt.get_root().unwrap();
+        let leaf_hash = mt.get_value(1).unwrap();
+
+        // the steps still hash to the root, but they are the path of another value index
+        for value_index in [3, 5, usize::MAX] {
+            let mut replayed = mt.prove(1).unwrap();
+            replayed.value_index = value_index;
+
+            assert!(!replayed.verify(&leaf_hash, &root, &basic_hash));
+            assert_eq!(
+                None,
+                replayed.verify_any(&leaf_hash, core::slice::from_ref(&root), &basic_hash)
+            );
+        }
+    }
+
+    #[test]
+    fn verify_for_tree() {
+        let mt = tree_of(1, &["1", "2"]);
+        let root = mt.get_root().unwrap();
+        let leaf_hash = mt.get_value(0).unwrap();
+        let proof = mt.prove(0).unwrap();
+
+        assert!(proof.verify_for_tree(TreeId(7), &leaf_hash, &root, &basic_hash));
+        assert!(!proof.verify_for_tree(TreeId(8), &leaf_hash, &root, &basic_hash));
+    }
+
+    #[test]
+    fn iter_with_proofs_matches_prove() {
+        let values = ["a", "b", "c", "d", "e", "f"];
+        let mt = tree_of(3, &values);
+        let root = mt.get_root().unwrap();
+
+        let items: Vec<_> = mt
+            .iter_with_proofs()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(values.len(), items.len());
+        for (value_index, leaf_hash, proof) in items {
+            assert_eq!(mt.get_value(value_index).unwrap(), leaf_hash);
+            assert_eq!(mt.prove(value_index).unwrap(), proof);
+            assert!(proof.verify(&leaf_hash, &root, &basic_hash));
+        }
+    }
+
+    #[test]
+    fn iter_with_proofs_stale_tree() {
+        let mut mt = tree_of(2, &["a"]);
+        mt.insert("b").unwrap();
+
+        assert_eq!(
+            Some(MerkleTreeError::StaleInternalNode(1)),
+            mt.iter_with_proofs().err()
+        );
+    }
+
+    #[test]
+    fn sorted_pairs_prove_and_verify() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .pair_order(PairOrder::Sorted)
+            .build()
+            .unwrap();
+        for value in ["d", "c", "b", "a"] {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+        let root = mt.get_root().unwrap();
+
+        // the smaller hash of each pair comes first, whatever its position
+        assert_eq!(b"H(H(H(a)H(b))H(H(c)H(d)))".to_vec(), root.0);
+
+        for value_index in 0..4 {
+            let mut proof = mt.prove(value_index).unwrap();
+            let leaf_hash = mt.get_value(value_index).unwrap();
+
+            assert_eq!(PairOrder::Sorted, proof.pair_order);
+            assert!(proof.verify(&leaf_hash, &root, &basic_hash));
+
+            // the positions of the siblings do not matter
+            for step in &mut proof.steps {
+                step.position = Position::Left;
+            }
+            assert!(proof.verify(&leaf_hash, &root, &basic_hash));
+        }
+    }
+
+    #[test]
+    fn from_siblings_matches_prove() {
+        let mt = tree_of(3, &["1", "2", "3", "4", "5"]);
+        let root = mt.get_root().unwrap();
+
+        for value_index in 0..8 {
+            let proof = mt.prove(value_index).unwrap();
+            let derived = MerkleProof::from_siblings(
+                TreeId(7),
+                value_index,
+                PairOrder::Positional,
+                proof.sibling_hashes(),
+            );
+
+            assert_eq!(proof, derived);
+            assert!(derived.verify(&mt.get_value(value_index).unwrap(), &root, &basic_hash));
+        }
+    }
+
+    #[test]
+    fn proofs_verify_without_std() {
+        use merkle_tree_verify::NodeHasher;
+
+        struct ShortHasher;
+
+        impl NodeHasher for ShortHasher {
+            type Digest = [u8; 8];
+
+            fn hash_nodes(&self, left: &[u8; 8], right: &[u8; 8]) -> [u8; 8] {
+                short_hash(&[&left[..], &right[..]].concat())
+                    .try_into()
+                    .unwrap()
+            }
+        }
+
+        let mut mt = MerkleTree::from_height(short_hash as HashFunction, 2).unwrap();
+        for value in ["a", "b", "c"] {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+
+        let proof = mt.prove(1).unwrap();
+        let steps = proof.steps.iter().map(|step| {
+            let position = match step.position {
+                Position::Left => merkle_tree_verify::Position::Left,
+                Position::Right => merkle_tree_verify::Position::Right,
+            };
+
+            (position, step.hash.clone().try_into().unwrap())
+        });
+
+        assert!(merkle_tree_verify::verify(
+            &ShortHasher,
+            short_hash(b"b").try_into().unwrap(),
+            steps,
+            merkle_tree_verify::PairOrder::Positional,
+            &mt.get_root().unwrap().0.try_into().unwrap(),
+        ));
+    }
+}
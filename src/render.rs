@@ -0,0 +1,184 @@
+//! text rendering of merkle trees, e.g. to find where the roots of two merkle trees diverge
+//!
+//! only the nodes above the inserted values are drawn with their indices and truncated hashes,
+//! the empty nodes on their right being summarized:
+//! - `render_ascii` draws each level on its own line, from the root down to the leaves
+//! - `to_dot` produces a Graphviz graph, e.g. to render with `dot -Tsvg`
+
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::store::NodeStore;
+use crate::{hex, Hasher, MerkleTree};
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// draw the merkle tree level by level, showing the first `hash_width` hexadecimal digits
+    /// of the hash of each node, or `stale` for the internal nodes to recompute
+    pub fn render_ascii(&self, hash_width: usize) -> String {
+        (0..=self.height)
+            .map(|level| {
+                let width = self.level_width(level);
+
+                let mut line = format!("level {}:", level);
+                for index in (1 << level)..((1 << level) + width) {
+                    line.push_str(&format!(" [{}] {}", index, self.label(index, hash_width)));
+                }
+
+                let empty = (1 << level) - width;
+                if empty > 0 {
+                    line.push_str(&format!(" (+{} empty)", empty));
+                }
+
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// produce a Graphviz DOT graph of the merkle tree, labelling the nodes with their indices
+    /// and the first `hash_width` hexadecimal digits of their hashes,
+    /// and the leaves with the indices of their values
+    ///
+    /// the empty children of the drawn nodes are drawn dashed, without their subtrees
+    pub fn to_dot(&self, hash_width: usize) -> String {
+        let mut dot = String::from("digraph merkle_tree {\n");
+
+        if self.length == 0 {
+            dot.push_str("    n1 [label=\"1\\nempty\", style=dashed];\n");
+        }
+
+        for level in 0..=self.height {
+            let width = self.level_width(level);
+
+            for index in (1 << level)..((1 << level) + width) {
+                let label = self.label(index, hash_width);
+                if level == self.height {
+                    let value_index = index - self.first_leaf_node_index;
+                    dot.push_str(&format!(
+                        "    n{} [label=\"value {}\\n{}\", shape=box];\n",
+                        index, value_index, label
+                    ));
+                } else {
+                    dot.push_str(&format!(
+                        "    n{} [label=\"{}\\n{}\"];\n",
+                        index, index, label
+                    ));
+                }
+
+                if level > 0 {
+                    dot.push_str(&format!("    n{} -> n{};\n", index / 2, index));
+                }
+            }
+
+            // the right child of the last drawn node is empty if no value is below it
+            let last = (1 << level) + width - 1;
+            if level < self.height && width > 0 && self.level_width(level + 1) < 2 * width {
+                let empty = 2 * last + 1;
+                dot.push_str(&format!(
+                    "    n{} [label=\"{}\\nempty\", style=dashed];\n    n{} -> n{};\n",
+                    empty, empty, last, empty
+                ));
+            }
+        }
+
+        dot.push('}');
+        dot
+    }
+
+    /// get the number of nodes of a level above the inserted values
+    fn level_width(&self, level: usize) -> usize {
+        // the nodes of a level are stored from 2^level to 2^(level + 1) - 1
+        // and each of them covers 2^(height - level) leaves
+        self.length.div_ceil(1 << (self.height - level))
+    }
+
+    /// get the truncated hash of the node at `index`, or `stale` if it has to be recomputed
+    fn label(&self, index: usize, hash_width: usize) -> String {
+        match self.nodes.get_hash(index) {
+            Some(hash) => truncate(&hex::encode(&hash), hash_width),
+            None => "stale".to_string(),
+        }
+    }
+}
+
+/// keep the first `width` characters of `hash`, marking the truncation with `..`
+fn truncate(hash: &str, width: usize) -> String {
+    match hash.get(..width) {
+        Some(prefix) if prefix.len() < hash.len() => format!("{}..", prefix),
+        _ => hash.to_string(),
+    }
+}
+
+/// draw the merkle tree with `render_ascii`, showing 8 hexadecimal digits of each hash
+impl<H: Hasher, S: NodeStore> fmt::Display for MerkleTree<H, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render_ascii(8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+
+    #[test]
+    fn render_levels() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.update_internal_nodes().unwrap();
+        mt.insert("3").unwrap();
+
+        assert_eq!(
+            "level 0: [1] stale\n\
+             level 1: [2] 48284828.. [3] stale\n\
+             level 2: [4] 48283129 [5] 48283229 [6] 48283329 (+1 empty)",
+            mt.to_string()
+        );
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.insert("3").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(
+            r#"digraph merkle_tree {
+    n1 [label="1\n4828.."];
+    n2 [label="2\n4828.."];
+    n1 -> n2;
+    n3 [label="3\n4828.."];
+    n1 -> n3;
+    n7 [label="7\nempty", style=dashed];
+    n3 -> n7;
+    n4 [label="value 0\n4828..", shape=box];
+    n2 -> n4;
+    n5 [label="value 1\n4828..", shape=box];
+    n2 -> n5;
+    n6 [label="value 2\n4828..", shape=box];
+    n3 -> n6;
+}"#,
+            mt.to_dot(4)
+        );
+    }
+
+    #[test]
+    fn render_empty_tree() {
+        let mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+
+        assert_eq!(
+            "level 0: (+1 empty)\nlevel 1: (+2 empty)\nlevel 2: (+4 empty)",
+            mt.render_ascii(4)
+        );
+        assert_eq!(
+            "digraph merkle_tree {\n    n1 [label=\"1\\nempty\", style=dashed];\n}",
+            mt.to_dot(4)
+        );
+    }
+}
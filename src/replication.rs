@@ -0,0 +1,121 @@
+//! bitmaps of the nodes held by partial replicas of a merkle tree
+//!
+//! a replica compares its own nodes with the bitmap of a peer to know
+//! which nodes it must fetch from that peer
+
+use crate::store::NodeStore;
+use crate::{Hasher, MerkleTree, MerkleTreeError};
+use alloc::vec;
+use alloc::vec::Vec;
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// get a bitmap of the nodes present in the merkle tree,
+    /// where bit `i % 8` of byte `i / 8` is set if the node at index `i` is present
+    ///
+    /// only the present nodes are visited, so the cost beyond the allocation of the bitmap
+    /// follows the number of stored nodes rather than the size of the merkle tree
+    pub fn presence_bitmap(&self) -> Vec<u8> {
+        let mut bitmap = vec![0; self.nodes.node_count().div_ceil(8)];
+
+        for i in self.nodes.present_indices() {
+            bitmap[i / 8] |= 1 << (i % 8);
+        }
+
+        bitmap
+    }
+
+    /// get the indices of the nodes present in the peer's `bitmap`
+    /// but missing from the merkle tree, in ascending order
+    ///
+    /// only the nodes whose bit is set are looked up, skipping the empty bytes of the bitmap
+    ///
+    /// fail if the bitmap does not have the size of the merkle tree's bitmap
+    pub fn fetch_missing(&self, bitmap: &[u8]) -> Result<Vec<usize>, MerkleTreeError> {
+        let node_count = self.nodes.node_count();
+        if bitmap.len() != node_count.div_ceil(8) {
+            return Err(MerkleTreeError::BitmapSizeMismatch);
+        }
+
+        Ok(bitmap
+            .iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte != 0)
+            .flat_map(|(byte_index, &byte)| {
+                (0..8)
+                    .filter(move |bit| byte & (1 << bit) != 0)
+                    .map(move |bit| byte_index * 8 + bit)
+            })
+            .filter(|&i| i < node_count && !self.nodes.has_hash(i))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::basic_hash;
+    use crate::{MerkleTree, MerkleTreeError};
+    use alloc::vec;
+
+    #[test]
+    fn presence_bitmap() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+        assert_eq!(vec![0b0000_0000], mt.presence_bitmap());
+
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        assert_eq!(vec![0b0011_0000], mt.presence_bitmap());
+
+        mt.update_internal_nodes().unwrap();
+        // the empty subtree of the root is not stored
+        assert_eq!(vec![0b0011_0110], mt.presence_bitmap());
+    }
+
+    #[test]
+    fn presence_bitmap_spans_several_bytes() {
+        let mut mt = MerkleTree::from_height(basic_hash, 3).unwrap();
+        mt.insert("1").unwrap();
+
+        assert_eq!(vec![0b0000_0000, 0b0000_0001], mt.presence_bitmap());
+    }
+
+    #[test]
+    fn fetch_missing() {
+        let mut source = MerkleTree::from_height(basic_hash, 2).unwrap();
+        source.insert("1").unwrap();
+        source.insert("2").unwrap();
+        source.insert("3").unwrap();
+        source.update_internal_nodes().unwrap();
+
+        let mut replica = MerkleTree::from_height(basic_hash, 2).unwrap();
+        replica.insert("1").unwrap();
+        replica.insert("2").unwrap();
+
+        assert_eq!(
+            Ok(vec![1, 2, 3, 6]),
+            replica.fetch_missing(&source.presence_bitmap())
+        );
+        assert_eq!(Ok(vec![]), source.fetch_missing(&replica.presence_bitmap()));
+    }
+
+    #[test]
+    fn fetch_missing_from_sparse_bitmaps() {
+        let mut source = MerkleTree::from_height(basic_hash, 16).unwrap();
+        source.insert("1").unwrap();
+        let replica = MerkleTree::from_height(basic_hash, 16).unwrap();
+
+        // the only leaf is the first one, at the start of the last level
+        let bitmap = source.presence_bitmap();
+        assert_eq!(1, bitmap.iter().map(|byte| byte.count_ones()).sum::<u32>());
+        assert_eq!(Ok(vec![1 << 16]), replica.fetch_missing(&bitmap));
+    }
+
+    #[test]
+    fn fetch_missing_wrong_size() {
+        let mt = MerkleTree::from_height(basic_hash, 3).unwrap();
+
+        assert_eq!(
+            Err(MerkleTreeError::BitmapSizeMismatch),
+            mt.fetch_missing(&[0])
+        );
+    }
+}
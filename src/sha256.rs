@@ -0,0 +1,179 @@
+//! a ready-to-use SHA-256 hasher, as specified by FIPS 180-4
+//!
+//! only available with the `sha2` feature
+
+use crate::{Hash, Hasher, MerkleTree};
+use alloc::string::String;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// a hasher computing the SHA-256 digest of the values and
+/// of the concatenation of the children of each node,
+/// which hashes as in RFC 6962 when wrapped in `DomainSeparated`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Sha256Hasher;
+
+impl Hasher for Sha256Hasher {
+    fn hash_leaf(&self, value: &[u8]) -> Hash {
+        sha256(value).to_vec()
+    }
+
+    fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash {
+        sha256(&[left, right].concat()).to_vec()
+    }
+}
+
+impl FromIterator<String> for MerkleTree<Sha256Hasher> {
+    /// build a merkle tree of SHA-256 digests with `MerkleTree::from_leaves`
+    fn from_iter<I: IntoIterator<Item = String>>(values: I) -> Self {
+        MerkleTree::from_leaves(Sha256Hasher, values)
+            .expect("a merkle tree sized for its values can hold them")
+    }
+}
+
+/// compute the SHA-256 digest of `data`
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state = INITIAL_STATE;
+
+    // pad the message with a 1 bit, zeros and its length in bits,
+    // up to a multiple of the 64-byte block size
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        compress(&mut state, block);
+    }
+
+    let mut digest = [0; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+
+    digest
+}
+
+/// mix a 64-byte `block` into the `state`
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let choice = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(choice)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let majority = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(majority);
+
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hex, DomainSeparated};
+
+    #[test]
+    fn sha256_test_vectors() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            hex::encode(&sha256(b""))
+        );
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            hex::encode(&sha256(b"abc"))
+        );
+        assert_eq!(
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            hex::encode(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            ))
+        );
+    }
+
+    #[test]
+    fn collect_into_merkle_tree() {
+        let mt: MerkleTree<Sha256Hasher> =
+            ["1", "2"].iter().map(|value| value.to_string()).collect();
+
+        let expected = sha256(&[sha256(b"1"), sha256(b"2")].concat());
+
+        assert_eq!(expected.to_vec(), mt.get_root().unwrap().0);
+    }
+
+    #[test]
+    fn rfc_6962_hashes() {
+        let hasher = DomainSeparated(Sha256Hasher);
+
+        // the hash of an empty merkle tree and of an empty leaf in RFC 6962
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            hex::encode(&hasher.hash_empty())
+        );
+        assert_eq!(
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            hex::encode(&hasher.hash_leaf(b""))
+        );
+        assert_eq!(
+            sha256(&[&[0x01], &sha256(b"\x001")[..], &sha256(b"\x002")[..]].concat()).to_vec(),
+            hasher.hash_nodes(&hasher.hash_leaf(b"1"), &hasher.hash_leaf(b"2"))
+        );
+    }
+
+    #[test]
+    fn sha256_merkle_root() {
+        let mut mt = MerkleTree::from_height(Sha256Hasher, 1).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let expected = sha256(&[sha256(b"1"), sha256(b"2")].concat());
+
+        assert_eq!(expected.to_vec(), mt.get_root().unwrap().0);
+    }
+}
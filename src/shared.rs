@@ -0,0 +1,176 @@
+//! merkle trees shared between threads, which can be snapshotted while values are inserted
+//!
+//! a `SharedTree` only lets values be appended, so the leaves below a given length never change:
+//! a snapshot only records the length of the merkle tree, then copies its leaves
+//! in small batches and computes their root without holding the lock,
+//! so that backups of busy merkle trees do not pause the insertions
+
+use std::sync::{Mutex, MutexGuard};
+
+use crate::snapshot::SparseSnapshot;
+use crate::store::{MemoryStore, NodeStore};
+use crate::{Hasher, LeafHash, MerkleTree, MerkleTreeBuilder, MerkleTreeError};
+
+/// the number of leaves copied by a snapshot each time it holds the lock
+const SNAPSHOT_BATCH: usize = 1024;
+
+/// a merkle tree that can be shared between threads, e.g. in an `Arc`,
+/// whose values can only be appended
+pub struct SharedTree<H, S = MemoryStore> {
+    tree: Mutex<MerkleTree<H, S>>,
+}
+
+impl<H: Hasher, S: NodeStore> SharedTree<H, S> {
+    pub fn new(mt: MerkleTree<H, S>) -> Self {
+        SharedTree {
+            tree: Mutex::new(mt),
+        }
+    }
+
+    /// lock the merkle tree, whose invariants hold even if a thread panicked while holding it
+    fn lock(&self) -> MutexGuard<'_, MerkleTree<H, S>> {
+        self.tree.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// insert a value like `MerkleTree::insert`
+    ///
+    /// fail if the value cannot be inserted
+    pub fn insert(&self, value: impl AsRef<[u8]>) -> Result<(), MerkleTreeError> {
+        self.lock().insert(value)
+    }
+
+    /// insert a leaf hash computed beforehand like `MerkleTree::insert_hash`
+    ///
+    /// fail if the leaf hash cannot be inserted
+    pub fn insert_hash(&self, leaf_hash: LeafHash) -> Result<(), MerkleTreeError> {
+        self.lock().insert_hash(leaf_hash)
+    }
+
+    /// update the internal nodes like `MerkleTree::update_internal_nodes`
+    ///
+    /// fail if the internal nodes cannot be updated
+    pub fn update_internal_nodes(&self) -> Result<(), MerkleTreeError> {
+        self.lock().update_internal_nodes()
+    }
+
+    /// run `read` on the merkle tree, e.g. to generate proofs, while holding the lock
+    pub fn read<R>(&self, read: impl FnOnce(&MerkleTree<H, S>) -> R) -> R {
+        read(&self.lock())
+    }
+
+    pub fn into_inner(self) -> MerkleTree<H, S> {
+        self.tree.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<H: Hasher + Clone, S: NodeStore> SharedTree<H, S> {
+    /// export the leaves and the head of the merkle tree as they are when `snapshot` is called,
+    /// like `export_sparse`, while the values keep being inserted by other threads
+    ///
+    /// the root of the snapshot is computed from its leaves,
+    /// so the internal nodes do not need to be up to date
+    ///
+    /// fail if an empty leaf is needed while empty leaves are forbidden
+    pub fn snapshot(&self) -> Result<SparseSnapshot, MerkleTreeError> {
+        let (tree_id, height, length, empty_leaf, pair_order, bind_index, hasher) = {
+            let mt = self.lock();
+
+            (
+                mt.tree_id,
+                mt.height,
+                mt.length,
+                mt.empty_leaf.clone(),
+                mt.pair_order,
+                mt.bind_index,
+                mt.hasher.clone(),
+            )
+        };
+
+        let mut leaves = Vec::with_capacity(length);
+        while leaves.len() < length {
+            let mt = self.lock();
+            let end = (leaves.len() + SNAPSHOT_BATCH).min(length);
+
+            for value_index in leaves.len()..end {
+                leaves.push((value_index, mt.get_value(value_index)?));
+            }
+        }
+
+        let root = MerkleTreeBuilder::new(hasher, height)
+            .empty_leaf(empty_leaf.clone())
+            .pair_order(pair_order)
+            .root_from_digests(leaves.iter().map(|(_, leaf_hash)| leaf_hash.clone()))?;
+
+        Ok(SparseSnapshot {
+            tree_id,
+            height,
+            length,
+            root,
+            leaves,
+            empty_leaf,
+            pair_order,
+            bind_index,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::short_hash;
+    use std::thread;
+
+    use super::*;
+    use crate::HashFunction;
+
+    #[test]
+    fn snapshot_is_a_point_in_time_copy() {
+        let shared =
+            SharedTree::new(MerkleTree::from_height(short_hash as HashFunction, 3).unwrap());
+        shared.insert("a").unwrap();
+        shared.insert("b").unwrap();
+
+        let snapshot = shared.snapshot().unwrap();
+        shared.insert("c").unwrap();
+        shared.update_internal_nodes().unwrap();
+
+        let mut expected = MerkleTree::from_height(short_hash as HashFunction, 3).unwrap();
+        expected.insert("a").unwrap();
+        expected.insert("b").unwrap();
+        expected.update_internal_nodes().unwrap();
+
+        assert_eq!(2, snapshot.length);
+        assert_eq!(expected.get_root().unwrap(), snapshot.root);
+        assert_eq!(expected.leaves().collect::<Vec<_>>(), snapshot.leaves);
+        assert_eq!(shared.read(|mt| mt.tree_id), snapshot.tree_id);
+        assert_eq!(3, shared.into_inner().len());
+    }
+
+    #[test]
+    fn snapshots_while_inserting() {
+        let values: Vec<String> = (0..3000).map(|i| i.to_string()).collect();
+        let shared =
+            SharedTree::new(MerkleTree::from_height(short_hash as HashFunction, 12).unwrap());
+
+        let snapshots = thread::scope(|scope| {
+            scope.spawn(|| {
+                for value in &values {
+                    shared.insert(value).unwrap();
+                }
+            });
+
+            (0..20)
+                .map(|_| shared.snapshot().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        // every snapshot holds the root of the values inserted before it
+        for snapshot in snapshots {
+            let mut expected = MerkleTree::from_height(short_hash as HashFunction, 12).unwrap();
+            for value in &values[..snapshot.length] {
+                expected.insert(value).unwrap();
+            }
+
+            assert_eq!(expected.root().unwrap(), snapshot.root);
+        }
+    }
+}
@@ -0,0 +1,515 @@
+//! sparse snapshots of merkle trees
+//!
+//! a sparse snapshot only records the inserted leaves and the head of the tree,
+//! so its size is proportional to the number of inserted values
+//! rather than to the capacity of the tree
+//!
+//! backup tools can also export and import all the nodes present in a merkle tree
+//! as `(index, hash)` pairs, without knowing how the nodes are laid out;
+//! as the imported internal nodes are served in proofs as is, nodes from an untrusted source
+//! can be checked against the imported leaves with `verify_on_load` before the tree is usable
+
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, RandomState};
+
+use crate::head::TreeId;
+use crate::store::NodeStore;
+use crate::{
+    EmptyLeaf, Hash, Hasher, LeafHash, MerkleTree, MerkleTreeBuilder, MerkleTreeError, NodeHash,
+    PairOrder,
+};
+
+/// the occupied leaves of a merkle tree together with its head
+#[derive(Clone, PartialEq, Debug)]
+pub struct SparseSnapshot {
+    pub tree_id: TreeId,
+    pub height: usize,
+    /// the number of values inserted in the tree
+    pub length: usize,
+    /// the root hash of the tree at the time of the export
+    pub root: NodeHash,
+    /// the `(value_index, hash)` pairs of the inserted values, sorted by index
+    pub leaves: Vec<(usize, LeafHash)>,
+    /// the configuration of the tree, without which its root cannot be recomputed
+    pub empty_leaf: EmptyLeaf,
+    pub pair_order: PairOrder,
+    pub bind_index: bool,
+}
+
+/// how much of the internal nodes imported with `import_nodes` is checked against the leaves
+///
+/// only available with the `std` feature, whose randomness chooses the sampled nodes
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadCheck {
+    /// recompute the whole merkle tree from the leaves and check every imported internal node
+    Full,
+    /// recompute the subtrees of this number of imported internal nodes chosen at random,
+    /// checking every imported internal node within them, which is cheaper than `Full`
+    /// as most internal nodes are close to the leaves
+    Sample(usize),
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// export the occupied leaves and the head of the merkle tree
+    ///
+    /// fail if the internal nodes are not up to date
+    pub fn export_sparse(&self) -> Result<SparseSnapshot, MerkleTreeError> {
+        Ok(SparseSnapshot {
+            tree_id: self.tree_id,
+            height: self.height,
+            length: self.length,
+            root: self.get_root()?,
+            leaves: self.leaves().collect(),
+            empty_leaf: self.empty_leaf.clone(),
+            pair_order: self.pair_order,
+            bind_index: self.bind_index,
+        })
+    }
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// iterate over the `(index, hash)` pairs of all the nodes present in the merkle tree,
+    /// in ascending order of index
+    pub fn export_nodes(&self) -> impl Iterator<Item = (usize, Hash)> + '_ {
+        self.nodes
+            .present_indices()
+            .into_iter()
+            .filter_map(|index| Some((index, self.nodes.get_hash(index)?)))
+    }
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTreeBuilder<H, S> {
+    /// build a merkle tree from the `(index, hash)` pairs exported with `export_nodes`
+    ///
+    /// the nodes missing from `nodes` are recomputed by the next call to `update_internal_nodes`,
+    /// and the ids of the values inserted with `insert_with_id` are not restored
+    ///
+    /// fail if the merkle tree cannot be built,
+    /// if an index is out of bounds,
+    /// if the leaves are not the first ones, as if they were inserted one after the other or,
+    /// with `verify_on_load`, if a checked internal node does not match the leaves below it
+    pub fn import_nodes(
+        self,
+        nodes: impl IntoIterator<Item = (usize, Hash)>,
+    ) -> Result<MerkleTree<H, S>, MerkleTreeError> {
+        #[cfg(feature = "std")]
+        let load_check = self.load_check;
+        let mut mt = self.build()?;
+        let node_count = mt.nodes.node_count();
+
+        for (index, hash) in nodes {
+            if index == 0 || index >= node_count {
+                return Err(MerkleTreeError::NodeOutOfBounds(index));
+            }
+
+            mt.nodes.set_hash(index, Some(hash));
+        }
+
+        let first_leaf_node_index = mt.first_leaf_node_index;
+        for index in mt.nodes.present_indices() {
+            if index >= first_leaf_node_index {
+                if index != first_leaf_node_index + mt.length {
+                    return Err(MerkleTreeError::InvalidSnapshot);
+                }
+
+                mt.length += 1;
+            }
+        }
+
+        // the empty subtrees are never exported, so the merkle tree is up to date
+        // as soon as every internal node above an inserted value is imported
+        mt.fully_stale = !MerkleTree::<H, S>::populated_internal_node_indices(mt.height, mt.length)
+            .all(|index| mt.nodes.has_hash(index));
+
+        #[cfg(feature = "std")]
+        if let Some(load_check) = load_check {
+            mt.check_loaded_nodes(load_check)?;
+        }
+
+        Ok(mt)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// check the imported internal nodes against the leaves, as defined by `load_check`
+    ///
+    /// fail if a checked internal node does not match the leaves below it
+    /// or if an empty leaf is needed while empty leaves are forbidden
+    fn check_loaded_nodes(&self, load_check: LoadCheck) -> Result<(), MerkleTreeError> {
+        let loaded: Vec<usize> = self
+            .nodes
+            .present_indices()
+            .into_iter()
+            .take_while(|&index| index < self.first_leaf_node_index)
+            .collect();
+
+        let checked = match load_check {
+            LoadCheck::Full => {
+                self.check_loaded_subtree(1)?;
+
+                // the traversal does not go down the empty subtrees
+                loaded
+                    .into_iter()
+                    .filter(|&index| self.is_empty_subtree(index))
+                    .collect()
+            }
+            LoadCheck::Sample(count) => {
+                // ordering the nodes by a randomly keyed hash of their index shuffles them
+                let state = RandomState::new();
+                let mut loaded = loaded;
+                loaded.sort_unstable_by_key(|&index| state.hash_one(index));
+                loaded.truncate(count);
+
+                loaded
+            }
+        };
+
+        for index in checked {
+            self.check_loaded_subtree(index)?;
+        }
+
+        Ok(())
+    }
+
+    /// compute the hash of the node at `index` from the leaves below it,
+    /// checking that the imported internal nodes on the way match the computed hashes
+    ///
+    /// fail if an imported internal node does not match
+    /// or if an empty leaf is needed while empty leaves are forbidden
+    fn check_loaded_subtree(&self, index: usize) -> Result<Hash, MerkleTreeError> {
+        if index >= self.first_leaf_node_index {
+            return self.get_node_hash(index);
+        }
+
+        let hash = if self.is_empty_subtree(index) {
+            self.get_empty_subtree_hash(index)?
+        } else {
+            let left_child_hash = self.check_loaded_subtree(2 * index)?;
+            let right_child_hash = self.check_loaded_subtree(2 * index + 1)?;
+            let (first, second) = self.pair_order.arrange(&left_child_hash, &right_child_hash);
+
+            self.hash_nodes(first, second)
+        };
+
+        match self.nodes.get_hash(index) {
+            Some(loaded) if loaded != hash => Err(MerkleTreeError::InvalidSnapshot),
+            _ => Ok(hash),
+        }
+    }
+}
+
+impl<H: Hasher> MerkleTree<H> {
+    /// restore a merkle tree from a sparse snapshot, with its configuration,
+    /// and recompute its internal nodes
+    ///
+    /// fail if the height is invalid,
+    /// if the length exceeds the capacity of the merkle tree,
+    /// if the leaves are not exactly the first `length` ones, in order,
+    /// if the internal nodes cannot be computed or
+    /// if the recomputed root is not the one of the snapshot
+    pub fn from_sparse(hasher: H, snapshot: SparseSnapshot) -> Result<Self, MerkleTreeError> {
+        let mut mt = MerkleTreeBuilder::new(hasher, snapshot.height)
+            .tree_id(snapshot.tree_id)
+            .empty_leaf(snapshot.empty_leaf)
+            .pair_order(snapshot.pair_order)
+            .bind_index(snapshot.bind_index)
+            .build()?;
+
+        if snapshot.length > mt.nodes.node_count() - mt.first_leaf_node_index {
+            return Err(MerkleTreeError::Full);
+        }
+        if snapshot.leaves.len() != snapshot.length {
+            return Err(MerkleTreeError::InvalidSnapshot);
+        }
+
+        for (expected_index, (value_index, LeafHash(hash))) in
+            snapshot.leaves.into_iter().enumerate()
+        {
+            if value_index != expected_index {
+                return Err(MerkleTreeError::InvalidSnapshot);
+            }
+
+            mt.nodes
+                .set_hash(mt.first_leaf_node_index + value_index, Some(hash));
+        }
+        mt.length = snapshot.length;
+        // the leaves were written directly, so all the internal nodes above them are computed
+        mt.fully_stale = true;
+
+        if mt.root()? != snapshot.root {
+            return Err(MerkleTreeError::InvalidSnapshot);
+        }
+
+        Ok(mt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use alloc::vec;
+
+    #[test]
+    fn export_sparse_only_occupied_leaves() {
+        let mut mt = MerkleTree::from_height(basic_hash, 3).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let snapshot = mt.export_sparse().unwrap();
+
+        assert_eq!(3, snapshot.height);
+        assert_eq!(2, snapshot.length);
+        assert_eq!(mt.get_root().unwrap(), snapshot.root);
+        assert_eq!(
+            vec![
+                (0, LeafHash(b"H(1)".to_vec())),
+                (1, LeafHash(b"H(2)".to_vec()))
+            ],
+            snapshot.leaves
+        );
+    }
+
+    #[test]
+    fn from_sparse_restores_tree() {
+        let mut mt = MerkleTree::from_height(basic_hash, 3).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.insert("3").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let mut restored =
+            MerkleTree::from_sparse(basic_hash, mt.export_sparse().unwrap()).unwrap();
+
+        assert_eq!(mt.tree_id, restored.tree_id);
+        assert_eq!(mt.length, restored.length);
+        assert_eq!(mt.nodes, restored.nodes);
+
+        restored.insert("4").unwrap();
+        assert_eq!(Ok(LeafHash(b"H(4)".to_vec())), restored.get_value(3));
+    }
+
+    #[test]
+    fn from_sparse_leaf_past_length() {
+        let snapshot = SparseSnapshot {
+            tree_id: TreeId(0),
+            height: 1,
+            length: 1,
+            root: NodeHash(b"H(H(1)H(2))".to_vec()),
+            leaves: vec![
+                (0, LeafHash(b"H(1)".to_vec())),
+                (1, LeafHash(b"H(2)".to_vec())),
+            ],
+            empty_leaf: EmptyLeaf::default(),
+            pair_order: PairOrder::Positional,
+            bind_index: false,
+        };
+
+        assert_eq!(
+            Some(MerkleTreeError::InvalidSnapshot),
+            MerkleTree::from_sparse(basic_hash, snapshot).err()
+        );
+    }
+
+    #[test]
+    fn from_sparse_restores_configuration() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .empty_leaf(EmptyLeaf::Index)
+            .pair_order(PairOrder::Sorted)
+            .bind_index(true)
+            .build()
+            .unwrap();
+        mt.insert("b").unwrap();
+        mt.insert("a").unwrap();
+        mt.insert("c").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let restored = MerkleTree::from_sparse(basic_hash, mt.export_sparse().unwrap()).unwrap();
+
+        assert_eq!(mt.get_root(), restored.get_root());
+        assert_eq!(mt.hash_value(3, "d"), restored.hash_value(3, "d"));
+    }
+
+    #[test]
+    fn from_sparse_rejects_gaps_and_other_roots() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.insert("3").unwrap();
+        mt.update_internal_nodes().unwrap();
+        let snapshot = mt.export_sparse().unwrap();
+
+        let mut gap = snapshot.clone();
+        gap.leaves.remove(1);
+        let mut shifted = snapshot.clone();
+        shifted.leaves[1].0 = 3;
+        let mut other_root = snapshot.clone();
+        other_root.root = NodeHash(b"H(forged)".to_vec());
+        let mut other_configuration = snapshot;
+        other_configuration.empty_leaf = EmptyLeaf::Index;
+
+        for snapshot in [gap, shifted, other_root, other_configuration] {
+            assert_eq!(
+                Some(MerkleTreeError::InvalidSnapshot),
+                MerkleTree::from_sparse(basic_hash, snapshot).err()
+            );
+        }
+    }
+
+    #[test]
+    fn from_sparse_length_past_capacity() {
+        let snapshot = SparseSnapshot {
+            tree_id: TreeId(0),
+            height: 1,
+            length: 3,
+            root: NodeHash(b"H(H(1)H(2))".to_vec()),
+            leaves: vec![],
+            empty_leaf: EmptyLeaf::default(),
+            pair_order: PairOrder::Positional,
+            bind_index: false,
+        };
+
+        assert_eq!(
+            Some(MerkleTreeError::Full),
+            MerkleTree::from_sparse(basic_hash, snapshot).err()
+        );
+    }
+
+    #[test]
+    fn export_and_import_nodes() {
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let nodes: Vec<_> = mt.export_nodes().collect();
+
+        assert_eq!(
+            vec![
+                (1, b"H(H(H(1)H(2))H(H(empty node)H(empty node)))".to_vec()),
+                (2, b"H(H(1)H(2))".to_vec()),
+                (4, b"H(1)".to_vec()),
+                (5, b"H(2)".to_vec()),
+            ],
+            nodes
+        );
+
+        let mut imported = MerkleTreeBuilder::new(basic_hash, 2)
+            .import_nodes(nodes)
+            .unwrap();
+
+        assert_eq!(mt.nodes, imported.nodes);
+        assert_eq!(2, imported.length);
+
+        imported.insert("3").unwrap();
+        imported.update_internal_nodes().unwrap();
+        mt.insert("3").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(mt.get_root(), imported.get_root());
+    }
+
+    #[test]
+    fn import_nodes_then_prove() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .tree_id(TreeId(7))
+            .build()
+            .unwrap();
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let nodes: Vec<_> = mt.export_nodes().collect();
+        let imported = MerkleTreeBuilder::new(basic_hash, 2)
+            .tree_id(TreeId(7))
+            .import_nodes(nodes.clone())
+            .unwrap();
+
+        // the proofs need the empty subtrees, which are not exported
+        assert_eq!(mt.prove(0), imported.prove(0));
+        assert_eq!(
+            mt.iter_with_proofs().unwrap().collect::<Vec<_>>(),
+            imported.iter_with_proofs().unwrap().collect::<Vec<_>>()
+        );
+
+        // without an internal node, the merkle tree stays stale until its nodes are updated
+        let partial: Vec<_> = nodes.into_iter().filter(|(index, _)| *index != 2).collect();
+        let mut imported = MerkleTreeBuilder::new(basic_hash, 2)
+            .tree_id(TreeId(7))
+            .import_nodes(partial)
+            .unwrap();
+
+        let mut proofs = imported.iter_with_proofs().unwrap();
+        assert_eq!(
+            Some(Err(MerkleTreeError::StaleInternalNode(3))),
+            proofs.next()
+        );
+        assert_eq!(None, proofs.next());
+
+        imported.update_internal_nodes().unwrap();
+        assert_eq!(mt.prove(1), imported.prove(1));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn verify_on_load_rejects_poisoned_nodes() {
+        let mut mt = MerkleTree::from_height(basic_hash, 3).unwrap();
+        for value in ["1", "2", "3", "4", "5"] {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+
+        let nodes: Vec<_> = mt.export_nodes().collect();
+        let mut poisoned = nodes.clone();
+        poisoned[2].1 = b"H(poison)".to_vec();
+        // a node above an empty subtree, which is not exported
+        poisoned.push((7, b"H(poison)".to_vec()));
+
+        for load_check in [LoadCheck::Full, LoadCheck::Sample(3)] {
+            let imported = MerkleTreeBuilder::new(basic_hash, 3)
+                .verify_on_load(load_check)
+                .import_nodes(nodes.clone())
+                .unwrap();
+            assert_eq!(mt.nodes, imported.nodes);
+        }
+
+        assert!(MerkleTreeBuilder::new(basic_hash, 3)
+            .import_nodes(poisoned.clone())
+            .is_ok());
+        assert_eq!(
+            Some(MerkleTreeError::InvalidSnapshot),
+            MerkleTreeBuilder::new(basic_hash, 3)
+                .verify_on_load(LoadCheck::Full)
+                .import_nodes(poisoned.clone())
+                .err()
+        );
+        // sampling all the internal nodes checks them all
+        assert_eq!(
+            Some(MerkleTreeError::InvalidSnapshot),
+            MerkleTreeBuilder::new(basic_hash, 3)
+                .verify_on_load(LoadCheck::Sample(7))
+                .import_nodes(poisoned)
+                .err()
+        );
+    }
+
+    #[test]
+    fn import_nodes_invalid() {
+        assert_eq!(
+            Some(MerkleTreeError::NodeOutOfBounds(8)),
+            MerkleTreeBuilder::new(basic_hash, 2)
+                .import_nodes(vec![(8, b"H(1)".to_vec())])
+                .err()
+        );
+        assert_eq!(
+            Some(MerkleTreeError::InvalidSnapshot),
+            MerkleTreeBuilder::new(basic_hash, 2)
+                .import_nodes(vec![(5, b"H(2)".to_vec())])
+                .err()
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! sparse merkle trees authenticating key-value pairs
+//!
+//! a sparse merkle tree has a leaf for every possible key, e.g. 2^256 leaves for 32-byte keys,
+//! almost all of them empty; the hash of an empty subtree only depends on its level,
+//! so these hashes are computed once and only the nodes above non-empty leaves are stored
+
+use std::collections::HashMap;
+
+use crate::{Hash, HashFunction, Hasher, LeafHash, MerkleTreeError, NodeHash};
+
+/// the greatest depth of a sparse merkle tree, i.e. 32-byte keys
+pub const MAX_DEPTH: usize = 256;
+
+/// the tag prefixed to the values hashed into the leaves of a sparse merkle tree
+pub const VALUE_TAG: u8 = 0x00;
+/// the only byte hashed into an empty leaf, which no tagged value can hash to,
+/// so that a stored value is never mistaken for an absent one
+pub const EMPTY_TAG: u8 = 0x01;
+
+/// get the hash of the leaf storing `value`, to check a `SparseProof` of its presence
+pub fn leaf_hash(hasher: &impl Hasher, value: &[u8]) -> LeafHash {
+    LeafHash(hasher.hash_leaf(&[&[VALUE_TAG], value].concat()))
+}
+
+/// get the hash of an empty leaf, to check a `SparseProof` of an absence
+pub fn empty_leaf_hash(hasher: &impl Hasher) -> LeafHash {
+    LeafHash(hasher.hash_leaf(&[EMPTY_TAG]))
+}
+
+/// a merkle tree with a leaf for every key of `depth` bits, storing only the non-empty paths
+#[derive(Clone)]
+pub struct SparseMerkleTree<H = HashFunction> {
+    hasher: H,
+    depth: usize,
+    /// `empty_hashes[level]` is the hash of an empty subtree whose root is `level` levels
+    /// above the leaves, so `empty_hashes[0]` is the hash of an empty leaf
+    empty_hashes: Vec<Hash>,
+    /// the hashes of the non-empty nodes by level above the leaves and by key prefix,
+    /// i.e. the key of any leaf below the node with its last `level` bits cleared
+    nodes: HashMap<(usize, Vec<u8>), Hash>,
+}
+
+/// a proof that a leaf is, or is not, at a key of a sparse merkle tree
+#[derive(Clone, PartialEq, Debug)]
+pub struct SparseProof {
+    pub key: Vec<u8>,
+    /// the siblings from the leaf up to the children of the root
+    pub siblings: Vec<Hash>,
+}
+
+impl SparseProof {
+    /// check that the leaf at `key` has hash `leaf_hash` in the sparse merkle tree with root `root`,
+    /// where the `empty_leaf_hash` proves that no value is stored at `key`,
+    /// see `leaf_hash` for the hash of a stored value
+    pub fn verify(&self, leaf_hash: &LeafHash, root: &NodeHash, hasher: &impl Hasher) -> bool {
+        if self.siblings.len() != self.key.len() * 8 {
+            return false;
+        }
+
+        let computed_root =
+            self.siblings
+                .iter()
+                .enumerate()
+                .fold(leaf_hash.0.clone(), |hash, (level, sibling)| {
+                    if is_right_child(&self.key, level) {
+                        hasher.hash_nodes(sibling, &hash)
+                    } else {
+                        hasher.hash_nodes(&hash, sibling)
+                    }
+                });
+
+        computed_root == root.0
+    }
+}
+
+/// check whether the ancestor of the leaf at `key`, `level` levels above the leaves,
+/// is the right child of its parent
+fn is_right_child(key: &[u8], level: usize) -> bool {
+    let bit = key.len() * 8 - 1 - level;
+
+    key[bit / 8] >> (7 - bit % 8) & 1 == 1
+}
+
+/// clear the last `level` bits of `key`, giving the prefix shared by the leaves
+/// below its ancestor `level` levels above the leaves
+fn prefix(key: &[u8], level: usize) -> Vec<u8> {
+    let mut prefix = key.to_vec();
+    for bit in key.len() * 8 - level..key.len() * 8 {
+        prefix[bit / 8] &= !(1 << (7 - bit % 8));
+    }
+
+    prefix
+}
+
+/// flip the bit of `prefix` telling on which side its node stands, giving the prefix of its sibling
+fn sibling_prefix(prefix: &[u8], level: usize) -> Vec<u8> {
+    let bit = prefix.len() * 8 - 1 - level;
+    let mut sibling = prefix.to_vec();
+    sibling[bit / 8] ^= 1 << (7 - bit % 8);
+
+    sibling
+}
+
+impl<H: Hasher> SparseMerkleTree<H> {
+    /// create an empty sparse merkle tree with the provided `hasher`,
+    /// with a leaf for every key of `depth` bits
+    ///
+    /// fail if the depth is 0, greater than `MAX_DEPTH` or not a multiple of 8
+    pub fn new(hasher: H, depth: usize) -> Result<Self, MerkleTreeError> {
+        if depth == 0 || depth > MAX_DEPTH || !depth.is_multiple_of(8) {
+            return Err(MerkleTreeError::InvalidHeight(depth));
+        }
+
+        let mut empty_hashes = Vec::with_capacity(depth + 1);
+        empty_hashes.push(empty_leaf_hash(&hasher).0);
+        for level in 0..depth {
+            let empty_hash = &empty_hashes[level];
+            empty_hashes.push(hasher.hash_nodes(empty_hash, empty_hash));
+        }
+
+        Ok(SparseMerkleTree {
+            hasher,
+            depth,
+            empty_hashes,
+            nodes: HashMap::new(),
+        })
+    }
+
+    /// store `value` at `key`, replacing the previous value if any,
+    /// and update the nodes on the path up to the root
+    ///
+    /// fail if the key does not have `depth` bits
+    pub fn insert(&mut self, key: &[u8], value: impl AsRef<[u8]>) -> Result<(), MerkleTreeError> {
+        let leaf_hash = leaf_hash(&self.hasher, value.as_ref()).0;
+
+        self.set_leaf(key, leaf_hash)
+    }
+
+    /// empty the leaf at `key` and update the nodes on the path up to the root
+    ///
+    /// fail if the key does not have `depth` bits
+    pub fn remove(&mut self, key: &[u8]) -> Result<(), MerkleTreeError> {
+        self.set_leaf(key, self.empty_hashes[0].clone())
+    }
+
+    /// get the hash of the leaf at `key`, which is the `empty_leaf_hash` if no value is stored there
+    ///
+    /// fail if the key does not have `depth` bits
+    pub fn get(&self, key: &[u8]) -> Result<LeafHash, MerkleTreeError> {
+        self.check_key(key)?;
+
+        Ok(LeafHash(self.node_hash(0, key.to_vec())))
+    }
+
+    /// get the root hash
+    pub fn root(&self) -> NodeHash {
+        NodeHash(self.node_hash(self.depth, vec![0; self.depth / 8]))
+    }
+
+    /// generate the proof of the leaf at `key`, whether a value is stored there or not
+    ///
+    /// fail if the key does not have `depth` bits
+    pub fn prove(&self, key: &[u8]) -> Result<SparseProof, MerkleTreeError> {
+        self.check_key(key)?;
+
+        let siblings = (0..self.depth)
+            .map(|level| self.node_hash(level, sibling_prefix(&prefix(key, level), level)))
+            .collect();
+
+        Ok(SparseProof {
+            key: key.to_vec(),
+            siblings,
+        })
+    }
+
+    /// the number of non-empty nodes stored, including the leaves
+    pub fn stored_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// fail if `key` does not have `depth` bits
+    fn check_key(&self, key: &[u8]) -> Result<(), MerkleTreeError> {
+        if key.len() * 8 != self.depth {
+            return Err(MerkleTreeError::InvalidKeyLength(key.len()));
+        }
+
+        Ok(())
+    }
+
+    /// get the hash of the node `level` levels above the leaves with the given key `prefix`
+    fn node_hash(&self, level: usize, prefix: Vec<u8>) -> Hash {
+        match self.nodes.get(&(level, prefix)) {
+            Some(hash) => hash.clone(),
+            None => self.empty_hashes[level].clone(),
+        }
+    }
+
+    /// store the node `level` levels above the leaves, unless it is empty
+    fn set_node(&mut self, level: usize, prefix: Vec<u8>, hash: Hash) {
+        if hash == self.empty_hashes[level] {
+            self.nodes.remove(&(level, prefix));
+        } else {
+            self.nodes.insert((level, prefix), hash);
+        }
+    }
+
+    fn set_leaf(&mut self, key: &[u8], leaf_hash: Hash) -> Result<(), MerkleTreeError> {
+        self.check_key(key)?;
+
+        let mut hash = leaf_hash;
+        for level in 0..self.depth {
+            let node_prefix = prefix(key, level);
+            let sibling_hash = self.node_hash(level, sibling_prefix(&node_prefix, level));
+            self.set_node(level, node_prefix, hash.clone());
+
+            hash = if is_right_child(key, level) {
+                self.hasher.hash_nodes(&sibling_hash, &hash)
+            } else {
+                self.hasher.hash_nodes(&hash, &sibling_hash)
+            };
+        }
+        self.set_node(self.depth, prefix(key, self.depth), hash);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{basic_hash, short_hash};
+
+    #[test]
+    fn insert_and_prove() {
+        let mut smt = SparseMerkleTree::new(basic_hash as HashFunction, 8).unwrap();
+        smt.insert(&[0b1000_0000], "a").unwrap();
+
+        // the leaf is the right child of the root and the leftmost leaf below it
+        let mut expected = b"H(\0a)".to_vec();
+        for level in 0..7 {
+            expected = basic_hash(&[expected, smt.empty_hashes[level].clone()].concat());
+        }
+        expected = basic_hash(&[smt.empty_hashes[7].clone(), expected].concat());
+
+        assert_eq!(NodeHash(expected), smt.root());
+        assert_eq!(
+            leaf_hash(&basic_hash, b"a"),
+            smt.get(&[0b1000_0000]).unwrap()
+        );
+
+        let proof = smt.prove(&[0b1000_0000]).unwrap();
+        assert!(proof.verify(&leaf_hash(&basic_hash, b"a"), &smt.root(), &basic_hash));
+        assert!(!proof.verify(&leaf_hash(&basic_hash, b"b"), &smt.root(), &basic_hash));
+        // the hash of the value itself, without the tag, is not a leaf
+        assert!(!proof.verify(&LeafHash(b"H(a)".to_vec()), &smt.root(), &basic_hash));
+    }
+
+    #[test]
+    fn prove_absence() {
+        let mut smt = SparseMerkleTree::new(short_hash as HashFunction, 256).unwrap();
+        smt.insert(&[1; 32], "a").unwrap();
+        smt.insert(&[2; 32], "b").unwrap();
+
+        let proof = smt.prove(&[3; 32]).unwrap();
+
+        assert!(proof.verify(&smt.get(&[3; 32]).unwrap(), &smt.root(), &short_hash));
+        assert_eq!(empty_leaf_hash(&short_hash), smt.get(&[3; 32]).unwrap());
+    }
+
+    #[test]
+    fn no_value_is_an_empty_leaf() {
+        let mut smt = SparseMerkleTree::new(basic_hash as HashFunction, 8).unwrap();
+        let empty_root = smt.root();
+
+        // the values that hash to the empty leaves of a plain hash function are stored as any other
+        for value in [&b"empty node"[..], b"", &[EMPTY_TAG]] {
+            smt.insert(&[7], value).unwrap();
+
+            assert_eq!(9, smt.stored_nodes());
+            assert_ne!(empty_root, smt.root());
+            assert_ne!(empty_leaf_hash(&basic_hash), smt.get(&[7]).unwrap());
+
+            let proof = smt.prove(&[7]).unwrap();
+            assert!(proof.verify(&leaf_hash(&basic_hash, value), &smt.root(), &basic_hash));
+            assert!(!proof.verify(&empty_leaf_hash(&basic_hash), &smt.root(), &basic_hash));
+        }
+    }
+
+    #[test]
+    fn only_populated_paths_are_stored() {
+        let mut smt = SparseMerkleTree::new(short_hash as HashFunction, 256).unwrap();
+        let empty_root = smt.root();
+
+        smt.insert(&[1; 32], "a").unwrap();
+        assert_eq!(257, smt.stored_nodes());
+
+        smt.remove(&[1; 32]).unwrap();
+        assert_eq!(0, smt.stored_nodes());
+        assert_eq!(empty_root, smt.root());
+    }
+
+    #[test]
+    fn invalid_depth_and_key() {
+        assert_eq!(
+            Some(MerkleTreeError::InvalidHeight(12)),
+            SparseMerkleTree::new(basic_hash as HashFunction, 12).err()
+        );
+
+        let mut smt = SparseMerkleTree::new(basic_hash as HashFunction, 16).unwrap();
+        assert_eq!(
+            Err(MerkleTreeError::InvalidKeyLength(1)),
+            smt.insert(&[0], "a")
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! storage of the nodes of merkle trees
+//!
+//! a `NodeStore` keeps the hashes of the nodes by their breadth first index,
+//! so that merkle trees can be backed by memory, files or databases alike
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+#[cfg(feature = "std")]
+use std::io;
+
+use crate::{Hash, Hasher, MerkleTree, Node};
+
+/// the default store, keeping all the nodes in memory
+///
+/// the nodes of each level are only allocated up to the last one with a hash,
+/// so that the empty subtrees of a merkle tree take no room
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    node_count: usize,
+    /// `levels[level]` holds the nodes from index `2^level` onwards,
+    /// without trailing empty nodes nor trailing empty levels
+    levels: Vec<Vec<Option<Node>>>,
+    /// the bytes allocated for the stored hashes, kept up to date by `set_hash`
+    /// so that `memory_usage` does not visit every node
+    hash_bytes: usize,
+}
+
+impl PartialEq for MemoryStore {
+    /// compare the nodes, whatever the capacities of their hashes
+    fn eq(&self, other: &Self) -> bool {
+        self.node_count == other.node_count && self.levels == other.levels
+    }
+}
+
+impl MemoryStore {
+    /// find the level of the node at `index` and its offset within this level
+    fn locate(index: usize) -> (usize, usize) {
+        let level = index.ilog2() as usize;
+
+        (level, index - (1 << level))
+    }
+
+    fn get(&self, index: usize) -> Option<&Node> {
+        if index == 0 {
+            return None;
+        }
+
+        let (level, offset) = Self::locate(index);
+
+        self.levels.get(level)?.get(offset)?.as_ref()
+    }
+}
+
+impl From<Vec<Option<Node>>> for MemoryStore {
+    /// convert nodes sorted in a breadth first fashion, where `nodes[0]` is unused
+    fn from(nodes: Vec<Option<Node>>) -> Self {
+        let mut store = MemoryStore::default();
+        store.reset(nodes.len());
+
+        for (index, node) in nodes.into_iter().enumerate().skip(1) {
+            store.set_hash(index, node.map(|node| node.hash));
+        }
+
+        store
+    }
+}
+
+/// a storage backend for the nodes of a merkle tree
+///
+/// a node is either stored with its hash or empty,
+/// i.e. a leaf where no value was inserted or an internal node to recompute
+pub trait NodeStore {
+    /// the number of nodes, including the unused node at index 0
+    fn node_count(&self) -> usize;
+
+    /// get the hash of the node at `index`, or `None` if the node is empty
+    fn get_hash(&self, index: usize) -> Option<Hash>;
+
+    /// store the `hash` of the node at `index`, or empty the node if `hash` is `None`
+    fn set_hash(&mut self, index: usize, hash: Option<Hash>);
+
+    /// check whether the node at `index` has a hash, without reading it
+    fn has_hash(&self, index: usize) -> bool {
+        self.get_hash(index).is_some()
+    }
+
+    /// hint that the nodes at `indices` are about to be read, e.g. to generate a proof,
+    /// so that stores backed by external storage can fetch them in a single batch
+    /// instead of one read per node
+    fn prefetch(&self, _indices: &[usize]) {}
+
+    /// discard all the nodes and make room for `node_count` empty nodes
+    fn reset(&mut self, node_count: usize);
+
+    /// get the indices of all the nodes with a hash, in ascending order
+    fn present_indices(&self) -> Vec<usize> {
+        (1..self.node_count())
+            .filter(|&index| self.has_hash(index))
+            .collect()
+    }
+
+    /// persist the changes made so far, for stores backed by external storage
+    #[cfg(feature = "std")]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// estimate the number of bytes the store holds in memory
+    fn memory_usage(&self) -> usize {
+        0
+    }
+}
+
+impl NodeStore for MemoryStore {
+    fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    fn get_hash(&self, index: usize) -> Option<Hash> {
+        self.get(index).map(|node| node.hash.clone())
+    }
+
+    fn set_hash(&mut self, index: usize, hash: Option<Hash>) {
+        let (level, offset) = Self::locate(index);
+
+        match hash {
+            Some(hash) => {
+                if self.levels.len() <= level {
+                    self.levels.resize(level + 1, Vec::new());
+                }
+
+                let nodes = &mut self.levels[level];
+                if nodes.len() <= offset {
+                    nodes.resize(offset + 1, None);
+                }
+
+                self.hash_bytes += hash.capacity();
+                if let Some(replaced) = nodes[offset].replace(Node { hash }) {
+                    self.hash_bytes -= replaced.hash.capacity();
+                }
+            }
+            None => {
+                if let Some(nodes) = self.levels.get_mut(level) {
+                    if let Some(removed) = nodes.get_mut(offset).and_then(Option::take) {
+                        self.hash_bytes -= removed.hash.capacity();
+                    }
+
+                    while let Some(None) = nodes.last() {
+                        nodes.pop();
+                    }
+                }
+
+                while self.levels.last().is_some_and(Vec::is_empty) {
+                    self.levels.pop();
+                }
+            }
+        }
+    }
+
+    fn has_hash(&self, index: usize) -> bool {
+        self.get(index).is_some()
+    }
+
+    fn reset(&mut self, node_count: usize) {
+        self.node_count = node_count;
+        self.levels.clear();
+        self.hash_bytes = 0;
+    }
+
+    fn present_indices(&self) -> Vec<usize> {
+        self.levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, nodes)| {
+                nodes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, node)| node.is_some())
+                    .map(move |(offset, _)| (1 << level) + offset)
+            })
+            .collect()
+    }
+
+    fn memory_usage(&self) -> usize {
+        let nodes: usize = self
+            .levels
+            .iter()
+            .map(|nodes| nodes.capacity() * size_of::<Option<Node>>())
+            .sum();
+
+        self.levels.capacity() * size_of::<Vec<Option<Node>>>() + nodes + self.hash_bytes
+    }
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// persist the changes made to the nodes so far, see `NodeStore::flush`
+    #[cfg(feature = "std")]
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.nodes.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::{MerkleTree, MerkleTreeBuilder};
+    use alloc::collections::BTreeMap;
+    use alloc::vec;
+    use core::cell::RefCell;
+
+    /// a store keeping only the nodes with a hash, counting its flushes
+    /// and recording its prefetches
+    #[derive(Default)]
+    struct MapStore {
+        node_count: usize,
+        hashes: BTreeMap<usize, Hash>,
+        #[cfg(feature = "std")]
+        flushes: usize,
+        prefetches: RefCell<Vec<Vec<usize>>>,
+    }
+
+    impl NodeStore for MapStore {
+        fn node_count(&self) -> usize {
+            self.node_count
+        }
+
+        fn get_hash(&self, index: usize) -> Option<Hash> {
+            self.hashes.get(&index).cloned()
+        }
+
+        fn set_hash(&mut self, index: usize, hash: Option<Hash>) {
+            match hash {
+                Some(hash) => self.hashes.insert(index, hash),
+                None => self.hashes.remove(&index),
+            };
+        }
+
+        fn reset(&mut self, node_count: usize) {
+            self.node_count = node_count;
+            self.hashes.clear();
+        }
+
+        #[cfg(feature = "std")]
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+
+        fn prefetch(&self, indices: &[usize]) {
+            self.prefetches.borrow_mut().push(indices.to_vec());
+        }
+    }
+
+    #[test]
+    fn custom_store_matches_memory_store() {
+        let mut stored = MerkleTreeBuilder::new(basic_hash, 2)
+            .node_store(MapStore::default())
+            .build()
+            .unwrap();
+        let mut mt = MerkleTree::from_height(basic_hash, 2).unwrap();
+
+        for value in ["1", "2", "3"] {
+            stored.insert(value).unwrap();
+            mt.insert(value).unwrap();
+        }
+        stored.update_internal_nodes().unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(mt.get_root(), stored.get_root());
+        assert_eq!(mt.get_value(3), stored.get_value(3));
+        // 3 leaves and 3 internal nodes
+        assert_eq!(6, stored.nodes.hashes.len());
+
+        #[cfg(feature = "std")]
+        {
+            stored.flush().unwrap();
+            assert_eq!(1, stored.nodes.flushes);
+        }
+    }
+
+    #[test]
+    fn build_resets_store() {
+        let mut store = MapStore::default();
+        store.reset(4);
+        store.set_hash(3, Some(basic_hash(b"stale")));
+
+        let mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .node_store(store)
+            .build()
+            .unwrap();
+
+        assert_eq!(8, mt.nodes.node_count());
+        assert!(mt.nodes.hashes.is_empty());
+    }
+
+    #[test]
+    fn proofs_prefetch_their_nodes() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .node_store(MapStore::default())
+            .build()
+            .unwrap();
+        for value in ["1", "2", "3"] {
+            mt.insert(value).unwrap();
+        }
+        mt.update_internal_nodes().unwrap();
+
+        mt.prove(2).unwrap();
+        mt.prove_many(&[0, 1, 2]).unwrap();
+
+        // the whole authentication path at once, then only the nodes the multiproof needs
+        assert_eq!(vec![vec![7, 2], vec![7]], *mt.nodes.prefetches.borrow());
+    }
+
+    #[test]
+    fn memory_store_allocates_stored_nodes_only() {
+        let mut store = MemoryStore::default();
+        store.reset(1 << 21);
+        store.set_hash(1 << 20, Some(basic_hash(b"1")));
+        store.set_hash(1, Some(basic_hash(b"root")));
+
+        assert_eq!(vec![1, 1 << 20], store.present_indices());
+        assert_eq!(Some(basic_hash(b"1")), store.get_hash(1 << 20));
+        assert_eq!(None, store.get_hash((1 << 20) + 1));
+
+        // emptying the stored nodes gives back an empty store
+        store.set_hash(1 << 20, None);
+        store.set_hash(1, None);
+        let mut empty = MemoryStore::default();
+        empty.reset(1 << 21);
+
+        assert_eq!(empty, store);
+    }
+
+    #[test]
+    fn memory_store_counts_hash_bytes() {
+        // the bytes of the hashes, visiting every stored node
+        fn walked_hash_bytes(store: &MemoryStore) -> usize {
+            store
+                .levels
+                .iter()
+                .flatten()
+                .flatten()
+                .map(|node| node.hash.capacity())
+                .sum()
+        }
+
+        let mut store = MemoryStore::default();
+        store.reset(16);
+        store.set_hash(8, Some(Vec::with_capacity(40)));
+        store.set_hash(9, Some(basic_hash(b"1")));
+        store.set_hash(8, Some(basic_hash(b"replaced")));
+        store.set_hash(1, Some(basic_hash(b"root")));
+        store.set_hash(9, None);
+        store.set_hash(5, None);
+
+        assert_eq!(walked_hash_bytes(&store), store.hash_bytes);
+
+        store.reset(16);
+        assert_eq!(0, store.hash_bytes);
+    }
+}
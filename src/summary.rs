@@ -0,0 +1,125 @@
+//! summaries of the changes between two heads of a merkle tree,
+//! e.g. to feed dashboards without traversing the merkle tree
+
+use crate::head::TreeHead;
+use crate::store::NodeStore;
+use crate::{Hasher, MerkleTree, MerkleTreeError, NodeHash};
+use alloc::vec::Vec;
+
+/// the changes from one head of a merkle tree to another
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChangeSummary {
+    /// the number of values inserted after the old head
+    pub added: usize,
+    /// the number of values of the old head updated since
+    pub updated: usize,
+    /// the number of internal nodes above the changed values, whose hashes were recomputed
+    pub affected_subtrees: usize,
+    pub root_changed: bool,
+    pub new_root: NodeHash,
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// summarize the changes from `old_head` to `new_head`,
+    /// where `delta` holds the indices of the values inserted or updated in between,
+    /// each index being counted once
+    ///
+    /// fail if the heads belong to different merkle trees,
+    /// if the new head has less values than the old one or
+    /// if an index of `delta` is out of the bounds of the new head
+    pub fn summarize_changes(
+        &self,
+        old_head: &TreeHead,
+        new_head: &TreeHead,
+        delta: &[usize],
+    ) -> Result<ChangeSummary, MerkleTreeError> {
+        if old_head.tree_id != new_head.tree_id || new_head.tree_id != self.tree_id {
+            return Err(MerkleTreeError::TreeMismatch);
+        }
+
+        if old_head.length > new_head.length {
+            return Err(MerkleTreeError::InvalidSizes(
+                old_head.length,
+                new_head.length,
+            ));
+        }
+
+        let mut leaves = Vec::with_capacity(delta.len());
+        for &value_index in delta {
+            if value_index >= new_head.length {
+                return Err(MerkleTreeError::ValueOutOfBounds(value_index));
+            }
+
+            leaves.push(self.first_leaf_node_index + value_index);
+        }
+        leaves.sort_unstable();
+        leaves.dedup();
+
+        let first_added = self.first_leaf_node_index + old_head.length;
+        let updated = leaves.iter().filter(|&&index| index < first_added).count();
+
+        Ok(ChangeSummary {
+            added: leaves.len() - updated,
+            updated,
+            affected_subtrees: Self::ancestor_indices(self.height, &leaves).len(),
+            root_changed: old_head.root != new_head.root,
+            new_root: new_head.root.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::head::TreeId;
+    use crate::test_util::basic_hash;
+    use crate::MerkleTreeBuilder;
+
+    #[test]
+    fn summarize_changes() {
+        let mut mt = MerkleTree::from_height(basic_hash, 3).unwrap();
+        mt.insert("a").unwrap();
+        mt.insert("b").unwrap();
+        let old_head = mt.prepare_head().unwrap();
+
+        mt.update_all(&[(1, "x")]).unwrap();
+        mt.insert("c").unwrap();
+        let new_head = mt.prepare_head().unwrap();
+
+        assert_eq!(
+            ChangeSummary {
+                added: 1,
+                updated: 1,
+                // the 2 parents of the changed values, their common parent and the root
+                affected_subtrees: 4,
+                root_changed: true,
+                new_root: new_head.root.clone(),
+            },
+            mt.summarize_changes(&old_head, &new_head, &[2, 1, 2])
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn summarize_changes_invalid() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .tree_id(TreeId(1))
+            .build()
+            .unwrap();
+        mt.insert("a").unwrap();
+        let head = mt.prepare_head().unwrap();
+        let other_head = TreeHead {
+            tree_id: TreeId(2),
+            ..head.clone()
+        };
+
+        assert_eq!(
+            Err(MerkleTreeError::TreeMismatch),
+            mt.summarize_changes(&head, &other_head, &[])
+        );
+        assert_eq!(
+            Err(MerkleTreeError::ValueOutOfBounds(1)),
+            mt.summarize_changes(&head, &head, &[1])
+        );
+    }
+}
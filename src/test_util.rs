@@ -0,0 +1,21 @@
+//! hash functions shared by the tests of all the modules
+
+use crate::Hash;
+use alloc::format;
+use alloc::string::String;
+
+/// a readable hash function, so that the expected hashes can be written by hand
+pub fn basic_hash(input: &[u8]) -> Hash {
+    format!("H({})", String::from_utf8_lossy(input)).into_bytes()
+}
+
+/// a hash function with a fixed size of 8 bytes, as the hashes of `basic_hash` grow with the height
+pub fn short_hash(input: &[u8]) -> Hash {
+    input
+        .iter()
+        .fold(0u64, |hash, byte| {
+            hash.wrapping_mul(31).wrapping_add(*byte as u64)
+        })
+        .to_le_bytes()
+        .to_vec()
+}
@@ -0,0 +1,195 @@
+//! opt-in timings of the operations of merkle trees, to compare hashers
+//!
+//! a merkle tree built with `collect_timings` records how long it spends hashing leaves,
+//! hashing internal nodes and traversing the merkle tree around these hashes
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::store::NodeStore;
+use crate::{Hash, Hasher, MerkleTree};
+
+/// the minimum, mean and maximum durations of a kind of operation
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct DurationStats {
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl DurationStats {
+    /// the mean duration of the operations, if any was recorded
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.count).ok()?;
+
+        self.total.checked_div(count)
+    }
+
+    fn record(&mut self, duration: Duration) {
+        if self.count == 0 || duration < self.min {
+            self.min = duration;
+        }
+        if duration > self.max {
+            self.max = duration;
+        }
+
+        self.count += 1;
+        self.total += duration;
+    }
+}
+
+/// the durations recorded by a merkle tree
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Timings {
+    /// the hashing of inserted values and empty leaves
+    pub leaf_hashing: DurationStats,
+    /// the hashing of internal nodes from their children
+    pub node_hashing: DurationStats,
+    /// each call to `update_internal_nodes` or `rebuild`, without the hashing
+    pub traversal: DurationStats,
+}
+
+/// the timings of a merkle tree behind a lock,
+/// so that they can be recorded through shared references
+#[derive(Debug, Default)]
+pub(crate) struct TimingCollector(Mutex<Timings>);
+
+impl TimingCollector {
+    fn timings(&self) -> Timings {
+        // timings are plain counters, so they stay meaningful even if a recording panicked
+        self.0
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .clone()
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Timings)) {
+        update(&mut self.0.lock().unwrap_or_else(|error| error.into_inner()));
+    }
+}
+
+impl Clone for TimingCollector {
+    fn clone(&self) -> Self {
+        TimingCollector(Mutex::new(self.timings()))
+    }
+}
+
+/// the start of a traversal, to subtract the hashing from its duration
+pub(crate) struct TraversalStart {
+    started_at: Instant,
+    hashing: Duration,
+}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    /// get the durations recorded so far,
+    /// or `None` if the merkle tree was not built with `collect_timings`
+    pub fn timings(&self) -> Option<Timings> {
+        self.timings.as_ref().map(TimingCollector::timings)
+    }
+
+    /// forget the durations recorded so far
+    pub fn reset_timings(&mut self) {
+        if let Some(collector) = &self.timings {
+            collector.update(|timings| *timings = Timings::default());
+        }
+    }
+
+    /// hash a leaf with the hasher, recording the duration if timings are collected
+    pub(crate) fn hash_leaf(&self, value: &[u8]) -> Hash {
+        let Some(collector) = &self.timings else {
+            return self.hasher.hash_leaf(value);
+        };
+
+        let started_at = Instant::now();
+        let hash = self.hasher.hash_leaf(value);
+        let elapsed = started_at.elapsed();
+
+        collector.update(|timings| timings.leaf_hashing.record(elapsed));
+
+        hash
+    }
+
+    /// hash an internal node with the hasher, recording the duration if timings are collected
+    pub(crate) fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash {
+        let Some(collector) = &self.timings else {
+            return self.hasher.hash_nodes(left, right);
+        };
+
+        let started_at = Instant::now();
+        let hash = self.hasher.hash_nodes(left, right);
+        let elapsed = started_at.elapsed();
+
+        collector.update(|timings| timings.node_hashing.record(elapsed));
+
+        hash
+    }
+
+    /// start timing a traversal if timings are collected
+    pub(crate) fn start_traversal(&self) -> Option<TraversalStart> {
+        let collector = self.timings.as_ref()?;
+
+        let timings = collector.timings();
+
+        Some(TraversalStart {
+            hashing: timings.leaf_hashing.total + timings.node_hashing.total,
+            started_at: Instant::now(),
+        })
+    }
+
+    /// record the duration of a traversal started with `start_traversal`
+    pub(crate) fn finish_traversal(&self, start: Option<TraversalStart>) {
+        if let (Some(collector), Some(start)) = (&self.timings, start) {
+            let elapsed = start.started_at.elapsed();
+
+            collector.update(|timings| {
+                let hashing =
+                    timings.leaf_hashing.total + timings.node_hashing.total - start.hashing;
+                timings.traversal.record(elapsed.saturating_sub(hashing));
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::basic_hash;
+    use crate::MerkleTreeBuilder;
+
+    #[test]
+    fn timings_not_collected() {
+        let mut mt = MerkleTree::from_height(basic_hash, 1).unwrap();
+        mt.insert("1").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        assert_eq!(None, mt.timings());
+    }
+
+    #[test]
+    fn timings_count_operations() {
+        let mut mt = MerkleTreeBuilder::new(basic_hash, 2)
+            .collect_timings(true)
+            .build()
+            .unwrap();
+
+        mt.insert("1").unwrap();
+        mt.insert("2").unwrap();
+        mt.update_internal_nodes().unwrap();
+
+        let timings = mt.timings().unwrap();
+
+        // 2 inserted values and their parent and the root,
+        // the empty subtree on the right of the root being precomputed when building
+        assert_eq!(2, timings.leaf_hashing.count);
+        assert_eq!(2, timings.node_hashing.count);
+        assert_eq!(1, timings.traversal.count);
+        assert!(timings.node_hashing.min <= timings.node_hashing.mean().unwrap());
+        assert!(timings.node_hashing.mean().unwrap() <= timings.node_hashing.max);
+
+        mt.reset_timings();
+
+        assert_eq!(Some(Timings::default()), mt.timings());
+        assert_eq!(None, mt.timings().unwrap().traversal.mean());
+    }
+}
@@ -0,0 +1,33 @@
+//! the instrumentation hooks of merkle trees without the standard library,
+//! which has the clock of the timings and metrics and the channels of the events,
+//! so the hooks only hash or do nothing
+
+use crate::store::NodeStore;
+use crate::{Hash, Hasher, MerkleTree};
+
+/// the start of a traversal, which is never recorded without the standard library
+pub(crate) enum TraversalStart {}
+
+impl<H: Hasher, S: NodeStore> MerkleTree<H, S> {
+    pub(crate) fn hash_leaf(&self, value: &[u8]) -> Hash {
+        self.hasher.hash_leaf(value)
+    }
+
+    pub(crate) fn hash_nodes(&self, left: &[u8], right: &[u8]) -> Hash {
+        self.hasher.hash_nodes(left, right)
+    }
+
+    pub(crate) fn start_traversal(&self) -> Option<TraversalStart> {
+        None
+    }
+
+    pub(crate) fn finish_traversal(&self, _start: Option<TraversalStart>) {}
+
+    pub(crate) fn record_proof(&self) {}
+
+    pub(crate) fn record_root_update(&self) {}
+
+    pub(crate) fn send_insert_event(&self, _value_index: usize, _id: Option<&str>) {}
+
+    pub(crate) fn send_update_event(&self, _value_index: usize) {}
+}
@@ -0,0 +1,126 @@
+//! verification of the inclusion proofs of merkle trees for embedded targets
+//!
+//! this crate only depends on `core` and never allocates: the digests have a fixed size
+//! chosen by the hasher, e.g. `[u8; 32]`, and the steps of a proof are read from any iterator,
+//! so firmware can verify proofs without the code building and storing merkle trees
+
+#![no_std]
+
+/// a hasher combining the fixed-size digests of two children into the digest of their parent
+pub trait NodeHasher {
+    type Digest: AsRef<[u8]> + PartialEq;
+
+    fn hash_nodes(&self, left: &Self::Digest, right: &Self::Digest) -> Self::Digest;
+}
+
+/// the side on which a sibling stands relative to the node on the path to the root
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Position {
+    Left,
+    Right,
+}
+
+/// how the digests of two children are ordered to hash their parent,
+/// which must match the order of the merkle tree the proof was generated from
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PairOrder {
+    /// the left child first
+    #[default]
+    Positional,
+    /// the lower digest first, whatever the positions of the children
+    Sorted,
+}
+
+/// check that the leaf with digest `leaf_hash` is included in the merkle tree with root `root`,
+/// given the `steps` of its proof, i.e. the siblings from the leaf up to the children of the root
+pub fn verify<H: NodeHasher>(
+    hasher: &H,
+    leaf_hash: H::Digest,
+    steps: impl IntoIterator<Item = (Position, H::Digest)>,
+    pair_order: PairOrder,
+    root: &H::Digest,
+) -> bool {
+    let computed_root = steps
+        .into_iter()
+        .fold(leaf_hash, |hash, (position, sibling)| {
+            let (left, right) = match position {
+                Position::Left => (&sibling, &hash),
+                Position::Right => (&hash, &sibling),
+            };
+
+            match pair_order {
+                PairOrder::Sorted if right.as_ref() < left.as_ref() => {
+                    hasher.hash_nodes(right, left)
+                }
+                _ => hasher.hash_nodes(left, right),
+            }
+        });
+
+    computed_root == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a toy hasher mixing two 8-byte digests, enough to tell the orders apart
+    struct MixHasher;
+
+    impl NodeHasher for MixHasher {
+        type Digest = [u8; 8];
+
+        fn hash_nodes(&self, left: &[u8; 8], right: &[u8; 8]) -> [u8; 8] {
+            let left = u64::from_le_bytes(*left);
+            let right = u64::from_le_bytes(*right);
+
+            (left.rotate_left(5) ^ right).wrapping_mul(31).to_le_bytes()
+        }
+    }
+
+    fn digest(value: u64) -> [u8; 8] {
+        value.to_le_bytes()
+    }
+
+    #[test]
+    fn verify_positional() {
+        let parent = MixHasher.hash_nodes(&digest(1), &digest(2));
+        let root = MixHasher.hash_nodes(&digest(3), &parent);
+        let steps = [(Position::Right, digest(2)), (Position::Left, digest(3))];
+
+        assert!(verify(
+            &MixHasher,
+            digest(1),
+            steps,
+            PairOrder::Positional,
+            &root
+        ));
+        assert!(!verify(
+            &MixHasher,
+            digest(2),
+            steps,
+            PairOrder::Positional,
+            &root
+        ));
+    }
+
+    #[test]
+    fn verify_sorted() {
+        let parent = MixHasher.hash_nodes(&digest(1), &digest(2));
+
+        // the positions are irrelevant once the digests are sorted
+        assert!(verify(
+            &MixHasher,
+            digest(2),
+            [(Position::Right, digest(1))],
+            PairOrder::Sorted,
+            &parent
+        ));
+        assert!(!verify(
+            &MixHasher,
+            digest(2),
+            [(Position::Right, digest(1))],
+            PairOrder::Positional,
+            &parent
+        ));
+    }
+}